@@ -0,0 +1,899 @@
+use super::{opcodes::Opcode, traps::Trap};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+#[error("line {line}, column {column}: {kind}")]
+pub struct AssemblerError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssemblerErrorKind,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum AssemblerErrorKind {
+    #[error("expected .ORIG before any instruction")]
+    MissingOrig,
+    #[error("only one .ORIG block is supported per file")]
+    MultipleOrig,
+    #[error("missing .END")]
+    MissingEnd,
+    #[error("unknown instruction or directive {0}")]
+    UnknownMnemonic(String),
+    #[error("expected {expected} operands, found {found}")]
+    OperandCount { expected: usize, found: usize },
+    #[error("invalid register {0}")]
+    InvalidRegister(String),
+    #[error("invalid number {0}")]
+    InvalidNumber(String),
+    #[error("expected a string literal")]
+    ExpectedString,
+    #[error("unterminated string literal")]
+    UnterminatedString,
+    #[error("invalid escape sequence \\{0}")]
+    InvalidEscape(char),
+    #[error("value {value} does not fit in {bits} bits")]
+    OutOfRange { value: i32, bits: u8 },
+    #[error("label {0} is already defined")]
+    DuplicateLabel(String),
+    #[error("undefined label {0}")]
+    UndefinedLabel(String),
+    #[error("invalid label {0}")]
+    InvalidLabel(String),
+    #[error("program does not fit in memory")]
+    ProgramTooLarge,
+}
+
+/// An assembled `.ORIG` block
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub symbols: BTreeMap<String, u16>,
+    /// Source line of every instruction by address, data directives aren't included
+    pub lines: BTreeMap<u16, usize>,
+}
+
+impl Program {
+    /// Serializes the program into the object file format: the origin followed by every word,
+    /// all of them big-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        std::iter::once(self.origin)
+            .chain(self.words.iter().copied())
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Word,
+    Str,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    column: usize,
+    kind: TokenKind,
+}
+
+enum Operation {
+    Orig(Token),
+    End,
+    Fill(Token),
+    Blkw(Token),
+    Stringz(Token),
+    Instruction {
+        mnemonic: Token,
+        operands: Vec<Token>,
+    },
+}
+
+struct Statement {
+    line: usize,
+    address: u16,
+    operation: Operation,
+}
+
+/// Assembles LC-3 source code in two passes: the first one assigns an address to every label
+/// and the second one encodes every statement
+pub fn assemble(source: &str) -> Result<Program, AssemblerError> {
+    let mut symbols = BTreeMap::new();
+    let mut statements = Vec::new();
+    let mut origin = None;
+    let mut location: u32 = 0;
+    let mut last_line = 0;
+    let mut ended = false;
+
+    // First pass
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index.saturating_add(1);
+        last_line = line_number;
+        let mut tokens = tokenize(line, line_number)?.into_iter();
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+
+        let (label, operation_token) = if is_operation(&first.text) {
+            (None, Some(first))
+        } else {
+            (Some(first), tokens.next())
+        };
+        let operands: Vec<Token> = tokens.collect();
+
+        if let Some(label) = label {
+            let Some(address) = origin.and_then(|_| u16::try_from(location).ok()) else {
+                return Err(error(
+                    line_number,
+                    label.column,
+                    AssemblerErrorKind::MissingOrig,
+                ));
+            };
+            let name = label.text.trim_end_matches(':').to_string();
+            if !is_valid_label(&name) {
+                return Err(error(
+                    line_number,
+                    label.column,
+                    AssemblerErrorKind::InvalidLabel(label.text),
+                ));
+            }
+            if symbols.insert(name.clone(), address).is_some() {
+                return Err(error(
+                    line_number,
+                    label.column,
+                    AssemblerErrorKind::DuplicateLabel(name),
+                ));
+            }
+        }
+
+        let Some(operation_token) = operation_token else {
+            continue;
+        };
+        let operation = parse_operation(operation_token, operands, line_number)?;
+
+        let size = match &operation {
+            Operation::Orig(token) => {
+                if origin.is_some() {
+                    return Err(error(
+                        line_number,
+                        token.column,
+                        AssemblerErrorKind::MultipleOrig,
+                    ));
+                }
+                let address = parse_unsigned(token, 16, line_number)?;
+                origin = Some(address);
+                location = address.into();
+                continue;
+            }
+            Operation::End => {
+                ended = true;
+                break;
+            }
+            Operation::Blkw(token) => parse_unsigned(token, 16, line_number)?.into(),
+            Operation::Stringz(token) => u32::try_from(token.text.chars().count())
+                .map_err(|_| {
+                    error(
+                        line_number,
+                        token.column,
+                        AssemblerErrorKind::ProgramTooLarge,
+                    )
+                })?
+                .saturating_add(1),
+            Operation::Fill(_) | Operation::Instruction { .. } => 1,
+        };
+
+        let column = operation_column(&operation);
+        let address = match origin {
+            Some(_) => u16::try_from(location)
+                .map_err(|_| error(line_number, column, AssemblerErrorKind::ProgramTooLarge))?,
+            None => return Err(error(line_number, column, AssemblerErrorKind::MissingOrig)),
+        };
+        location = location.saturating_add(size);
+        if location > u32::from(u16::MAX).saturating_add(1) {
+            return Err(error(
+                line_number,
+                column,
+                AssemblerErrorKind::ProgramTooLarge,
+            ));
+        }
+        statements.push(Statement {
+            line: line_number,
+            address,
+            operation,
+        });
+    }
+
+    let Some(origin) = origin else {
+        return Err(error(last_line, 1, AssemblerErrorKind::MissingOrig));
+    };
+    if !ended {
+        return Err(error(last_line, 1, AssemblerErrorKind::MissingEnd));
+    }
+
+    // Second pass
+    let mut words = Vec::new();
+    let mut lines = BTreeMap::new();
+    for statement in statements {
+        if matches!(statement.operation, Operation::Instruction { .. }) {
+            lines.insert(statement.address, statement.line);
+        }
+        encode_statement(&statement, &symbols, &mut words)?;
+    }
+
+    Ok(Program {
+        origin,
+        words,
+        symbols,
+        lines,
+    })
+}
+
+fn error(line: usize, column: usize, kind: AssemblerErrorKind) -> AssemblerError {
+    AssemblerError { line, column, kind }
+}
+
+fn tokenize(line: &str, line_number: usize) -> Result<Vec<Token>, AssemblerError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().enumerate().peekable();
+
+    while let Some(&(index, c)) = chars.peek() {
+        let column = index.saturating_add(1);
+        if c == ';' {
+            break;
+        }
+        if c.is_whitespace() || c == ',' {
+            chars.next();
+            continue;
+        }
+
+        let mut text = String::new();
+        if c == '"' {
+            chars.next();
+            let mut terminated = false;
+            while let Some((_, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        terminated = true;
+                        break;
+                    }
+                    '\\' => {
+                        let escaped = chars.next().map(|(_, c)| c).unwrap_or_default();
+                        text.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            'e' => '\x1b',
+                            '0' => '\0',
+                            '"' => '"',
+                            '\\' => '\\',
+                            other => {
+                                return Err(error(
+                                    line_number,
+                                    column,
+                                    AssemblerErrorKind::InvalidEscape(other),
+                                ))
+                            }
+                        });
+                    }
+                    c => text.push(c),
+                }
+            }
+            if !terminated {
+                return Err(error(
+                    line_number,
+                    column,
+                    AssemblerErrorKind::UnterminatedString,
+                ));
+            }
+            tokens.push(Token {
+                text,
+                column,
+                kind: TokenKind::Str,
+            });
+        } else {
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == ',' || c == ';' || c == '"' {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+            tokens.push(Token {
+                text,
+                column,
+                kind: TokenKind::Word,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_operation(word: &str) -> bool {
+    let word = word.to_ascii_uppercase();
+    matches!(
+        word.as_str(),
+        ".ORIG"
+            | ".END"
+            | ".FILL"
+            | ".BLKW"
+            | ".STRINGZ"
+            | "ADD"
+            | "AND"
+            | "NOT"
+            | "LD"
+            | "LDI"
+            | "LDR"
+            | "LEA"
+            | "ST"
+            | "STI"
+            | "STR"
+            | "JMP"
+            | "RET"
+            | "JSR"
+            | "JSRR"
+            | "RTI"
+            | "TRAP"
+            | "GETC"
+            | "OUT"
+            | "PUTS"
+            | "IN"
+            | "PUTSP"
+            | "HALT"
+            | "RDSEC"
+            | "WRSEC"
+            | "SETVEC"
+    ) || parse_branch_flags(&word).is_some()
+}
+
+/// Returns the n, z and p flags of a BR mnemonic, a plain BR is unconditional
+fn parse_branch_flags(word: &str) -> Option<(bool, bool, bool)> {
+    let flags = word.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some((true, true, true));
+    }
+    let (mut n, mut z, mut p) = (false, false, false);
+    for flag in flags.chars() {
+        let flag = match flag {
+            'N' => &mut n,
+            'Z' => &mut z,
+            'P' => &mut p,
+            _ => return None,
+        };
+        if *flag {
+            return None;
+        }
+        *flag = true;
+    }
+    Some((n, z, p))
+}
+
+fn is_valid_label(label: &str) -> bool {
+    let mut chars = label.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && parse_register(label).is_none()
+}
+
+fn parse_operation(
+    token: Token,
+    operands: Vec<Token>,
+    line: usize,
+) -> Result<Operation, AssemblerError> {
+    let directive = token.text.to_ascii_uppercase();
+    let single_operand = |operands: Vec<Token>| -> Result<Token, AssemblerError> {
+        let found = operands.len();
+        let mut operands = operands.into_iter();
+        match (operands.next(), found) {
+            (Some(operand), 1) => Ok(operand),
+            _ => Err(error(
+                line,
+                token.column,
+                AssemblerErrorKind::OperandCount { expected: 1, found },
+            )),
+        }
+    };
+
+    let operation = match directive.as_str() {
+        ".ORIG" => Operation::Orig(single_operand(operands)?),
+        ".END" => Operation::End,
+        ".FILL" => Operation::Fill(single_operand(operands)?),
+        ".BLKW" => Operation::Blkw(single_operand(operands)?),
+        ".STRINGZ" => {
+            let operand = single_operand(operands)?;
+            if operand.kind != TokenKind::Str {
+                return Err(error(
+                    line,
+                    operand.column,
+                    AssemblerErrorKind::ExpectedString,
+                ));
+            }
+            Operation::Stringz(operand)
+        }
+        _ => Operation::Instruction {
+            mnemonic: token,
+            operands,
+        },
+    };
+    Ok(operation)
+}
+
+fn operation_column(operation: &Operation) -> usize {
+    match operation {
+        Operation::Orig(token)
+        | Operation::Fill(token)
+        | Operation::Blkw(token)
+        | Operation::Stringz(token) => token.column,
+        Operation::Instruction { mnemonic, .. } => mnemonic.column,
+        Operation::End => 1,
+    }
+}
+
+fn encode_statement(
+    statement: &Statement,
+    symbols: &BTreeMap<String, u16>,
+    words: &mut Vec<u16>,
+) -> Result<(), AssemblerError> {
+    let line = statement.line;
+    match &statement.operation {
+        Operation::Fill(token) => {
+            let value = match parse_number(&token.text) {
+                Some(value) => fit_word(value, token, line)?,
+                None => resolve_label(token, symbols, line)?,
+            };
+            words.push(value);
+        }
+        Operation::Blkw(token) => {
+            let count = parse_unsigned(token, 16, line)?;
+            words.extend(std::iter::repeat_n(0, count.into()));
+        }
+        Operation::Stringz(token) => {
+            for c in token.text.chars() {
+                words.push(u16::try_from(u32::from(c)).map_err(|_| {
+                    error(
+                        line,
+                        token.column,
+                        AssemblerErrorKind::InvalidNumber(c.to_string()),
+                    )
+                })?);
+            }
+            words.push(0);
+        }
+        Operation::Instruction { mnemonic, operands } => {
+            let opcode = encode_instruction(statement, mnemonic, operands, symbols)?;
+            words.push(opcode.into());
+        }
+        Operation::Orig(_) | Operation::End => {}
+    }
+    Ok(())
+}
+
+fn encode_instruction(
+    statement: &Statement,
+    mnemonic: &Token,
+    operands: &[Token],
+    symbols: &BTreeMap<String, u16>,
+) -> Result<Opcode, AssemblerError> {
+    let line = statement.line;
+    let name = mnemonic.text.to_ascii_uppercase();
+    let expect = |expected: usize| -> Result<(), AssemblerError> {
+        if operands.len() == expected {
+            Ok(())
+        } else {
+            Err(error(
+                line,
+                mnemonic.column,
+                AssemblerErrorKind::OperandCount {
+                    expected,
+                    found: operands.len(),
+                },
+            ))
+        }
+    };
+    let operand = |index: usize| -> Result<&Token, AssemblerError> {
+        operands.get(index).ok_or(error(
+            line,
+            mnemonic.column,
+            AssemblerErrorKind::OperandCount {
+                expected: index.saturating_add(1),
+                found: operands.len(),
+            },
+        ))
+    };
+    let register = |index: usize| -> Result<u8, AssemblerError> {
+        let token = operand(index)?;
+        parse_register(&token.text).ok_or(error(
+            line,
+            token.column,
+            AssemblerErrorKind::InvalidRegister(token.text.clone()),
+        ))
+    };
+    let pc_offset = |index: usize, bits: u8| -> Result<u16, AssemblerError> {
+        let token = operand(index)?;
+        let offset = match parse_number(&token.text) {
+            Some(offset) => offset,
+            None => {
+                let target = resolve_label(token, symbols, line)?;
+                // Offsets are relative to the incremented PC
+                i32::from(target)
+                    .wrapping_sub(i32::from(statement.address))
+                    .wrapping_sub(1)
+            }
+        };
+        fit_signed(offset, bits, token, line)
+    };
+
+    let trap = |trap: Trap| -> Result<Opcode, AssemblerError> {
+        expect(0)?;
+        Ok(Opcode::TRAP {
+            trap_vec: trap.into(),
+        })
+    };
+
+    let opcode = match name.as_str() {
+        "ADD" | "AND" => {
+            expect(3)?;
+            let dr = register(0)?;
+            let sr1 = register(1)?;
+            let (mode, sr2) = match register(2) {
+                Ok(sr2) => (false, sr2),
+                Err(_) => {
+                    let token = operand(2)?;
+                    let value = parse_number(&token.text).ok_or(error(
+                        line,
+                        token.column,
+                        AssemblerErrorKind::InvalidNumber(token.text.clone()),
+                    ))?;
+                    let imm5 = fit_signed(value, 5, token, line)?;
+                    (true, low_byte(imm5))
+                }
+            };
+            if name == "ADD" {
+                Opcode::ADD { dr, sr1, mode, sr2 }
+            } else {
+                Opcode::AND { dr, sr1, mode, sr2 }
+            }
+        }
+        "NOT" => {
+            expect(2)?;
+            Opcode::NOT {
+                dr: register(0)?,
+                sr: register(1)?,
+            }
+        }
+        "LD" | "LDI" | "LEA" | "ST" | "STI" => {
+            expect(2)?;
+            let reg = register(0)?;
+            let offset = pc_offset(1, 9)?;
+            match name.as_str() {
+                "LD" => Opcode::LD { dr: reg, offset },
+                "LDI" => Opcode::LDI { dr: reg, offset },
+                "LEA" => Opcode::LEA { dr: reg, offset },
+                "ST" => Opcode::ST { sr: reg, offset },
+                _ => Opcode::STI { sr: reg, offset },
+            }
+        }
+        "LDR" | "STR" => {
+            expect(3)?;
+            let reg = register(0)?;
+            let base_r = register(1)?;
+            let token = operand(2)?;
+            let value = parse_number(&token.text).ok_or(error(
+                line,
+                token.column,
+                AssemblerErrorKind::InvalidNumber(token.text.clone()),
+            ))?;
+            let offset = low_byte(fit_signed(value, 6, token, line)?);
+            if name == "LDR" {
+                Opcode::LDR {
+                    dr: reg,
+                    base_r,
+                    offset,
+                }
+            } else {
+                Opcode::STR {
+                    sr: reg,
+                    base_r,
+                    offset,
+                }
+            }
+        }
+        "JMP" => {
+            expect(1)?;
+            Opcode::JMP {
+                base_r: register(0)?,
+            }
+        }
+        "RET" => {
+            expect(0)?;
+            Opcode::JMP { base_r: 7 }
+        }
+        "JSR" => {
+            expect(1)?;
+            Opcode::JSR {
+                mode: true,
+                offset: pc_offset(0, 11)?,
+            }
+        }
+        "JSRR" => {
+            expect(1)?;
+            // The base register is stored in bits 8..6 of the offset
+            Opcode::JSR {
+                mode: false,
+                offset: u16::from(register(0)?) << 6,
+            }
+        }
+        "RTI" => {
+            expect(0)?;
+            Opcode::RTI {}
+        }
+        "TRAP" => {
+            expect(1)?;
+            let token = operand(0)?;
+            Opcode::TRAP {
+                trap_vec: low_byte(parse_unsigned(token, 8, line)?),
+            }
+        }
+        "GETC" => trap(Trap::GetC)?,
+        "OUT" => trap(Trap::Out)?,
+        "PUTS" => trap(Trap::Puts)?,
+        "IN" => trap(Trap::In)?,
+        "PUTSP" => trap(Trap::Putsp)?,
+        "HALT" => trap(Trap::Halt)?,
+        "RDSEC" => trap(Trap::ReadSector)?,
+        "WRSEC" => trap(Trap::WriteSector)?,
+        "SETVEC" => trap(Trap::SetVector)?,
+        _ => {
+            let (n, z, p) = parse_branch_flags(&name).ok_or(error(
+                line,
+                mnemonic.column,
+                AssemblerErrorKind::UnknownMnemonic(mnemonic.text.clone()),
+            ))?;
+            expect(1)?;
+            Opcode::BR {
+                n,
+                z,
+                p,
+                offset: pc_offset(0, 9)?,
+            }
+        }
+    };
+    Ok(opcode)
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    if !matches!(chars.next(), Some('R' | 'r')) {
+        return None;
+    }
+    let digit = chars.next()?.to_digit(8)?;
+    if chars.next().is_some() {
+        return None;
+    }
+    u8::try_from(digit).ok()
+}
+
+/// Parses a number in any of the notations accepted by lc3as: `#10`, `#-10`, `10`, `x3000`,
+/// `0x3000`, `-x10` and `b1010`
+pub(super) fn parse_number(text: &str) -> Option<i32> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let (radix, digits) = if let Some(digits) = text.strip_prefix('#') {
+        (10, digits)
+    } else if let Some(digits) = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('x'))
+        .or_else(|| text.strip_prefix('X'))
+    {
+        (16, digits)
+    } else if let Some(digits) = text.strip_prefix('b').or_else(|| text.strip_prefix('B')) {
+        (2, digits)
+    } else {
+        (10, text)
+    };
+    if digits.is_empty() || (negative && digits.starts_with('-')) {
+        return None;
+    }
+    let value = i32::from_str_radix(digits, radix).ok()?;
+    if negative {
+        value.checked_neg()
+    } else {
+        Some(value)
+    }
+}
+
+fn parse_unsigned(token: &Token, bits: u8, line: usize) -> Result<u16, AssemblerError> {
+    let value = parse_number(&token.text).ok_or(error(
+        line,
+        token.column,
+        AssemblerErrorKind::InvalidNumber(token.text.clone()),
+    ))?;
+    let max = 1_i32.checked_shl(bits.into()).unwrap_or(i32::MAX);
+    if value < 0 || value >= max {
+        return Err(error(
+            line,
+            token.column,
+            AssemblerErrorKind::OutOfRange { value, bits },
+        ));
+    }
+    u16::try_from(value).map_err(|_| {
+        error(
+            line,
+            token.column,
+            AssemblerErrorKind::OutOfRange { value, bits },
+        )
+    })
+}
+
+/// Checks that a value fits in a two's complement field of the given width and returns it
+/// truncated to 16 bits
+fn fit_signed(value: i32, bits: u8, token: &Token, line: usize) -> Result<u16, AssemblerError> {
+    let out_of_range = || {
+        error(
+            line,
+            token.column,
+            AssemblerErrorKind::OutOfRange { value, bits },
+        )
+    };
+    let limit = 1_i32
+        .checked_shl(u32::from(bits).saturating_sub(1))
+        .ok_or_else(out_of_range)?;
+    if value < limit.wrapping_neg() || value >= limit {
+        return Err(out_of_range());
+    }
+    u16::try_from(value & 0xFFFF).map_err(|_| out_of_range())
+}
+
+/// Accepts both signed and unsigned 16 bit values
+fn fit_word(value: i32, token: &Token, line: usize) -> Result<u16, AssemblerError> {
+    if value < i32::from(i16::MIN) || value > i32::from(u16::MAX) {
+        return Err(error(
+            line,
+            token.column,
+            AssemblerErrorKind::OutOfRange { value, bits: 16 },
+        ));
+    }
+    u16::try_from(value & 0xFFFF).map_err(|_| {
+        error(
+            line,
+            token.column,
+            AssemblerErrorKind::OutOfRange { value, bits: 16 },
+        )
+    })
+}
+
+fn resolve_label(
+    token: &Token,
+    symbols: &BTreeMap<String, u16>,
+    line: usize,
+) -> Result<u16, AssemblerError> {
+    symbols.get(&token.text).copied().ok_or(error(
+        line,
+        token.column,
+        AssemblerErrorKind::UndefinedLabel(token.text.clone()),
+    ))
+}
+
+fn low_byte(value: u16) -> u8 {
+    let [_, low] = value.to_be_bytes();
+    low
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn assemble_for_loop() -> Result<(), Box<dyn std::error::Error>> {
+        let source = std::fs::read_to_string("./test-programs/for_loop.asm")?;
+        let expected = std::fs::read("./test-programs/for_loop.obj")?;
+        let program = assemble(&source)?;
+        assert_eq!(expected, program.to_bytes());
+        assert_eq!(Some(&0x3001), program.symbols.get("LOOP"));
+        Ok(())
+    }
+
+    #[test]
+    fn assemble_add_overflow() -> Result<(), Box<dyn std::error::Error>> {
+        let source = std::fs::read_to_string("./test-programs/add_overflow.asm")?;
+        let expected = std::fs::read("./test-programs/add_overflow.obj")?;
+        assert_eq!(expected, assemble(&source)?.to_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn assemble_directives() -> Result<(), AssemblerError> {
+        let source = r#"
+            .ORIG x4000
+            LEA R0, MSG     ; comment with "quotes"
+            PUTS
+            HALT
+    MSG     .STRINGZ "Hi\n"
+    BUF     .BLKW 2
+    PTR     .FILL MSG
+            .FILL #-1
+            .END
+        "#;
+        let program = assemble(source)?;
+        assert_eq!(0x4000, program.origin);
+        assert_eq!(
+            vec![
+                0xE002, 0xF022, 0xF025, 0x0048, 0x0069, 0x000A, 0x0000, 0x0000, 0x0000, 0x4003,
+                0xFFFF
+            ],
+            program.words
+        );
+        // Only instructions are mapped back to their source lines
+        assert_eq!(
+            BTreeMap::from([(0x4000, 3), (0x4001, 4), (0x4002, 5)]),
+            program.lines
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn assemble_control_flow() -> Result<(), AssemblerError> {
+        let source = "
+            .ORIG x3000
+    START   JSR SUB
+            JSRR R3
+            BRnp START
+            BR START
+            TRAP x25
+    SUB     LDR R1, R6, #-1
+            STR R1, R6, 3
+            NOT R2, R1
+            RET
+            .END
+        ";
+        let program = assemble(source)?;
+        assert_eq!(
+            vec![0x4804, 0x40C0, 0x0BFD, 0x0FFC, 0xF025, 0x63BF, 0x7383, 0x947F, 0xC1C0],
+            program.words
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn report_undefined_label() {
+        let source = ".ORIG x3000\n  BRz NOWHERE\n.END";
+        assert_eq!(
+            Err(AssemblerError {
+                line: 2,
+                column: 7,
+                kind: AssemblerErrorKind::UndefinedLabel(String::from("NOWHERE")),
+            }),
+            assemble(source)
+        );
+    }
+
+    #[test]
+    fn report_immediate_out_of_range() {
+        let source = ".ORIG x3000\nADD R0, R0, #16\n.END";
+        assert_eq!(
+            Err(AssemblerError {
+                line: 2,
+                column: 13,
+                kind: AssemblerErrorKind::OutOfRange { value: 16, bits: 5 },
+            }),
+            assemble(source)
+        );
+    }
+
+    #[test]
+    fn report_missing_orig() {
+        let source = "ADD R0, R0, #1\n.END";
+        assert_eq!(
+            Err(AssemblerError {
+                line: 1,
+                column: 1,
+                kind: AssemblerErrorKind::MissingOrig,
+            }),
+            assemble(source)
+        );
+    }
+}
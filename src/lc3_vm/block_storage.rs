@@ -0,0 +1,220 @@
+use super::devices::{invalid_state, Device, DeviceContext, STATUS_READY};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::{Range, RangeInclusive},
+    path::Path,
+};
+
+pub(super) const MR_BLKCMD: u16 = 0xFE10;
+pub(super) const MR_BLKSEC: u16 = 0xFE12;
+pub(super) const MR_BLKADDR: u16 = 0xFE14;
+pub(super) const MR_BLKSR: u16 = 0xFE16;
+/// Commands written to BLKCMD
+pub(super) const COMMAND_READ: u16 = 1;
+pub(super) const COMMAND_WRITE: u16 = 2;
+/// Bit of the status register that is set when the last command failed
+pub(super) const STATUS_ERROR: u16 = 0b0000_0000_0000_0001;
+/// Words in a sector, stored big-endian in the backing file like in object files
+pub const SECTOR_WORDS: usize = 256;
+
+/// Block device whose sectors are stored in a host file. The program selects a sector in
+/// BLKSEC and a buffer in BLKADDR, then writes a command to BLKCMD (1 reads the sector into the
+/// buffer, 2 writes the buffer to the sector). Transfers finish straight away, BLKSR keeps its
+/// ready bit set and has its error bit set when the last command failed
+pub struct BlockStorage<F> {
+    backing: F,
+    command: u16,
+    sector: u16,
+    address: u16,
+    status: u16,
+}
+
+impl BlockStorage<File> {
+    /// Opens the backing file, creating it when it doesn't exist
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self::new(file))
+    }
+}
+
+impl<F: Read + Write + Seek> BlockStorage<F> {
+    /// Uses any seekable backing store, for example an in memory `Cursor`
+    pub fn new(backing: F) -> Self {
+        Self {
+            backing,
+            command: 0,
+            sector: 0,
+            address: 0,
+            status: STATUS_READY,
+        }
+    }
+
+    /// Memory taken by the buffer, None when it runs past the end of memory
+    fn buffer(&self, memory_size: usize) -> Option<Range<usize>> {
+        let start = usize::from(self.address);
+        let end = start.checked_add(SECTOR_WORDS)?;
+        (end <= memory_size).then_some(start..end)
+    }
+
+    fn seek_sector(&mut self) -> io::Result<()> {
+        let sector_bytes = SECTOR_WORDS.saturating_mul(2);
+        let offset = usize::from(self.sector).saturating_mul(sector_bytes);
+        let offset = u64::try_from(offset).map_err(|_| io::ErrorKind::InvalidInput)?;
+        self.backing.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    fn execute(&mut self, context: &mut DeviceContext) -> io::Result<()> {
+        let buffer = self
+            .buffer(context.memory().len())
+            .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.seek_sector()?;
+        match self.command {
+            COMMAND_READ => {
+                // Sectors past the end of the file read as zeros
+                let mut bytes = Vec::new();
+                let sector_bytes = SECTOR_WORDS.saturating_mul(2);
+                let limit = u64::try_from(sector_bytes).map_err(|_| io::ErrorKind::InvalidInput)?;
+                (&mut self.backing).take(limit).read_to_end(&mut bytes)?;
+                bytes.resize(sector_bytes, 0);
+                let words: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|pair| {
+                        u16::from_be_bytes([
+                            pair.first().copied().unwrap_or_default(),
+                            pair.get(1).copied().unwrap_or_default(),
+                        ])
+                    })
+                    .collect();
+                context.write_memory(self.address, &words)?;
+            }
+            COMMAND_WRITE => {
+                let bytes: Vec<u8> = context
+                    .memory()
+                    .get(buffer)
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(|word| word.to_be_bytes())
+                    .collect();
+                self.backing.write_all(&bytes)?;
+                self.backing.flush()?;
+            }
+            _ => return Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+        Ok(())
+    }
+}
+
+impl<F: Read + Write + Seek> Device for BlockStorage<F> {
+    fn registers(&self) -> RangeInclusive<u16> {
+        MR_BLKCMD..=MR_BLKSR
+    }
+
+    fn read(&mut self, address: u16, _context: &mut DeviceContext) -> io::Result<u16> {
+        Ok(self.peek(address))
+    }
+
+    fn write(&mut self, address: u16, value: u16, context: &mut DeviceContext) -> io::Result<()> {
+        match address {
+            MR_BLKCMD => {
+                self.command = value;
+                // Failures are reported to the program through the status register
+                self.status = match self.execute(context) {
+                    Ok(()) => STATUS_READY,
+                    Err(_) => STATUS_READY | STATUS_ERROR,
+                };
+            }
+            MR_BLKSEC => self.sector = value,
+            MR_BLKADDR => self.address = value,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        match address {
+            MR_BLKCMD => self.command,
+            MR_BLKSEC => self.sector,
+            MR_BLKADDR => self.address,
+            MR_BLKSR => self.status,
+            _ => 0,
+        }
+    }
+
+    fn active(&self) -> bool {
+        false
+    }
+
+    /// Only the registers are saved, the sectors stay in the backing file
+    fn save(&self) -> Vec<u16> {
+        vec![self.command, self.sector, self.address, self.status]
+    }
+
+    fn restore(&mut self, state: &[u16]) -> io::Result<()> {
+        let [command, sector, address, status] = state else {
+            return Err(invalid_state());
+        };
+        self.command = *command;
+        self.sector = *sector;
+        self.address = *address;
+        self.status = *status;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lc3_vm::io_device::BufferIo;
+
+    #[test]
+    fn sectors_round_trip_through_file() -> Result<(), Box<dyn std::error::Error>> {
+        let path =
+            std::env::temp_dir().join(format!("lc3-block-storage-{}.img", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let mut io = BufferIo::with_input(Vec::new());
+        let mut memory = vec![0; 1 << 16];
+        let mut running = true;
+        let mut storage = BlockStorage::open(&path)?;
+        let mut context = DeviceContext::new(&mut io, &mut memory, &mut running);
+
+        // Reading past the end of the file gives zeros
+        storage.write(MR_BLKSEC, 2, &mut context)?;
+        storage.write(MR_BLKADDR, 0x4000, &mut context)?;
+        storage.write(MR_BLKCMD, COMMAND_READ, &mut context)?;
+        assert_eq!(STATUS_READY, storage.peek(MR_BLKSR));
+
+        context.write_memory(0x4000, &[0x1234; 256])?;
+        storage.write(MR_BLKCMD, COMMAND_WRITE, &mut context)?;
+        assert_eq!(STATUS_READY, storage.peek(MR_BLKSR));
+        let contents = std::fs::read(&path)?;
+        assert_eq!(3 * 512, contents.len());
+        assert_eq!(Some(&[0x12, 0x34][..]), contents.get(1024..1026));
+
+        storage.write(MR_BLKADDR, 0x5000, &mut context)?;
+        storage.write(MR_BLKCMD, COMMAND_READ, &mut context)?;
+        assert_eq!(
+            Some(&[0x1234; 256][..]),
+            context.memory().get(0x5000..0x5100)
+        );
+
+        // The buffer can't run past the end of memory
+        storage.write(MR_BLKADDR, 0xFF80, &mut context)?;
+        storage.write(MR_BLKCMD, COMMAND_READ, &mut context)?;
+        assert_eq!(STATUS_READY | STATUS_ERROR, storage.peek(MR_BLKSR));
+
+        // Every word written to memory is logged with its previous value
+        let written = context.into_written();
+        assert_eq!(3 * 256, written.len());
+        assert_eq!(Some(&(0x4000, 0)), written.first());
+        assert_eq!(Some(&(0x50FF, 0)), written.last());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
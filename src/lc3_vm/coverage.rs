@@ -0,0 +1,298 @@
+use super::{flags::ConditionFlags, opcodes::Opcode, virtual_machine::VM};
+use std::{collections::BTreeMap, fmt::Write as _, ops::Range};
+
+/// How many times a conditional branch jumped and fell through
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Counts how many times every address was executed and which way every conditional branch
+/// went. Fed by `record` after every instruction, for example from `VM::run_with`
+pub struct Coverage {
+    hits: Vec<u64>,
+    branches: BTreeMap<u16, Branch>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self {
+            hits: vec![0; 1 << 16],
+            branches: BTreeMap::new(),
+        }
+    }
+}
+
+impl Coverage {
+    /// Counts the instruction the VM just executed
+    pub fn record(&mut self, vm: &VM) {
+        let Some((address, instruction)) = vm.last_instruction() else {
+            return;
+        };
+        if let Some(hits) = self.hits.get_mut(usize::from(address)) {
+            *hits = hits.wrapping_add(1);
+        }
+        // BR doesn't change the condition codes, so they still tell which way it went
+        if let Ok(Opcode::BR { n, z, p, .. }) = Opcode::try_from(instruction) {
+            if is_conditional(instruction) {
+                let taken = match vm.condition() {
+                    ConditionFlags::NEG => n,
+                    ConditionFlags::ZRO => z,
+                    ConditionFlags::POS => p,
+                };
+                let branch = self.branches.entry(address).or_default();
+                if taken {
+                    branch.taken = branch.taken.wrapping_add(1);
+                } else {
+                    branch.not_taken = branch.not_taken.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Number of times the instruction at the address was executed
+    pub fn hits(&self, address: u16) -> u64 {
+        self.hits
+            .get(usize::from(address))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Directions taken by the conditional branch at the address, None when it never ran
+    pub fn branch(&self, address: u16) -> Option<Branch> {
+        self.branches.get(&address).copied()
+    }
+
+    /// Renders every instruction of a source file with its hit count, `#####` marks the ones
+    /// that never ran. `lines` maps instruction addresses to source lines, like
+    /// `Program::lines`
+    pub fn report(
+        &self,
+        vm: &VM,
+        file_name: &str,
+        source: &str,
+        lines: &BTreeMap<u16, usize>,
+    ) -> String {
+        let by_line: BTreeMap<usize, u16> = lines
+            .iter()
+            .map(|(address, line)| (*line, *address))
+            .collect();
+        let covered = lines
+            .keys()
+            .filter(|address| self.hits(**address) > 0)
+            .count();
+        let (directions, covered_directions) = self.directions(vm, lines.keys().copied());
+        let mut report = format!(
+            "Coverage of {file_name}: {covered} of {} instructions, {covered_directions} of {directions} branch directions\n",
+            lines.len()
+        );
+        for (index, text) in source.lines().enumerate() {
+            let line = index.saturating_add(1);
+            let hits = match by_line.get(&line) {
+                Some(address) => match self.hits(*address) {
+                    0 => String::from("#####"),
+                    hits => hits.to_string(),
+                },
+                None => String::from("-"),
+            };
+            let _ = write!(report, "{hits:>8}  {line:>5}  {text}");
+            if let Some(address) = by_line.get(&line) {
+                if let Some(branch) = self.conditional(vm, *address) {
+                    let _ = write!(
+                        report,
+                        "    [taken {}, not taken {}]",
+                        branch.taken, branch.not_taken
+                    );
+                }
+            }
+            report.push('\n');
+        }
+        report
+    }
+
+    /// Renders every word of a memory range with its hit count and disassembly, for programs
+    /// without source. Addresses are named after the symbols of the VM
+    pub fn listing(&self, vm: &VM, range: Range<u16>) -> String {
+        let addresses: Vec<u16> = range.clone().collect();
+        let covered = addresses
+            .iter()
+            .filter(|address| self.hits(**address) > 0)
+            .count();
+        let mut report = format!(
+            "Coverage of x{:04X}-x{:04X}: {covered} of {} words executed\n",
+            range.start,
+            range.end.wrapping_sub(1),
+            addresses.len()
+        );
+        for word in vm.disassemble(range) {
+            let hits = match self.hits(word.address) {
+                0 => String::from("#####"),
+                hits => hits.to_string(),
+            };
+            let _ = write!(
+                report,
+                "{hits:>8}  {}",
+                word.to_string_with_symbols(vm.symbols())
+            );
+            if let Some(branch) = self.conditional(vm, word.address) {
+                let _ = write!(
+                    report,
+                    "    [taken {}, not taken {}]",
+                    branch.taken, branch.not_taken
+                );
+            }
+            report.push('\n');
+        }
+        report
+    }
+
+    /// Renders an lcov tracefile record for a source file, with line and branch coverage
+    pub fn lcov(&self, vm: &VM, file_name: &str, lines: &BTreeMap<u16, usize>) -> String {
+        let mut record = format!("TN:\nSF:{file_name}\n");
+        let mut branches_found: usize = 0;
+        let mut branches_hit: usize = 0;
+        for (address, line) in lines {
+            let Some(branch) = self.conditional(vm, *address) else {
+                continue;
+            };
+            // lcov uses `-` for branches whose line never ran
+            let executed = self.hits(*address) > 0;
+            for (index, count) in [branch.taken, branch.not_taken].into_iter().enumerate() {
+                let count = if executed {
+                    count.to_string()
+                } else {
+                    String::from("-")
+                };
+                let _ = writeln!(record, "BRDA:{line},0,{index},{count}");
+            }
+            branches_found = branches_found.saturating_add(2);
+            branches_hit = branches_hit
+                .saturating_add(usize::from(branch.taken > 0))
+                .saturating_add(usize::from(branch.not_taken > 0));
+        }
+        let _ = writeln!(record, "BRF:{branches_found}\nBRH:{branches_hit}");
+        for (address, line) in lines {
+            let _ = writeln!(record, "DA:{line},{}", self.hits(*address));
+        }
+        let hit = lines
+            .keys()
+            .filter(|address| self.hits(**address) > 0)
+            .count();
+        let _ = writeln!(record, "LF:{}\nLH:{hit}\nend_of_record", lines.len());
+        record
+    }
+
+    /// Directions of the instruction at the address when it is a conditional branch, also
+    /// when it never ran
+    fn conditional(&self, vm: &VM, address: u16) -> Option<Branch> {
+        is_conditional(vm.peek_word(address)).then(|| self.branch(address).unwrap_or_default())
+    }
+
+    /// Number of branch directions of the conditional branches among the addresses, and how
+    /// many of them were taken at least once
+    fn directions(&self, vm: &VM, addresses: impl Iterator<Item = u16>) -> (usize, usize) {
+        addresses
+            .filter_map(|address| self.conditional(vm, address))
+            .fold((0, 0), |(found, hit), branch| {
+                (
+                    found.saturating_add(2),
+                    hit.saturating_add(usize::from(branch.taken > 0))
+                        .saturating_add(usize::from(branch.not_taken > 0)),
+                )
+            })
+    }
+}
+
+/// BR with every flag always jumps and with none never does, only the others have two
+/// directions
+fn is_conditional(instruction: u16) -> bool {
+    matches!(
+        Opcode::try_from(instruction),
+        Ok(Opcode::BR { n, z, p, .. }) if !(n && z && p) && (n || z || p)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lc3_vm::{assembler::assemble, io_device::BufferIo};
+
+    #[test]
+    fn lines_and_branch_directions_are_covered() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "\
+        .ORIG x3000
+        AND R0, R0, #0
+LOOP    ADD R0, R0, #1
+        ADD R1, R0, #-3
+        BRn LOOP
+        BRp NEVER
+        HALT
+NEVER   ADD R0, R0, #0
+        HALT
+        .END
+";
+        let program = assemble(source)?;
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&program.to_bytes())?;
+        let mut coverage = Coverage::default();
+        vm.run_with(|vm| coverage.record(vm))?;
+
+        assert_eq!(3, coverage.hits(0x3003));
+        assert_eq!(
+            Some(Branch {
+                taken: 2,
+                not_taken: 1
+            }),
+            coverage.branch(0x3003)
+        );
+        assert_eq!(
+            Some(Branch {
+                taken: 0,
+                not_taken: 1
+            }),
+            coverage.branch(0x3004)
+        );
+        assert_eq!(
+            "\
+Coverage of loop.asm: 6 of 8 instructions, 3 of 4 branch directions
+       -      1  .ORIG x3000
+       1      2          AND R0, R0, #0
+       3      3  LOOP    ADD R0, R0, #1
+       3      4          ADD R1, R0, #-3
+       3      5          BRn LOOP    [taken 2, not taken 1]
+       1      6          BRp NEVER    [taken 0, not taken 1]
+       1      7          HALT
+   #####      8  NEVER   ADD R0, R0, #0
+   #####      9          HALT
+       -     10          .END
+",
+            coverage.report(&vm, "loop.asm", source, &program.lines)
+        );
+        assert_eq!(
+            "\
+TN:
+SF:loop.asm
+BRDA:5,0,0,2
+BRDA:5,0,1,1
+BRDA:6,0,0,0
+BRDA:6,0,1,1
+BRF:4
+BRH:3
+DA:2,1
+DA:3,3
+DA:4,3
+DA:5,3
+DA:6,1
+DA:7,1
+DA:8,0
+DA:9,0
+LF:8
+LH:6
+end_of_record
+",
+            coverage.lcov(&vm, "loop.asm", &program.lines)
+        );
+        Ok(())
+    }
+}
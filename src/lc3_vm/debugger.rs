@@ -0,0 +1,581 @@
+use super::{
+    assembler::parse_number,
+    history::{History, ReverseStop},
+    opcodes::Opcode,
+    registers::Register,
+    symbols::describe,
+    virtual_machine::{MemoryAccess, VM},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, Write},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DebuggerError {
+    #[error("Failed to read command: {0}")]
+    Input(String),
+    #[error("Failed to write output: {0}")]
+    Output(String),
+}
+
+const HELP: &str = "\
+step [count]          (s)  execute count instructions, 1 by default
+continue              (c)  run until a breakpoint is hit or the program halts
+finish                (f)  run until the current subroutine returns
+reverse-step [count]  (rs) undo count instructions, 1 by default
+reverse-continue      (rc) undo instructions until a breakpoint or a watched write
+break <location>      (b)  set a breakpoint at an address or label
+watch <location>      (w)  stop when the word at an address or label is written
+delete <location>     (d)  remove a breakpoint or watchpoint
+breakpoints           (bl) list breakpoints and watchpoints
+registers             (r)  print registers
+memory <location> [n] (m)  print n words of memory, 8 by default
+list [location] [n]   (l)  disassemble n words, starting at the PC by default
+set <location> <value>     write a word into memory, device registers are refused
+help                  (h)  print this message
+quit                  (q)  exit the debugger
+Locations are labels or addresses written as x3000, 0x3000 or decimal";
+
+/// Why the machine stopped after running one or more instructions
+enum Stop {
+    Halted,
+    Breakpoint(u16),
+    /// The last instruction wrote the watched address, or is the next one to write it when
+    /// running backwards
+    Watchpoint(u16),
+    Finished,
+    /// Running backwards undid every recorded instruction
+    Start,
+    Error(String),
+}
+
+/// Interactive debugger that drives a [`VM`] one instruction at a time. The executed
+/// instructions are recorded so they can be undone, with the default history unless the VM
+/// already has one. The symbols are also given to the VM to name addresses in its errors
+pub struct Debugger {
+    vm: VM,
+    symbols: BTreeMap<String, u16>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    pub fn new(mut vm: VM, symbols: BTreeMap<String, u16>) -> Self {
+        vm.running = true;
+        vm.set_symbols(symbols.clone());
+        if vm.history().is_none() {
+            vm.set_history(Some(History::default()));
+        }
+        Self {
+            vm,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Reads commands until `quit` is entered or the input ends
+    pub fn run<I, W>(&mut self, commands: I, mut output: W) -> Result<(), DebuggerError>
+    where
+        I: IntoIterator<Item = io::Result<String>>,
+        W: Write,
+    {
+        self.print_location(&mut output)?;
+        prompt(&mut output)?;
+        for command in commands {
+            let command = command.map_err(|err| DebuggerError::Input(err.to_string()))?;
+            if !self.command(&command, &mut output)? {
+                break;
+            }
+            prompt(&mut output)?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single command, returns false when the debugger should exit
+    pub fn command<W: Write>(&mut self, line: &str, output: &mut W) -> Result<bool, DebuggerError> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let arguments: Vec<&str> = words.collect();
+        let first = arguments.first().copied();
+        let second = arguments.get(1).copied();
+
+        match command {
+            "s" | "step" => {
+                let count = match first {
+                    Some(count) => match count.parse::<usize>() {
+                        Ok(count) => count,
+                        Err(_) => {
+                            self.reply(output, &format!("invalid count {count}"))?;
+                            return Ok(true);
+                        }
+                    },
+                    None => 1,
+                };
+                let mut stop = None;
+                for _ in 0..count {
+                    if let Some(reason) = self.step() {
+                        stop = Some(reason);
+                        break;
+                    }
+                }
+                self.report(stop, output)?;
+            }
+            "c" | "continue" => {
+                let stop = self.resume(false);
+                self.report(Some(stop), output)?;
+            }
+            "f" | "finish" => {
+                let stop = self.resume(true);
+                self.report(Some(stop), output)?;
+            }
+            "rs" | "reverse-step" => {
+                let count = match first {
+                    Some(count) => match count.parse::<u64>() {
+                        Ok(count) => count,
+                        Err(_) => {
+                            self.reply(output, &format!("invalid count {count}"))?;
+                            return Ok(true);
+                        }
+                    },
+                    None => 1,
+                };
+                let stop = match self.vm.step_back(count) {
+                    Ok(undone) if undone < count => Some(Stop::Start),
+                    Ok(_) => None,
+                    Err(err) => Some(Stop::Error(err.to_string())),
+                };
+                self.report(stop, output)?;
+            }
+            "rc" | "reverse-continue" => {
+                let stop = match self
+                    .vm
+                    .reverse_continue(&self.breakpoints, &self.watchpoints)
+                {
+                    Ok(ReverseStop::Breakpoint(address)) => Stop::Breakpoint(address),
+                    Ok(ReverseStop::Watchpoint(address)) => Stop::Watchpoint(address),
+                    Ok(ReverseStop::Start) => Stop::Start,
+                    Err(err) => Stop::Error(err.to_string()),
+                };
+                self.report(Some(stop), output)?;
+            }
+            "b" | "break" => match self.location(first) {
+                Ok(address) => {
+                    self.breakpoints.insert(address);
+                    self.reply(output, &format!("breakpoint set at {}", self.name(address)))?;
+                }
+                Err(err) => self.reply(output, &err)?,
+            },
+            "w" | "watch" => match self.location(first) {
+                Ok(address) => {
+                    self.watchpoints.insert(address);
+                    self.reply(output, &format!("watchpoint set at {}", self.name(address)))?;
+                }
+                Err(err) => self.reply(output, &err)?,
+            },
+            "d" | "delete" => match self.location(first) {
+                Ok(address) => {
+                    let message = if self.breakpoints.remove(&address) {
+                        format!("breakpoint at {} deleted", self.name(address))
+                    } else if self.watchpoints.remove(&address) {
+                        format!("watchpoint at {} deleted", self.name(address))
+                    } else {
+                        format!("no breakpoint at {}", self.name(address))
+                    };
+                    self.reply(output, &message)?;
+                }
+                Err(err) => self.reply(output, &err)?,
+            },
+            "bl" | "breakpoints" => {
+                if self.breakpoints.is_empty() && self.watchpoints.is_empty() {
+                    self.reply(output, "no breakpoints")?;
+                }
+                for address in &self.breakpoints {
+                    self.reply(output, &self.name(*address))?;
+                }
+                for address in &self.watchpoints {
+                    self.reply(output, &format!("{} (watch)", self.name(*address)))?;
+                }
+            }
+            "r" | "registers" => self.print_registers(output)?,
+            "m" | "memory" => match (self.location(first), count_argument(second, 8)) {
+                (Ok(address), Ok(count)) => self.print_memory(address, count, output)?,
+                (Err(err), _) | (_, Err(err)) => self.reply(output, &err)?,
+            },
+            "l" | "list" => {
+                let address = match first {
+                    Some(_) => self.location(first),
+                    None => Ok(self.pc()),
+                };
+                match (address, count_argument(second, 8)) {
+                    (Ok(address), Ok(count)) => {
+                        let end = address.saturating_add(count);
+                        for word in self.vm.disassemble(address..end) {
+                            self.reply(output, &word.to_string_with_symbols(&self.symbols))?;
+                        }
+                    }
+                    (Err(err), _) | (_, Err(err)) => self.reply(output, &err)?,
+                }
+            }
+            "set" => match (self.location(first), second.map(parse_value)) {
+                (Ok(address), Some(Some(value))) => match self.vm.poke_word(address, value) {
+                    Ok(()) => self.reply(output, &format!("x{:04X} = x{:04X}", address, value))?,
+                    Err(err) => self.reply(output, &err.to_string())?,
+                },
+                (Err(err), _) => self.reply(output, &err)?,
+                _ => self.reply(output, "expected a value")?,
+            },
+            "h" | "help" => self.reply(output, HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => self.reply(output, &format!("unknown command {command}, try help"))?,
+        }
+        Ok(true)
+    }
+
+    /// Executes one instruction, returns the reason to stop if the machine can't keep running
+    fn step(&mut self) -> Option<Stop> {
+        if !self.vm.running {
+            return Some(Stop::Halted);
+        }
+        if let Err(err) = self.vm.next_instruction() {
+            self.vm.running = false;
+            return Some(Stop::Error(err.to_string()));
+        }
+        if !self.vm.running {
+            return Some(Stop::Halted);
+        }
+        None
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit or the program halts. When `until_return` is set it also
+    /// stops once the subroutine that is currently executing returns, calls are tracked by
+    /// counting the JSR and JSRR instructions, the TRAPs that run OS service routines and the
+    /// interrupt and exception handlers entered, that haven't been matched by a RET or RTI yet
+    fn resume(&mut self, until_return: bool) -> Stop {
+        let mut depth: usize = 0;
+        loop {
+            if let Some(stop) = self.step() {
+                return stop;
+            }
+            let entries = self.vm.last_entries();
+            depth = depth.saturating_add(usize::from(entries.interrupt.is_some()));
+            // An instruction that raised an exception didn't call or return
+            let opcode = match entries.exception {
+                Some(_) => {
+                    depth = depth.saturating_add(1);
+                    None
+                }
+                None => self
+                    .vm
+                    .last_instruction()
+                    .and_then(|(_, instruction)| Opcode::try_from(instruction).ok()),
+            };
+            match opcode {
+                Some(Opcode::JSR { .. }) => depth = depth.saturating_add(1),
+                Some(Opcode::TRAP { .. }) if self.vm.os_loaded() => depth = depth.saturating_add(1),
+                Some(Opcode::JMP { base_r: 7 } | Opcode::RTI {}) => match depth.checked_sub(1) {
+                    Some(outer) => depth = outer,
+                    None if until_return => return Stop::Finished,
+                    None => {}
+                },
+                _ => {}
+            }
+            if let Some(address) = self.watched_write() {
+                return Stop::Watchpoint(address);
+            }
+            let pc = self.pc();
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+    }
+
+    /// Watched address written by the last instruction
+    fn watched_write(&self) -> Option<u16> {
+        self.vm
+            .last_accesses()
+            .iter()
+            .find_map(|access| match access {
+                MemoryAccess::Write { address, .. } if self.watchpoints.contains(address) => {
+                    Some(*address)
+                }
+                _ => None,
+            })
+    }
+
+    fn report<W: Write>(&self, stop: Option<Stop>, output: &mut W) -> Result<(), DebuggerError> {
+        match stop {
+            Some(Stop::Halted) => return self.reply(output, "program halted"),
+            Some(Stop::Error(err)) => return self.reply(output, &format!("error: {err}")),
+            Some(Stop::Breakpoint(address)) => {
+                self.reply(output, &format!("breakpoint at {}", self.name(address)))?
+            }
+            Some(Stop::Watchpoint(address)) => self.reply(
+                output,
+                &format!(
+                    "watchpoint at {}: x{:04X}",
+                    self.name(address),
+                    self.vm.peek_word(address)
+                ),
+            )?,
+            Some(Stop::Start) => self.reply(output, "reached the start of the history")?,
+            Some(Stop::Finished) | None => {}
+        }
+        self.print_location(output)
+    }
+
+    fn print_location<W: Write>(&self, output: &mut W) -> Result<(), DebuggerError> {
+        let pc = self.pc();
+        let line = self
+            .vm
+            .disassemble(pc..pc.saturating_add(1))
+            .first()
+            .map(|word| word.to_string_with_symbols(&self.symbols))
+            .unwrap_or_else(|| describe(&self.symbols, pc));
+        self.reply(output, &format!("=> {line}"))
+    }
+
+    fn print_registers<W: Write>(&self, output: &mut W) -> Result<(), DebuggerError> {
+        let registers: Vec<String> = Register::ALL
+            .iter()
+            .map(|register| format!("{} x{:04X}", register, self.vm.reg(*register)))
+            .collect();
+        self.reply(output, &registers.join("  "))?;
+        self.reply(
+            output,
+            &format!(
+                "PC x{:04X}  PSR x{:04X}  COND {}",
+                self.pc(),
+                self.vm.psr(),
+                self.vm.condition()
+            ),
+        )
+    }
+
+    fn print_memory<W: Write>(
+        &self,
+        address: u16,
+        count: u16,
+        output: &mut W,
+    ) -> Result<(), DebuggerError> {
+        let addresses: Vec<u16> = (0..count)
+            .map(|offset| address.wrapping_add(offset))
+            .collect();
+        for row in addresses.chunks(8) {
+            let Some(start) = row.first() else {
+                continue;
+            };
+            let words: Vec<String> = row
+                .iter()
+                .map(|address| format!("x{:04X}", self.vm.peek_word(*address)))
+                .collect();
+            self.reply(output, &format!("x{:04X}: {}", start, words.join(" ")))?;
+        }
+        Ok(())
+    }
+
+    fn reply<W: Write>(&self, output: &mut W, message: &str) -> Result<(), DebuggerError> {
+        writeln!(output, "{message}").map_err(|err| DebuggerError::Output(err.to_string()))
+    }
+
+    fn pc(&self) -> u16 {
+        self.vm.pc()
+    }
+
+    /// Resolves a label or an address
+    fn location(&self, argument: Option<&str>) -> Result<u16, String> {
+        let argument = argument.ok_or(String::from("expected a location"))?;
+        if let Some(address) = self.symbols.get(argument) {
+            return Ok(*address);
+        }
+        let hex = argument
+            .strip_prefix("0x")
+            .or_else(|| argument.strip_prefix('x'));
+        let address = match hex {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => argument.parse().ok(),
+        };
+        address.ok_or(format!("unknown location {argument}"))
+    }
+
+    /// Renders an address with the closest label before it, if there is one
+    fn name(&self, address: u16) -> String {
+        describe(&self.symbols, address)
+    }
+}
+
+fn prompt<W: Write>(output: &mut W) -> Result<(), DebuggerError> {
+    write!(output, "(lc3) ")
+        .and_then(|_| output.flush())
+        .map_err(|err| DebuggerError::Output(err.to_string()))
+}
+
+fn count_argument(argument: Option<&str>, default: u16) -> Result<u16, String> {
+    match argument {
+        Some(count) => count.parse().map_err(|_| format!("invalid count {count}")),
+        None => Ok(default),
+    }
+}
+
+/// Parses a word in any notation the assembler accepts, negative values are stored in two's
+/// complement
+fn parse_value(value: &str) -> Option<u16> {
+    let value = parse_number(value)?;
+    if value < i32::from(i16::MIN) || value > i32::from(u16::MAX) {
+        return None;
+    }
+    u16::try_from(value & 0xFFFF).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lc3_vm::assembler::assemble;
+    use crate::lc3_vm::io_device::BufferIo;
+
+    const PROGRAM: &str = "
+        .ORIG x3000
+        AND R0, R0, #0
+        JSR INC
+        JSR INC
+        ADD R2, R0, #0
+        HALT
+INC     ADD R0, R0, #1
+        RET
+        .END
+    ";
+
+    fn debugger() -> Result<Debugger, Box<dyn std::error::Error>> {
+        let program = assemble(PROGRAM)?;
+        let mut vm = VM::default();
+        vm.load_bytes(&program.to_bytes())?;
+        Ok(Debugger::new(vm, program.symbols))
+    }
+
+    fn run_script(debugger: &mut Debugger, script: &str) -> Result<String, DebuggerError> {
+        let mut output = Vec::new();
+        debugger.run(script.lines().map(|line| Ok(line.to_string())), &mut output)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    #[test]
+    fn break_on_label_and_finish() -> Result<(), Box<dyn std::error::Error>> {
+        let mut debugger = debugger()?;
+        let output = run_script(&mut debugger, "break INC\ncontinue\nfinish\nregisters")?;
+        assert!(output.contains("breakpoint at INC (x3005)"));
+        assert!(output.contains("=> x3002  x4802  JSR INC (x3005)"));
+        assert!(output.contains("R0 x0001"));
+        Ok(())
+    }
+
+    #[test]
+    fn finish_returns_over_interrupt_handlers() -> Result<(), Box<dyn std::error::Error>> {
+        // The key press interrupts WAIT, the RTI of the handler doesn't return from WAIT
+        let program = assemble(
+            "
+            .ORIG x3000
+            LEA R0, HANDLER
+            STI R0, VECTOR
+            JSR WAIT
+            ADD R2, R1, #0
+            HALT
+    WAIT    LD R0, ENABLE
+            STI R0, KBSR
+    LOOP    LD R1, KEY
+            BRz LOOP
+            RET
+    HANDLER LDI R1, KBDR
+            ST R1, KEY
+            RTI
+    VECTOR  .FILL x0180
+    ENABLE  .FILL x4000
+    KBSR    .FILL xFE00
+    KBDR    .FILL xFE02
+    KEY     .FILL #0
+            .END
+        ",
+        )?;
+        let mut vm = VM::new(Box::new(BufferIo::with_input(b"k".to_vec())));
+        vm.load_bytes(&program.to_bytes())?;
+        let mut debugger = Debugger::new(vm, program.symbols);
+        let output = run_script(
+            &mut debugger,
+            "break WAIT
+continue
+finish",
+        )?;
+        assert!(output.contains("=> x3003  x1460  ADD R2, R1, #0"));
+        Ok(())
+    }
+
+    #[test]
+    fn step_and_edit_memory() -> Result<(), Box<dyn std::error::Error>> {
+        let mut debugger = debugger()?;
+        let output = run_script(
+            &mut debugger,
+            "step 2\nset x4000 #-2\nmemory x4000 1\nset xFFFE x0000\nstep",
+        )?;
+        assert!(output.contains("=> INC (x3005)  x1021  ADD R0, R0, #1"));
+        assert!(output.contains("x4000: xFFFE"));
+        // Device registers aren't written, clearing MCR would stop the machine
+        assert!(output.contains("Memory failure: xFFFE is a device register"));
+        assert!(!output.contains("program halted"));
+        Ok(())
+    }
+
+    #[test]
+    fn continue_until_halt() -> Result<(), Box<dyn std::error::Error>> {
+        let mut debugger = debugger()?;
+        let output = run_script(&mut debugger, "continue\nstep")?;
+        assert_eq!(2, output.matches("program halted").count());
+        assert_eq!(2, debugger.vm.reg(Register::R2));
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_step_and_watch() -> Result<(), Box<dyn std::error::Error>> {
+        let mut debugger = debugger()?;
+        let output = run_script(&mut debugger, "step 4\nreverse-step 2\nregisters")?;
+        assert!(output.contains("=> x3002  x4802  JSR INC (x3005)"));
+        assert!(output.contains("=> INC (x3005)  x1021  ADD R0, R0, #1\n(lc3) R0 x0000"));
+
+        // Watched writes stop the debugger after them forward and before them backwards
+        let program = assemble(
+            "
+            .ORIG x3000
+            AND R0, R0, #0
+    LOOP    ADD R0, R0, #1
+            ST R0, COUNT
+            ADD R1, R0, #-3
+            BRn LOOP
+            HALT
+    COUNT   .FILL #0
+            .END
+        ",
+        )?;
+        let mut vm = VM::default();
+        vm.load_bytes(&program.to_bytes())?;
+        let mut debugger = Debugger::new(vm, program.symbols);
+        let output = run_script(&mut debugger, "watch COUNT\nc\nc\nrc\nrc\nrs 100")?;
+        let stops: Vec<&str> = output
+            .lines()
+            .filter(|line| line.contains("watchpoint at") || line.contains("history"))
+            .collect();
+        assert_eq!(
+            vec![
+                "(lc3) watchpoint at COUNT (x3006): x0001",
+                "(lc3) watchpoint at COUNT (x3006): x0002",
+                "(lc3) watchpoint at COUNT (x3006): x0001",
+                "(lc3) watchpoint at COUNT (x3006): x0000",
+                "(lc3) reached the start of the history",
+            ],
+            stops
+        );
+        assert!(output.ends_with("=> x3000  x5020  AND R0, R0, #0\n(lc3) "));
+        Ok(())
+    }
+}
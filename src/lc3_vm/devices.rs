@@ -0,0 +1,505 @@
+use super::io_device::IoDevice;
+use std::{io, ops::RangeInclusive, time::Instant};
+
+/// Page of memory reserved for device registers
+pub const DEVICE_PAGE: RangeInclusive<u16> = 0xFE00..=0xFFFF;
+
+const MR_KBSR: u16 = 0xFE00;
+const MR_KBDR: u16 = 0xFE02;
+const MR_DSR: u16 = 0xFE04;
+const MR_DDR: u16 = 0xFE06;
+const MR_TCR: u16 = 0xFE08;
+const MR_TIR: u16 = 0xFE0A;
+const MR_TSR: u16 = 0xFE0C;
+const MR_MCR: u16 = 0xFFFE;
+/// Ready bit of the device status registers
+pub const STATUS_READY: u16 = 0b1000_0000_0000_0000;
+/// Interrupt enable bit of the device status registers
+pub const STATUS_INTERRUPT_ENABLE: u16 = 0b0100_0000_0000_0000;
+/// Clock enable bit of the machine control register, the machine stops when it is cleared
+const MCR_CLOCK_ENABLE: u16 = 0b1000_0000_0000_0000;
+/// Enable bit of the timer control register
+const TIMER_ENABLE: u16 = 0b1000_0000_0000_0000;
+/// Bit of the timer control register that makes the timer count milliseconds instead of
+/// instructions
+const TIMER_WALL_CLOCK: u16 = 0b0000_0000_0000_0001;
+
+/// Parts of the machine a device can use while handling an access to its registers
+pub struct DeviceContext<'a> {
+    /// Keyboard and console of the machine
+    pub io: &'a mut dyn IoDevice,
+    /// Main memory, for devices that transfer whole blocks of words
+    memory: &'a mut [u16],
+    /// Address and previous value of every word written to memory, so the write can be undone
+    written: Vec<(u16, u16)>,
+    /// Cleared to stop the machine
+    pub running: &'a mut bool,
+}
+
+impl<'a> DeviceContext<'a> {
+    pub fn new(io: &'a mut dyn IoDevice, memory: &'a mut [u16], running: &'a mut bool) -> Self {
+        Self {
+            io,
+            memory,
+            written: Vec::new(),
+            running,
+        }
+    }
+
+    pub fn memory(&self) -> &[u16] {
+        self.memory
+    }
+
+    /// Copies the words into memory starting at `address`, fails without writing anything
+    /// when they run past the end of memory
+    pub fn write_memory(&mut self, address: u16, words: &[u16]) -> io::Result<()> {
+        let start = usize::from(address);
+        let memory = start
+            .checked_add(words.len())
+            .and_then(|end| self.memory.get_mut(start..end))
+            .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?;
+        for ((address, word), value) in (address..=u16::MAX).zip(memory.iter_mut()).zip(words) {
+            self.written.push((address, *word));
+            *word = *value;
+        }
+        Ok(())
+    }
+
+    /// Address and previous value of every word written to memory, in order
+    pub fn into_written(self) -> Vec<(u16, u16)> {
+        self.written
+    }
+}
+
+/// Interrupt requested by a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptRequest {
+    /// Address of the interrupt vector table entry holding the handler
+    pub vector: u16,
+    /// Priority level, the interrupt is only taken when it is higher than the one in the PSR
+    pub priority: u16,
+}
+
+/// A peripheral whose registers are mapped into the device page
+pub trait Device {
+    /// Addresses of the registers handled by the device
+    fn registers(&self) -> RangeInclusive<u16>;
+    /// Called when the program reads one of the registers
+    fn read(&mut self, address: u16, context: &mut DeviceContext) -> io::Result<u16>;
+    /// Called when the program writes one of the registers
+    fn write(&mut self, address: u16, value: u16, context: &mut DeviceContext) -> io::Result<()>;
+    /// Value of a register without side effects, used by debuggers
+    fn peek(&self, address: u16) -> u16;
+    /// Called before every instruction, returns the interrupt the device requests if any
+    fn tick(&mut self, _context: &mut DeviceContext) -> io::Result<Option<InterruptRequest>> {
+        Ok(None)
+    }
+    /// Whether the next `tick` can change the state returned by `save`. Besides the devices
+    /// whose registers an instruction accesses, the history only saves the state of active
+    /// ones, so devices that only change when their registers are accessed return false
+    fn active(&self) -> bool {
+        true
+    }
+    /// Internal state saved in machine snapshots, devices without state save nothing
+    fn save(&self) -> Vec<u16> {
+        Vec::new()
+    }
+    /// Restores the state returned by `save`
+    fn restore(&mut self, state: &[u16]) -> io::Result<()> {
+        match state {
+            [] => Ok(()),
+            _ => Err(invalid_state()),
+        }
+    }
+}
+
+/// Error returned when a device is restored from state it didn't save
+pub fn invalid_state() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid device state")
+}
+
+/// Keyboard status (KBSR) and data (KBDR) registers
+#[derive(Default)]
+pub struct Keyboard {
+    status: u16,
+    data: u16,
+}
+
+impl Keyboard {
+    const REQUEST: InterruptRequest = InterruptRequest {
+        vector: 0x0180,
+        priority: 4,
+    };
+
+    /// Latches the next key press into KBDR and sets the ready bit of KBSR, unless the last
+    /// key hasn't been read yet
+    fn poll(&mut self, io: &mut dyn IoDevice) -> io::Result<()> {
+        if self.status & STATUS_READY == 0 && io.key_available()? {
+            self.data = io.read_key()?.into();
+            self.status |= STATUS_READY;
+        }
+        Ok(())
+    }
+}
+
+impl Device for Keyboard {
+    fn registers(&self) -> RangeInclusive<u16> {
+        MR_KBSR..=MR_KBDR
+    }
+
+    fn read(&mut self, address: u16, context: &mut DeviceContext) -> io::Result<u16> {
+        match address {
+            MR_KBSR => {
+                self.poll(context.io)?;
+                Ok(self.status)
+            }
+            MR_KBDR => {
+                // Reading the key clears the ready bit until the next one is pressed
+                self.status &= !STATUS_READY;
+                Ok(self.data)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16, _context: &mut DeviceContext) -> io::Result<()> {
+        // Only the interrupt enable bit can be written, the rest belongs to the keyboard
+        if address == MR_KBSR {
+            self.status = (value & STATUS_INTERRUPT_ENABLE) | (self.status & STATUS_READY);
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        match address {
+            MR_KBSR => self.status,
+            MR_KBDR => self.data,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, context: &mut DeviceContext) -> io::Result<Option<InterruptRequest>> {
+        if self.status & STATUS_INTERRUPT_ENABLE == 0 {
+            return Ok(None);
+        }
+        self.poll(context.io)?;
+        Ok((self.status & STATUS_READY != 0).then_some(Self::REQUEST))
+    }
+
+    /// Only an enabled keyboard latches keys when ticked, and only until one is ready
+    fn active(&self) -> bool {
+        self.status & STATUS_INTERRUPT_ENABLE != 0 && self.status & STATUS_READY == 0
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![self.status, self.data]
+    }
+
+    fn restore(&mut self, state: &[u16]) -> io::Result<()> {
+        let [status, data] = state else {
+            return Err(invalid_state());
+        };
+        self.status = *status;
+        self.data = *data;
+        Ok(())
+    }
+}
+
+/// Display status (DSR) and data (DDR) registers
+pub struct Display {
+    status: u16,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        // Output is written straight away, so the display is always ready
+        Self {
+            status: STATUS_READY,
+        }
+    }
+}
+
+impl Display {
+    const REQUEST: InterruptRequest = InterruptRequest {
+        vector: 0x0182,
+        priority: 4,
+    };
+}
+
+impl Device for Display {
+    fn registers(&self) -> RangeInclusive<u16> {
+        MR_DSR..=MR_DDR
+    }
+
+    fn read(&mut self, address: u16, _context: &mut DeviceContext) -> io::Result<u16> {
+        Ok(self.peek(address))
+    }
+
+    fn write(&mut self, address: u16, value: u16, context: &mut DeviceContext) -> io::Result<()> {
+        match address {
+            MR_DSR => {
+                self.status = (value & STATUS_INTERRUPT_ENABLE) | (self.status & STATUS_READY)
+            }
+            MR_DDR => {
+                let [_, char] = value.to_be_bytes();
+                context.io.write(&[char])?;
+                context.io.flush()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        match address {
+            MR_DSR => self.status,
+            _ => 0,
+        }
+    }
+
+    /// The display is always ready, so it requests an interrupt as long as it is enabled
+    fn tick(&mut self, _context: &mut DeviceContext) -> io::Result<Option<InterruptRequest>> {
+        let interrupt =
+            self.status & STATUS_INTERRUPT_ENABLE != 0 && self.status & STATUS_READY != 0;
+        Ok(interrupt.then_some(Self::REQUEST))
+    }
+
+    fn active(&self) -> bool {
+        false
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![self.status]
+    }
+
+    fn restore(&mut self, state: &[u16]) -> io::Result<()> {
+        let [status] = state else {
+            return Err(invalid_state());
+        };
+        self.status = *status;
+        Ok(())
+    }
+}
+
+/// Programmable timer. The control register (TCR) enables the timer, its interrupt and the wall
+/// clock mode, the interval register (TIR) holds the period and the status register (TSR) has
+/// its ready bit set every time the period elapses, until it is read. The period counts
+/// executed instructions, which keeps programs deterministic, or milliseconds in wall clock
+/// mode
+pub struct Timer {
+    control: u16,
+    interval: u16,
+    status: u16,
+    instructions: u16,
+    started: Instant,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self {
+            control: 0,
+            interval: 0,
+            status: 0,
+            instructions: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Timer {
+    const REQUEST: InterruptRequest = InterruptRequest {
+        vector: 0x0181,
+        priority: 6,
+    };
+
+    /// Starts counting a new period
+    fn restart(&mut self) {
+        self.instructions = 0;
+        self.started = Instant::now();
+    }
+
+    fn period_elapsed(&mut self) -> bool {
+        if self.control & TIMER_WALL_CLOCK != 0 {
+            self.started.elapsed().as_millis() >= u128::from(self.interval)
+        } else {
+            self.instructions = self.instructions.saturating_add(1);
+            self.instructions >= self.interval
+        }
+    }
+}
+
+impl Device for Timer {
+    fn registers(&self) -> RangeInclusive<u16> {
+        MR_TCR..=MR_TSR
+    }
+
+    fn read(&mut self, address: u16, _context: &mut DeviceContext) -> io::Result<u16> {
+        let value = self.peek(address);
+        if address == MR_TSR {
+            self.status &= !STATUS_READY;
+        }
+        Ok(value)
+    }
+
+    fn write(&mut self, address: u16, value: u16, _context: &mut DeviceContext) -> io::Result<()> {
+        match address {
+            MR_TCR => self.control = value,
+            MR_TIR => self.interval = value,
+            _ => return Ok(()),
+        }
+        self.restart();
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        match address {
+            MR_TCR => self.control,
+            MR_TIR => self.interval,
+            MR_TSR => self.status,
+            _ => 0,
+        }
+    }
+
+    fn tick(&mut self, _context: &mut DeviceContext) -> io::Result<Option<InterruptRequest>> {
+        // A zero interval would expire on every instruction, so it leaves the timer stopped
+        if self.control & TIMER_ENABLE == 0 || self.interval == 0 {
+            return Ok(None);
+        }
+        if self.period_elapsed() {
+            self.status |= STATUS_READY;
+            self.restart();
+        }
+        let interrupt =
+            self.control & STATUS_INTERRUPT_ENABLE != 0 && self.status & STATUS_READY != 0;
+        Ok(interrupt.then_some(Self::REQUEST))
+    }
+
+    fn active(&self) -> bool {
+        self.control & TIMER_ENABLE != 0 && self.interval != 0
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![self.control, self.interval, self.status, self.instructions]
+    }
+
+    /// A wall clock period starts over, the time that passed before saving is lost
+    fn restore(&mut self, state: &[u16]) -> io::Result<()> {
+        let [control, interval, status, instructions] = state else {
+            return Err(invalid_state());
+        };
+        self.control = *control;
+        self.interval = *interval;
+        self.status = *status;
+        self.restart();
+        self.instructions = *instructions;
+        Ok(())
+    }
+}
+
+/// Machine control register (MCR), the machine runs while its clock enable bit is set
+pub struct MachineControl {
+    value: u16,
+}
+
+impl Default for MachineControl {
+    fn default() -> Self {
+        Self {
+            value: MCR_CLOCK_ENABLE,
+        }
+    }
+}
+
+impl Device for MachineControl {
+    fn registers(&self) -> RangeInclusive<u16> {
+        MR_MCR..=MR_MCR
+    }
+
+    fn read(&mut self, _address: u16, _context: &mut DeviceContext) -> io::Result<u16> {
+        Ok(self.value)
+    }
+
+    fn write(&mut self, _address: u16, value: u16, context: &mut DeviceContext) -> io::Result<()> {
+        self.value = value;
+        if value & MCR_CLOCK_ENABLE == 0 {
+            *context.running = false;
+        }
+        Ok(())
+    }
+
+    fn peek(&self, _address: u16) -> u16 {
+        self.value
+    }
+
+    fn active(&self) -> bool {
+        false
+    }
+
+    fn save(&self) -> Vec<u16> {
+        vec![self.value]
+    }
+
+    fn restore(&mut self, state: &[u16]) -> io::Result<()> {
+        let [value] = state else {
+            return Err(invalid_state());
+        };
+        self.value = *value;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lc3_vm::io_device::BufferIo;
+
+    #[test]
+    fn keyboard_latches_one_key_at_a_time() -> Result<(), Box<dyn std::error::Error>> {
+        let mut io = BufferIo::with_input(b"ab".to_vec());
+        let mut memory = [0; 4];
+        let mut running = true;
+        let mut context = DeviceContext::new(&mut io, &mut memory, &mut running);
+        let mut keyboard = Keyboard::default();
+        assert_eq!(None, keyboard.tick(&mut context)?);
+
+        keyboard.write(MR_KBSR, 0xFFFF, &mut context)?;
+        assert_eq!(STATUS_INTERRUPT_ENABLE, keyboard.peek(MR_KBSR));
+        assert_eq!(Some(Keyboard::REQUEST), keyboard.tick(&mut context)?);
+        // The second key waits until the first one is read
+        assert_eq!(0xC000, keyboard.read(MR_KBSR, &mut context)?);
+        assert_eq!(u16::from(b'a'), keyboard.read(MR_KBDR, &mut context)?);
+        assert_eq!(STATUS_INTERRUPT_ENABLE, keyboard.peek(MR_KBSR));
+        assert_eq!(0xC000, keyboard.read(MR_KBSR, &mut context)?);
+        assert_eq!(u16::from(b'b'), keyboard.read(MR_KBDR, &mut context)?);
+        Ok(())
+    }
+
+    #[test]
+    fn timer_counts_instructions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut io = BufferIo::with_input(Vec::new());
+        let mut memory = [0; 4];
+        let mut running = true;
+        let mut context = DeviceContext::new(&mut io, &mut memory, &mut running);
+        let mut timer = Timer::default();
+        timer.write(MR_TIR, 3, &mut context)?;
+        assert_eq!(None, timer.tick(&mut context)?);
+        assert_eq!(0, timer.peek(MR_TSR));
+
+        timer.write(MR_TCR, TIMER_ENABLE, &mut context)?;
+        for _ in 0..3 {
+            assert_eq!(None, timer.tick(&mut context)?);
+        }
+        // Expired, but interrupts are disabled
+        assert_eq!(STATUS_READY, timer.read(MR_TSR, &mut context)?);
+        assert_eq!(0, timer.read(MR_TSR, &mut context)?);
+
+        timer.write(MR_TCR, TIMER_ENABLE | STATUS_INTERRUPT_ENABLE, &mut context)?;
+        assert_eq!(None, timer.tick(&mut context)?);
+        assert_eq!(None, timer.tick(&mut context)?);
+        assert_eq!(Some(Timer::REQUEST), timer.tick(&mut context)?);
+        // The request stays until the status register is read
+        assert_eq!(Some(Timer::REQUEST), timer.tick(&mut context)?);
+        timer.read(MR_TSR, &mut context)?;
+        assert_eq!(None, timer.tick(&mut context)?);
+        Ok(())
+    }
+}
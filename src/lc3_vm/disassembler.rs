@@ -0,0 +1,87 @@
+use super::{opcodes::Opcode, symbols::describe};
+use std::{collections::BTreeMap, fmt};
+
+/// A word of memory together with the instruction it decodes to
+#[derive(Debug, PartialEq)]
+pub struct DisassembledWord {
+    pub address: u16,
+    pub word: u16,
+    pub opcode: Opcode,
+}
+
+impl fmt::Display for DisassembledWord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "x{:04X}  x{:04X}  {}",
+            self.address,
+            self.word,
+            self.opcode.to_assembly(Some(self.address))
+        )
+    }
+}
+
+impl DisassembledWord {
+    /// Formats the word like `Display` does, with its address and the ones it refers to named
+    /// after the labels in `symbols`
+    pub fn to_string_with_symbols(&self, symbols: &BTreeMap<String, u16>) -> String {
+        format!(
+            "{}  x{:04X}  {}",
+            describe(symbols, self.address),
+            self.word,
+            self.opcode.to_assembly_with_symbols(self.address, symbols)
+        )
+    }
+}
+
+/// Decodes consecutive words of memory starting at `origin`. Every word decodes to some
+/// instruction, so data is rendered as the instruction that shares its encoding
+pub fn disassemble(origin: u16, words: &[u16]) -> Vec<DisassembledWord> {
+    words
+        .iter()
+        .zip((0..=u16::MAX).map(|offset| origin.wrapping_add(offset)))
+        .filter_map(|(word, address)| {
+            let opcode = Opcode::try_from(*word).ok()?;
+            Some(DisassembledWord {
+                address,
+                word: *word,
+                opcode,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn disassemble_for_loop() {
+        let lines: Vec<String> = disassemble(0x3000, &[0x5020, 0x1021, 0x1236, 0x09FD])
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            vec![
+                "x3000  x5020  AND R0, R0, #0",
+                "x3001  x1021  ADD R0, R0, #1",
+                "x3002  x1236  ADD R1, R0, #-10",
+                "x3003  x09FD  BRn x3001",
+            ],
+            lines
+        );
+
+        let symbols = BTreeMap::from([(String::from("LOOP"), 0x3001)]);
+        let lines: Vec<String> = disassemble(0x3002, &[0x1236, 0x09FD])
+            .iter()
+            .map(|word| word.to_string_with_symbols(&symbols))
+            .collect();
+        assert_eq!(
+            vec![
+                "LOOP+1 (x3002)  x1236  ADD R1, R0, #-10",
+                "LOOP+2 (x3003)  x09FD  BRn LOOP (x3001)",
+            ],
+            lines
+        );
+    }
+}
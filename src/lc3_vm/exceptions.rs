@@ -0,0 +1,40 @@
+use std::fmt;
+
+/// Exceptions raised by the processor, each one has an entry in the interrupt vector table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    PrivilegeViolation,
+    IllegalOpcode,
+    AccessViolation,
+}
+
+impl Exception {
+    /// Address of the interrupt vector table entry holding the handler
+    pub fn vector(self) -> u16 {
+        match self {
+            Exception::PrivilegeViolation => 0x0100,
+            Exception::IllegalOpcode => 0x0101,
+            Exception::AccessViolation => 0x0102,
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exception::PrivilegeViolation => write!(f, "privilege mode violation"),
+            Exception::IllegalOpcode => write!(f, "illegal opcode"),
+            Exception::AccessViolation => write!(f, "access control violation"),
+        }
+    }
+}
+
+/// What the VM does when an exception is raised
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExceptionMode {
+    /// Stop running and report the exception as an error
+    #[default]
+    Stop,
+    /// Jump to the handler in the interrupt vector table like on real hardware
+    Trap,
+}
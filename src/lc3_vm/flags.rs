@@ -1,3 +1,13 @@
+use std::fmt;
+
+/// Privilege mode bit of the processor status register, set in user mode
+pub(super) const PSR_USER: u16 = 0b1000_0000_0000_0000;
+/// Priority level bits of the processor status register
+pub(super) const PSR_PRIORITY: u16 = 0b0000_0111_0000_0000;
+/// Condition code bits of the processor status register
+pub(super) const PSR_CONDITION: u16 = 0b0000_0000_0000_0111;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum ConditionFlags {
     POS,
@@ -8,9 +18,50 @@ pub enum ConditionFlags {
 impl From<ConditionFlags> for u16 {
     fn from(val: ConditionFlags) -> Self {
         match val {
-            ConditionFlags::POS => 0,
-            ConditionFlags::ZRO => 2,
-            ConditionFlags::NEG => 4,
+            ConditionFlags::POS => 0b001,
+            ConditionFlags::ZRO => 0b010,
+            ConditionFlags::NEG => 0b100,
+        }
+    }
+}
+
+impl From<u16> for ConditionFlags {
+    /// Reads the condition codes out of a processor status register value
+    fn from(value: u16) -> Self {
+        if value & u16::from(ConditionFlags::NEG) != 0 {
+            ConditionFlags::NEG
+        } else if value & u16::from(ConditionFlags::ZRO) != 0 {
+            ConditionFlags::ZRO
+        } else {
+            ConditionFlags::POS
+        }
+    }
+}
+
+impl fmt::Display for ConditionFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = match self {
+            ConditionFlags::NEG => "n",
+            ConditionFlags::ZRO => "z",
+            ConditionFlags::POS => "p",
+        };
+        f.write_str(flag)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Privilege {
+    Supervisor,
+    User,
+}
+
+impl From<u16> for Privilege {
+    /// Reads the privilege mode out of a processor status register value
+    fn from(value: u16) -> Self {
+        if value & PSR_USER == 0 {
+            Privilege::Supervisor
+        } else {
+            Privilege::User
         }
     }
 }
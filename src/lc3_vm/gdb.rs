@@ -0,0 +1,451 @@
+use super::virtual_machine::{MemoryAccess, VMError, VM};
+use std::{
+    collections::BTreeSet,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GdbError {
+    #[error("Failed to listen for connections: {0}")]
+    Listen(String),
+    #[error("Connection failure: {0}")]
+    Connection(String),
+}
+
+/// Number of registers exposed to the client: R0 to R7, PC and PSR
+const REGISTER_COUNT: u16 = 10;
+/// Number of the PSR in register packets
+const PSR_REGISTER: u16 = 9;
+/// How many instructions are executed between checks for a client interrupt
+const INTERRUPT_CHECK_INTERVAL: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+struct Watchpoint {
+    kind: WatchKind,
+    start: u16,
+    end: u16,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+/// GDB remote serial protocol server
+///
+/// The LC-3 memory is word addressed, so RSP addresses are word addresses too: `m3000,4` reads
+/// the 4 bytes that make up the words at x3000 and x3001. Words and registers are sent in
+/// little-endian byte order.
+pub struct GdbServer {
+    vm: VM,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+}
+
+impl GdbServer {
+    pub fn new(mut vm: VM) -> Self {
+        vm.running = true;
+        Self {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+        }
+    }
+
+    /// Waits for a single client on localhost and serves it until it detaches, kills the
+    /// program or the program halts. `listening` is called with the bound address before
+    /// waiting, port 0 binds any free port
+    pub fn listen(
+        &mut self,
+        port: u16,
+        listening: impl FnOnce(SocketAddr),
+    ) -> Result<(), GdbError> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|err| GdbError::Listen(err.to_string()))?;
+        let address = listener
+            .local_addr()
+            .map_err(|err| GdbError::Listen(err.to_string()))?;
+        listening(address);
+        let (stream, _) = listener
+            .accept()
+            .map_err(|err| GdbError::Listen(err.to_string()))?;
+        self.serve(stream)
+    }
+
+    pub fn serve(&mut self, stream: TcpStream) -> Result<(), GdbError> {
+        let writer = stream
+            .try_clone()
+            .map_err(|err| GdbError::Connection(err.to_string()))?;
+        let mut connection = Connection {
+            reader: BufReader::new(stream),
+            writer,
+        };
+
+        while let Some(packet) = connection.read_packet()? {
+            let reply = match packet.as_str() {
+                "k" => return Ok(()),
+                "D" => {
+                    connection.send("OK")?;
+                    return Ok(());
+                }
+                _ => self.handle(&packet, &mut connection),
+            };
+            connection.send(&reply)?;
+            if reply.starts_with('W') {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str, connection: &mut Connection) -> String {
+        let mut chars = packet.chars();
+        let Some(command) = chars.next() else {
+            return String::new();
+        };
+        let arguments = chars.as_str();
+
+        match command {
+            '?' => String::from("S05"),
+            'g' => (0..REGISTER_COUNT)
+                .map(|register| {
+                    encode_word(self.vm.get_register_value(register).unwrap_or_default())
+                })
+                .collect(),
+            'G' => {
+                let words = decode_words(arguments);
+                if words.len() < usize::from(REGISTER_COUNT) {
+                    return String::from("E01");
+                }
+                for (register, value) in (0..REGISTER_COUNT).zip(words) {
+                    if self.write_register(register, value).is_err() {
+                        return String::from("E01");
+                    }
+                }
+                String::from("OK")
+            }
+            'p' => match u16::from_str_radix(arguments, 16) {
+                Ok(register) => match self.vm.get_register_value(register) {
+                    Ok(value) => encode_word(value),
+                    Err(_) => String::from("E01"),
+                },
+                Err(_) => String::from("E01"),
+            },
+            'P' => {
+                let Some((register, value)) = arguments.split_once('=') else {
+                    return String::from("E01");
+                };
+                let register = u16::from_str_radix(register, 16).ok();
+                let value = decode_words(value).first().copied();
+                match (register, value) {
+                    (Some(register), Some(value))
+                        if self.write_register(register, value).is_ok() =>
+                    {
+                        String::from("OK")
+                    }
+                    _ => String::from("E01"),
+                }
+            }
+            'm' => match parse_address_length(arguments) {
+                Some((address, length)) => self.read_memory(address, length),
+                None => String::from("E01"),
+            },
+            'M' => {
+                let Some((range, data)) = arguments.split_once(':') else {
+                    return String::from("E01");
+                };
+                match (parse_address_length(range), decode_bytes(data)) {
+                    (Some((address, _)), Some(bytes)) => self.write_memory(address, &bytes),
+                    _ => String::from("E01"),
+                }
+            }
+            's' => self.resume(true, connection),
+            'c' => self.resume(false, connection),
+            'Z' | 'z' => self.update_point(command == 'Z', arguments),
+            'H' => String::from("OK"),
+            'q' => {
+                if arguments.starts_with("Supported") {
+                    String::from("PacketSize=1000")
+                } else if arguments == "Attached" {
+                    String::from("1")
+                } else if arguments == "C" {
+                    String::from("QC1")
+                } else if arguments == "fThreadInfo" {
+                    String::from("m1")
+                } else if arguments == "sThreadInfo" {
+                    String::from("l")
+                } else {
+                    String::new()
+                }
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Runs one instruction, or until a breakpoint or watchpoint is hit, and returns the stop
+    /// reply
+    fn resume(&mut self, single_step: bool, connection: &mut Connection) -> String {
+        let mut executed: u32 = 0;
+        loop {
+            if !self.vm.running {
+                return String::from("W00");
+            }
+            if self.vm.next_instruction().is_err() {
+                // Report faulting instructions as SIGILL
+                return String::from("S04");
+            }
+            if !self.vm.running {
+                return String::from("W00");
+            }
+            if let Some(reply) = self.watchpoint_hit() {
+                return reply;
+            }
+            if single_step || self.breakpoints.contains(&self.pc()) {
+                return String::from("S05");
+            }
+            executed = executed.wrapping_add(1);
+            if executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && connection.interrupted() {
+                return String::from("S02");
+            }
+        }
+    }
+
+    fn watchpoint_hit(&self) -> Option<String> {
+        self.vm.last_accesses().iter().find_map(|access| {
+            let (address, write) = match access {
+                MemoryAccess::Read { address, .. } => (*address, false),
+                MemoryAccess::Write { address, .. } => (*address, true),
+            };
+            let watchpoint = self.watchpoints.iter().find(|watchpoint| {
+                address >= watchpoint.start
+                    && address < watchpoint.end
+                    && match watchpoint.kind {
+                        WatchKind::Write => write,
+                        WatchKind::Read => !write,
+                        WatchKind::Access => true,
+                    }
+            })?;
+            let name = match watchpoint.kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            Some(format!("T05{name}:{address:x};"))
+        })
+    }
+
+    fn update_point(&mut self, insert: bool, arguments: &str) -> String {
+        let mut fields = arguments.split(',');
+        let kind = fields.next();
+        let address = fields.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+        let length = fields.next().and_then(|l| u16::from_str_radix(l, 16).ok());
+        let (Some(kind), Some(address), Some(length)) = (kind, address, length) else {
+            return String::from("E01");
+        };
+
+        let watch_kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return String::from("OK");
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+
+        // Lengths are in bytes, round them up to whole words
+        let words = length.div_ceil(2).max(1);
+        let end = address.saturating_add(words);
+        if insert {
+            self.watchpoints.push(Watchpoint {
+                kind: watch_kind,
+                start: address,
+                end,
+            });
+        } else {
+            self.watchpoints.retain(|watchpoint| {
+                !(watchpoint.kind == watch_kind
+                    && watchpoint.start == address
+                    && watchpoint.end == end)
+            });
+        }
+        String::from("OK")
+    }
+
+    fn read_memory(&self, address: u16, length: u16) -> String {
+        (0..length)
+            .map(|offset| {
+                let word = self.vm.peek_word(address.wrapping_add(offset >> 1));
+                let [low, high] = word.to_le_bytes();
+                let byte = if offset & 1 == 0 { low } else { high };
+                format!("{byte:02x}")
+            })
+            .collect()
+    }
+
+    fn write_memory(&mut self, address: u16, bytes: &[u8]) -> String {
+        for (offset, chunk) in (0..=u16::MAX).zip(bytes.chunks(2)) {
+            let word_address = address.wrapping_add(offset);
+            let [low, high] = self.vm.peek_word(word_address).to_le_bytes();
+            let low = chunk.first().copied().unwrap_or(low);
+            let high = chunk.get(1).copied().unwrap_or(high);
+            if self
+                .vm
+                .poke_word(word_address, u16::from_le_bytes([low, high]))
+                .is_err()
+            {
+                return String::from("E01");
+            }
+        }
+        String::from("OK")
+    }
+
+    /// Writes a register, the PSR goes through `VM::set_psr` so changing the privilege mode
+    /// swaps the stack pointers
+    fn write_register(&mut self, register: u16, value: u16) -> Result<(), VMError> {
+        if register == PSR_REGISTER {
+            self.vm.set_psr(value);
+            return Ok(());
+        }
+        self.vm.update_register(register, value)
+    }
+
+    fn pc(&self) -> u16 {
+        self.vm.pc()
+    }
+}
+
+impl Connection {
+    /// Reads the next packet and acknowledges it, packets with a bad checksum are rejected
+    /// until the client sends a valid one. An interrupt request outside of a packet is ignored,
+    /// the end of the stream is returned as None
+    fn read_packet(&mut self) -> Result<Option<String>, GdbError> {
+        loop {
+            loop {
+                let mut start = [0; 1];
+                match self.read_byte(&mut start)? {
+                    false => return Ok(None),
+                    true if start == [b'$'] => break,
+                    // Interrupts while the program is stopped are ignored, acks need no answer
+                    true => continue,
+                }
+            }
+
+            let mut data = Vec::new();
+            self.reader
+                .read_until(b'#', &mut data)
+                .map_err(|err| GdbError::Connection(err.to_string()))?;
+            if data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            for byte in checksum.iter_mut() {
+                let mut buffer = [0; 1];
+                if !self.read_byte(&mut buffer)? {
+                    return Ok(None);
+                }
+                *byte = buffer.first().copied().unwrap_or_default();
+            }
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected == Some(compute_checksum(&data)) {
+                self.write(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.write(b"-")?;
+        }
+    }
+
+    fn read_byte(&mut self, buffer: &mut [u8; 1]) -> Result<bool, GdbError> {
+        match std::io::Read::read(&mut self.reader, buffer) {
+            Ok(0) => Ok(false),
+            Ok(_) => Ok(true),
+            Err(err) => Err(GdbError::Connection(err.to_string())),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<(), GdbError> {
+        let packet = format!("${}#{:02x}", data, compute_checksum(data.as_bytes()));
+        self.write(packet.as_bytes())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), GdbError> {
+        self.writer
+            .write_all(bytes)
+            .and_then(|_| self.writer.flush())
+            .map_err(|err| GdbError::Connection(err.to_string()))
+    }
+
+    /// Checks without blocking whether the client sent an interrupt request (Ctrl-C)
+    fn interrupted(&mut self) -> bool {
+        if !self.reader.buffer().is_empty() {
+            return self.consume_interrupt();
+        }
+        if self.reader.get_ref().set_nonblocking(true).is_err() {
+            return false;
+        }
+        let available = matches!(self.reader.fill_buf(), Ok(buffer) if !buffer.is_empty());
+        if self.reader.get_ref().set_nonblocking(false).is_err() {
+            return false;
+        }
+        available && self.consume_interrupt()
+    }
+
+    fn consume_interrupt(&mut self) -> bool {
+        if self.reader.buffer().first() == Some(&0x03) {
+            self.reader.consume(1);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn compute_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_address_length(arguments: &str) -> Option<(u16, u16)> {
+    let (address, length) = arguments.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn encode_word(word: u16) -> String {
+    word.to_le_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn decode_bytes(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+fn decode_words(hex: &str) -> Vec<u16> {
+    decode_bytes(hex)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .filter_map(|pair| Some(u16::from_le_bytes([*pair.first()?, *pair.get(1)?])))
+        .collect()
+}
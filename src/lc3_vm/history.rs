@@ -0,0 +1,193 @@
+use super::snapshot::Snapshot;
+use std::collections::VecDeque;
+
+/// Number of instructions kept by `History::default`
+pub const DEFAULT_HISTORY_CAPACITY: usize = 100_000;
+/// Number of instructions between two checkpoints of `History::default`
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 10_000;
+
+/// Why `VM::reverse_continue` stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReverseStop {
+    /// The PC reached a breakpoint, the instruction at it is the next one to execute
+    Breakpoint(u16),
+    /// The instruction that writes the watched address is the next one to execute
+    Watchpoint(u16),
+    /// Every recorded instruction was undone
+    Start,
+}
+
+/// What one executed instruction changed, enough to put the machine back as it was before it
+pub(super) struct Undo {
+    pub(super) registers: [u16; 8],
+    pub(super) pc: u16,
+    pub(super) psr: u16,
+    pub(super) saved_ssp: u16,
+    pub(super) saved_usp: u16,
+    pub(super) running: bool,
+    /// Address and previous value of every memory word written, in the order of the writes
+    pub(super) memory: Vec<(u16, u16)>,
+    /// Bus index and previous state of every device the instruction accessed or ticked while
+    /// it was active
+    pub(super) devices: Vec<(usize, Vec<u16>)>,
+}
+
+/// Undo log of the last executed instructions, so the machine can run backwards. Attached with
+/// `VM::set_history`. The log is a ring buffer that drops the oldest instructions once it is
+/// full, and a snapshot is kept every few instructions so that long jumps back restore one
+/// instead of undoing every instruction. Both are bounded, which keeps memory use predictable.
+///
+/// Output can't be taken back, and input read again after stepping back comes from the input
+/// device, unless the keyboard already had it latched
+pub struct History {
+    capacity: usize,
+    entries: VecDeque<Undo>,
+    /// Entry being filled by the instruction that is executing
+    current: Option<Undo>,
+    checkpoint_interval: u64,
+    /// Snapshots taken before the instruction with the given step number executed
+    checkpoints: VecDeque<(u64, Snapshot)>,
+    /// Step number of the next instruction, steps count recorded instructions minus undone ones
+    step: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_CAPACITY)
+    }
+}
+
+impl History {
+    /// Keeps up to `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::new(),
+            current: None,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            checkpoints: VecDeque::new(),
+            step: 0,
+        }
+    }
+
+    /// Takes a snapshot every `interval` instructions, 0 takes none
+    pub fn set_checkpoint_interval(&mut self, interval: u64) {
+        self.checkpoint_interval = interval;
+        self.checkpoints.clear();
+    }
+
+    /// Number of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Step number of the next instruction. It goes down when instructions are undone
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// Whether a checkpoint is due before the next instruction
+    pub(super) fn checkpoint_due(&self) -> bool {
+        self.capacity > 0
+            && self.checkpoint_interval > 0
+            && self.step.is_multiple_of(self.checkpoint_interval)
+            && self
+                .checkpoints
+                .back()
+                .is_none_or(|(step, _)| *step < self.step)
+    }
+
+    pub(super) fn checkpoint(&mut self, snapshot: Snapshot) {
+        self.checkpoints.push_back((self.step, snapshot));
+    }
+
+    pub(super) fn begin(&mut self, undo: Undo) {
+        self.current = Some(undo);
+    }
+
+    /// Records a memory write of the instruction that is executing
+    pub(super) fn written(&mut self, address: u16, previous: u16) {
+        if let Some(current) = self.current.as_mut() {
+            current.memory.push((address, previous));
+        }
+    }
+
+    /// Records the state of a device before the instruction that is executing changes it. Only
+    /// the first state is kept, so `save` is called once per device and instruction
+    pub(super) fn device_changing(&mut self, index: usize, save: impl FnOnce() -> Vec<u16>) {
+        if let Some(current) = self.current.as_mut() {
+            if current.devices.iter().all(|(saved, _)| *saved != index) {
+                current.devices.push((index, save()));
+            }
+        }
+    }
+
+    /// Ends the entry of the instruction that executed, dropping the oldest one when the log is
+    /// full, together with the checkpoints that can't be reached anymore
+    pub(super) fn finish(&mut self) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(current);
+        self.step = self.step.wrapping_add(1);
+        let oldest = self.oldest();
+        while self
+            .checkpoints
+            .front()
+            .is_some_and(|(step, _)| *step < oldest)
+        {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    /// Takes the entry of the last instruction out of the log
+    pub(super) fn pop(&mut self) -> Option<Undo> {
+        let undo = self.entries.pop_back()?;
+        self.step = self.step.wrapping_sub(1);
+        while self
+            .checkpoints
+            .back()
+            .is_some_and(|(step, _)| *step > self.step)
+        {
+            self.checkpoints.pop_back();
+        }
+        Some(undo)
+    }
+
+    /// Removes the closest checkpoint that is at most `count` instructions back, and the
+    /// entries of the instructions that ran after it. Returns the snapshot and the number of
+    /// instructions it goes back
+    pub(super) fn rewind(&mut self, count: u64) -> Option<(Snapshot, u64)> {
+        let target = self.step.saturating_sub(count);
+        let index = self
+            .checkpoints
+            .iter()
+            .position(|(step, _)| *step >= target && *step < self.step)?;
+        let (step, snapshot) = self.checkpoints.remove(index)?;
+        self.checkpoints.truncate(index);
+        let skipped = self.step.wrapping_sub(step);
+        let kept = self
+            .entries
+            .len()
+            .saturating_sub(usize::try_from(skipped).unwrap_or(usize::MAX));
+        self.entries.truncate(kept);
+        self.step = step;
+        Some((snapshot, skipped))
+    }
+
+    /// Step number of the oldest instruction that can be undone
+    fn oldest(&self) -> u64 {
+        self.step
+            .saturating_sub(u64::try_from(self.entries.len()).unwrap_or(u64::MAX))
+    }
+}
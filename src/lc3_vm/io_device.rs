@@ -0,0 +1,155 @@
+use nix::{
+    sys::{
+        select,
+        time::{TimeVal, TimeValLike},
+    },
+    unistd,
+};
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    os::fd::{AsFd, AsRawFd},
+    path::Path,
+    rc::Rc,
+};
+
+/// Keyboard and console used by the VM for traps and memory mapped keyboard registers
+pub trait IoDevice {
+    /// Returns true when a key press is waiting to be read, without blocking
+    fn key_available(&mut self) -> io::Result<bool>;
+    /// Blocks until a key is pressed and returns it
+    fn read_key(&mut self) -> io::Result<u8>;
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    /// Called before every instruction with its number, counted from 0 since the machine was
+    /// created, for devices that record or replay input
+    fn clock(&mut self, _instruction: u64) {}
+}
+
+/// Reads keys from stdin and writes to stdout
+#[derive(Default)]
+pub struct TerminalIo;
+
+impl IoDevice for TerminalIo {
+    fn key_available(&mut self) -> io::Result<bool> {
+        let stdin = io::stdin();
+        let mut fd = select::FdSet::new();
+        fd.insert(stdin.as_fd());
+        let mut timeout = TimeVal::seconds(0);
+        let ready = select::select(None, &mut fd, None, None, &mut timeout)?;
+        Ok(ready > 0 && fd.contains(stdin.as_fd()))
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        // Read straight from the file descriptor, bytes left in the buffer of io::Stdin
+        // wouldn't be seen by key_available
+        let mut buffer = [0; 1];
+        match unistd::read(io::stdin().as_raw_fd(), &mut buffer)? {
+            0 => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            _ => Ok(u8::from_be_bytes(buffer)),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        io::stdout().write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+/// Reads keys from any reader and writes to any writer. Keys are available as long as the
+/// reader has bytes left
+pub struct StreamIo<R, W> {
+    input: R,
+    output: W,
+    pending: Option<u8>,
+}
+
+impl<R: Read, W: Write> StreamIo<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            pending: None,
+        }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+}
+
+impl<R: Read, W: Write> IoDevice for StreamIo<R, W> {
+    fn key_available(&mut self) -> io::Result<bool> {
+        if self.pending.is_none() {
+            let mut buffer = [0; 1];
+            if self.input.read(&mut buffer)? == 1 {
+                self.pending = Some(u8::from_be_bytes(buffer));
+            }
+        }
+        Ok(self.pending.is_some())
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        if let Some(key) = self.pending.take() {
+            return Ok(key);
+        }
+        let mut buffer = [0; 1];
+        self.input.read_exact(&mut buffer)?;
+        Ok(u8::from_be_bytes(buffer))
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.write_all(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Output buffer that stays readable after the device is moved into the VM
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Scripted keyboard input and captured console output, kept in memory
+pub type BufferIo = StreamIo<VecDeque<u8>, SharedBuffer>;
+
+impl BufferIo {
+    pub fn with_input(input: impl Into<VecDeque<u8>>) -> Self {
+        Self::new(input.into(), SharedBuffer::default())
+    }
+}
+
+/// Keyboard input read from a file and console output written to another one
+pub type FileIo = StreamIo<BufReader<File>, BufWriter<File>>;
+
+impl FileIo {
+    pub fn open(input: &Path, output: &Path) -> io::Result<Self> {
+        Ok(Self::new(
+            BufReader::new(File::open(input)?),
+            BufWriter::new(File::create(output)?),
+        ))
+    }
+}
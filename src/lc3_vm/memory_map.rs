@@ -0,0 +1,102 @@
+use std::{fmt::Write as _, ops::Range};
+
+/// Block of memory filled by one loaded object file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// File name of the object file, or `OS` for the bundled image
+    pub name: String,
+    pub range: Range<u16>,
+}
+
+impl Segment {
+    fn overlaps(&self, range: &Range<u16>) -> bool {
+        self.range.start < range.end && range.start < self.range.end
+    }
+
+    /// Renders the addresses of the segment, like `x3000-x3011`
+    fn addresses(&self) -> String {
+        let last = self.range.end.saturating_sub(1);
+        if last > self.range.start {
+            format!("x{:04X}-x{last:04X}", self.range.start)
+        } else {
+            format!("x{:04X}", self.range.start)
+        }
+    }
+}
+
+/// Segments loaded into the memory of a machine, sorted by address. Loading a segment over
+/// another one is refused, so object files linked into one run can't overwrite each other
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    segments: Vec<Segment>,
+}
+
+impl MemoryMap {
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Adds a segment, or returns an error naming the segment it overlaps
+    pub(super) fn insert(&mut self, segment: Segment) -> Result<(), String> {
+        if let Some(other) = self
+            .segments
+            .iter()
+            .find(|other| other.overlaps(&segment.range))
+        {
+            return Err(format!(
+                "{} at {} overlaps {} at {}",
+                segment.name,
+                segment.addresses(),
+                other.name,
+                other.addresses()
+            ));
+        }
+        let index = self
+            .segments
+            .partition_point(|other| other.range.start <= segment.range.start);
+        self.segments.insert(index, segment);
+        Ok(())
+    }
+
+    /// Lists every segment with its addresses and size
+    pub fn summary(&self) -> String {
+        let mut summary = String::from("Memory map\n");
+        for segment in &self.segments {
+            let _ = writeln!(
+                summary,
+                "  {:<11}  {:>5} words  {}",
+                segment.addresses(),
+                segment.range.len(),
+                segment.name
+            );
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overlapping_segments_are_refused() {
+        let segment = |name: &str, range: Range<u16>| Segment {
+            name: name.to_string(),
+            range,
+        };
+        let mut map = MemoryMap::default();
+        assert_eq!(Ok(()), map.insert(segment("main.obj", 0x3000..0x3012)));
+        assert_eq!(Ok(()), map.insert(segment("OS", 0x0000..0x0200)));
+        assert_eq!(Ok(()), map.insert(segment("lib.obj", 0x3012..0x3020)));
+        assert_eq!(
+            Err(String::from(
+                "extra.obj at x3010-x3013 overlaps main.obj at x3000-x3011"
+            )),
+            map.insert(segment("extra.obj", 0x3010..0x3014))
+        );
+        assert_eq!(
+            "Memory map\n  x0000-x01FF    512 words  OS\n  x3000-x3011     18 words  main.obj\n  x3012-x301F     14 words  lib.obj\n",
+            map.summary()
+        );
+    }
+}
@@ -1,4 +1,22 @@
-mod flags;
-mod opcodes;
-mod traps;
-pub mod virtual_machine;
+pub(crate) mod assembler;
+pub(crate) mod block_storage;
+pub(crate) mod coverage;
+pub(crate) mod debugger;
+pub(crate) mod devices;
+pub(crate) mod disassembler;
+pub(crate) mod exceptions;
+pub(crate) mod flags;
+pub(crate) mod gdb;
+pub(crate) mod history;
+pub(crate) mod io_device;
+pub(crate) mod memory_map;
+pub(crate) mod opcodes;
+pub(crate) mod os;
+pub(crate) mod profiler;
+pub(crate) mod registers;
+pub(crate) mod replay;
+pub(crate) mod snapshot;
+pub(crate) mod symbols;
+pub(crate) mod trace;
+pub(crate) mod traps;
+pub(crate) mod virtual_machine;
@@ -1,3 +1,11 @@
+use super::{
+    symbols::describe,
+    traps::Trap,
+    virtual_machine::{
+        sign_extend_11_bits, sign_extend_5_bits, sign_extend_6_bits, sign_extend_9_bits,
+    },
+};
+use std::{collections::BTreeMap, fmt};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -217,6 +225,215 @@ impl TryFrom<u16> for Opcode {
     type Error = OpcodeError;
 }
 
+impl From<Opcode> for u16 {
+    fn from(opcode: Opcode) -> Self {
+        match opcode {
+            Opcode::BR { n, z, p, offset } => {
+                (u16::from(n) << 11)
+                    | (u16::from(z) << 10)
+                    | (u16::from(p) << 9)
+                    | (offset & 0b0000_0001_1111_1111)
+            }
+            Opcode::ADD { dr, sr1, mode, sr2 } => {
+                (1 << 12) | encode_alu_operands(dr, sr1, mode, sr2)
+            }
+            Opcode::LD { dr, offset } => {
+                (2 << 12) | encode_register(dr, 9) | (offset & 0b0000_0001_1111_1111)
+            }
+            Opcode::ST { sr, offset } => {
+                (3 << 12) | encode_register(sr, 9) | (offset & 0b0000_0001_1111_1111)
+            }
+            Opcode::JSR { mode, offset } => {
+                (4 << 12) | (u16::from(mode) << 11) | (offset & 0b0000_0111_1111_1111)
+            }
+            Opcode::AND { dr, sr1, mode, sr2 } => {
+                (5 << 12) | encode_alu_operands(dr, sr1, mode, sr2)
+            }
+            Opcode::LDR { dr, base_r, offset } => {
+                (6 << 12)
+                    | encode_register(dr, 9)
+                    | encode_register(base_r, 6)
+                    | (u16::from(offset) & 0b0000_0000_0011_1111)
+            }
+            Opcode::STR { sr, base_r, offset } => {
+                (7 << 12)
+                    | encode_register(sr, 9)
+                    | encode_register(base_r, 6)
+                    | (u16::from(offset) & 0b0000_0000_0011_1111)
+            }
+            Opcode::RTI {} => 8 << 12,
+            // The unused bits of NOT are all set
+            Opcode::NOT { dr, sr } => {
+                (9 << 12) | encode_register(dr, 9) | encode_register(sr, 6) | 0b0000_0000_0011_1111
+            }
+            Opcode::LDI { dr, offset } => {
+                (10 << 12) | encode_register(dr, 9) | (offset & 0b0000_0001_1111_1111)
+            }
+            Opcode::STI { sr, offset } => {
+                (11 << 12) | encode_register(sr, 9) | (offset & 0b0000_0001_1111_1111)
+            }
+            Opcode::JMP { base_r } => (12 << 12) | encode_register(base_r, 6),
+            Opcode::RES {} => 13 << 12,
+            Opcode::LEA { dr, offset } => {
+                (14 << 12) | encode_register(dr, 9) | (offset & 0b0000_0001_1111_1111)
+            }
+            Opcode::TRAP { trap_vec } => (15 << 12) | u16::from(trap_vec),
+        }
+    }
+}
+
+impl Opcode {
+    /// Mnemonic of the opcode, without operands or variants like JSRR and RET
+    pub fn name(&self) -> &'static str {
+        match self {
+            Opcode::BR { .. } => "BR",
+            Opcode::ADD { .. } => "ADD",
+            Opcode::LD { .. } => "LD",
+            Opcode::ST { .. } => "ST",
+            Opcode::JSR { .. } => "JSR",
+            Opcode::AND { .. } => "AND",
+            Opcode::LDR { .. } => "LDR",
+            Opcode::STR { .. } => "STR",
+            Opcode::RTI {} => "RTI",
+            Opcode::NOT { .. } => "NOT",
+            Opcode::LDI { .. } => "LDI",
+            Opcode::STI { .. } => "STI",
+            Opcode::JMP { .. } => "JMP",
+            Opcode::RES {} => "RES",
+            Opcode::LEA { .. } => "LEA",
+            Opcode::TRAP { .. } => "TRAP",
+        }
+    }
+
+    /// Renders the instruction as LC-3 assembly. When the address of the instruction is known
+    /// PC-relative operands are resolved into the absolute address they point to, otherwise
+    /// they are rendered as signed offsets
+    pub fn to_assembly(&self, address: Option<u16>) -> String {
+        self.render(&|offset: u16| match address {
+            Some(address) => format!("x{:04X}", address.wrapping_add(1).wrapping_add(offset)),
+            None => format!("#{}", signed(offset)),
+        })
+    }
+
+    /// Renders the instruction at the address as LC-3 assembly, with PC-relative operands
+    /// resolved into the labels they point to, like `BRn LOOP (x3001)`
+    pub fn to_assembly_with_symbols(
+        &self,
+        address: u16,
+        symbols: &BTreeMap<String, u16>,
+    ) -> String {
+        self.render(&|offset: u16| describe(symbols, address.wrapping_add(1).wrapping_add(offset)))
+    }
+
+    /// Renders the instruction with PC-relative operands formatted by `pc_relative`
+    fn render(&self, pc_relative: &dyn Fn(u16) -> String) -> String {
+        match self {
+            Opcode::BR { n, z, p, offset } => {
+                if !(n | z | p) {
+                    return String::from("NOP");
+                }
+                let flags: String = [(n, 'n'), (z, 'z'), (p, 'p')]
+                    .iter()
+                    .filter(|(set, _)| **set)
+                    .map(|(_, flag)| flag)
+                    .collect();
+                format!("BR{} {}", flags, pc_relative(sign_extend_9_bits(*offset)))
+            }
+            Opcode::ADD { dr, sr1, mode, sr2 } => {
+                format!("ADD {}", alu_operands(*dr, *sr1, *mode, *sr2))
+            }
+            Opcode::LD { dr, offset } => {
+                format!("LD R{}, {}", dr, pc_relative(sign_extend_9_bits(*offset)))
+            }
+            Opcode::ST { sr, offset } => {
+                format!("ST R{}, {}", sr, pc_relative(sign_extend_9_bits(*offset)))
+            }
+            Opcode::JSR { mode, offset } => {
+                if *mode {
+                    format!("JSR {}", pc_relative(sign_extend_11_bits(*offset)))
+                } else {
+                    // The base register is stored in bits 8..6 of the offset
+                    format!("JSRR R{}", (offset >> 6) & 0b111)
+                }
+            }
+            Opcode::AND { dr, sr1, mode, sr2 } => {
+                format!("AND {}", alu_operands(*dr, *sr1, *mode, *sr2))
+            }
+            Opcode::LDR { dr, base_r, offset } => format!(
+                "LDR R{}, R{}, #{}",
+                dr,
+                base_r,
+                signed(sign_extend_6_bits(*offset))
+            ),
+            Opcode::STR { sr, base_r, offset } => format!(
+                "STR R{}, R{}, #{}",
+                sr,
+                base_r,
+                signed(sign_extend_6_bits(*offset))
+            ),
+            Opcode::RTI {} => String::from("RTI"),
+            Opcode::NOT { dr, sr } => format!("NOT R{}, R{}", dr, sr),
+            Opcode::LDI { dr, offset } => {
+                format!("LDI R{}, {}", dr, pc_relative(sign_extend_9_bits(*offset)))
+            }
+            Opcode::STI { sr, offset } => {
+                format!("STI R{}, {}", sr, pc_relative(sign_extend_9_bits(*offset)))
+            }
+            Opcode::JMP { base_r: 7 } => String::from("RET"),
+            Opcode::JMP { base_r } => format!("JMP R{}", base_r),
+            Opcode::RES {} => String::from("RES"),
+            Opcode::LEA { dr, offset } => {
+                format!("LEA R{}, {}", dr, pc_relative(sign_extend_9_bits(*offset)))
+            }
+            Opcode::TRAP { trap_vec } => match Trap::try_from(*trap_vec) {
+                Ok(Trap::GetC) => String::from("GETC"),
+                Ok(Trap::Out) => String::from("OUT"),
+                Ok(Trap::Puts) => String::from("PUTS"),
+                Ok(Trap::In) => String::from("IN"),
+                Ok(Trap::Putsp) => String::from("PUTSP"),
+                Ok(Trap::Halt) => String::from("HALT"),
+                Ok(Trap::ReadSector) => String::from("RDSEC"),
+                Ok(Trap::WriteSector) => String::from("WRSEC"),
+                Ok(Trap::SetVector) => String::from("SETVEC"),
+                Err(_) => format!("TRAP x{:02X}", trap_vec),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_assembly(None))
+    }
+}
+
+fn alu_operands(dr: u8, sr1: u8, mode: bool, sr2: u8) -> String {
+    if mode {
+        format!("R{}, R{}, #{}", dr, sr1, signed(sign_extend_5_bits(sr2)))
+    } else {
+        format!("R{}, R{}, R{}", dr, sr1, sr2 & 0b111)
+    }
+}
+
+/// Reinterprets a sign extended value as a two's complement number
+fn signed(value: u16) -> i16 {
+    i16::from_be_bytes(value.to_be_bytes())
+}
+
+fn encode_register(register: u8, shift: u16) -> u16 {
+    u16::from(register & 0b0000_0111) << shift
+}
+
+fn encode_alu_operands(dr: u8, sr1: u8, mode: bool, sr2: u8) -> u16 {
+    let sr2 = if mode {
+        // imm mode
+        u16::from(sr2 & 0b0001_1111)
+    } else {
+        u16::from(sr2 & 0b0000_0111)
+    };
+    encode_register(dr, 9) | encode_register(sr1, 6) | (u16::from(mode) << 5) | sr2
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -312,4 +529,45 @@ mod test {
         assert_eq!(and, Opcode::try_from(instruction)?);
         Ok(())
     }
+
+    #[test]
+    fn encode_decode_roundtrip() -> Result<(), OpcodeError> {
+        let instructions: [u16; 8] = [
+            0b_0000_0010_0000_0100,
+            0b_0001_0010_1000_0011,
+            0b_0001_0010_1010_1011,
+            0b_0100_1111_1111_1111,
+            0b_0100_0000_1100_0000,
+            0b_1001_0110_1011_1111,
+            0b_1100_0001_1100_0000,
+            0b_1111_0000_0010_0101,
+        ];
+        for instruction in instructions {
+            assert_eq!(instruction, u16::from(Opcode::try_from(instruction)?));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn display_signed_offsets() -> Result<(), OpcodeError> {
+        assert_eq!("BRnp #-3", Opcode::try_from(0x0BFD)?.to_string());
+        assert_eq!("ADD R1, R0, #-10", Opcode::try_from(0x1236)?.to_string());
+        assert_eq!("LDR R1, R6, #-1", Opcode::try_from(0x63BF)?.to_string());
+        assert_eq!("JSRR R3", Opcode::try_from(0x40C0)?.to_string());
+        assert_eq!("RET", Opcode::try_from(0xC1C0)?.to_string());
+        assert_eq!("RDSEC", Opcode::try_from(0xF026)?.to_string());
+        assert_eq!("SETVEC", Opcode::try_from(0xF028)?.to_string());
+        assert_eq!("TRAP x30", Opcode::try_from(0xF030)?.to_string());
+        assert_eq!("PUTS", Opcode::try_from(0xF022)?.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn display_resolved_targets() -> Result<(), OpcodeError> {
+        let br = Opcode::try_from(0x0BFD)?;
+        assert_eq!("BRnp x3000", br.to_assembly(Some(0x3002)));
+        let lea = Opcode::try_from(0xE002)?;
+        assert_eq!("LEA R0, x4003", lea.to_assembly(Some(0x4000)));
+        Ok(())
+    }
 }
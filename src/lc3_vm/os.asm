@@ -0,0 +1,853 @@
+; LC-3 operating system image: trap and interrupt vector tables, trap service routines and
+; exception handlers. Loaded with VM::load_os, after which TRAP enters supervisor mode and jumps
+; through the vector table like on real hardware, service routines return with RTI
+        .ORIG x0000
+
+; Trap vector table, x0000 to x00FF
+        .FILL BAD_TRAP    ; x00
+        .FILL BAD_TRAP    ; x01
+        .FILL BAD_TRAP    ; x02
+        .FILL BAD_TRAP    ; x03
+        .FILL BAD_TRAP    ; x04
+        .FILL BAD_TRAP    ; x05
+        .FILL BAD_TRAP    ; x06
+        .FILL BAD_TRAP    ; x07
+        .FILL BAD_TRAP    ; x08
+        .FILL BAD_TRAP    ; x09
+        .FILL BAD_TRAP    ; x0A
+        .FILL BAD_TRAP    ; x0B
+        .FILL BAD_TRAP    ; x0C
+        .FILL BAD_TRAP    ; x0D
+        .FILL BAD_TRAP    ; x0E
+        .FILL BAD_TRAP    ; x0F
+        .FILL BAD_TRAP    ; x10
+        .FILL BAD_TRAP    ; x11
+        .FILL BAD_TRAP    ; x12
+        .FILL BAD_TRAP    ; x13
+        .FILL BAD_TRAP    ; x14
+        .FILL BAD_TRAP    ; x15
+        .FILL BAD_TRAP    ; x16
+        .FILL BAD_TRAP    ; x17
+        .FILL BAD_TRAP    ; x18
+        .FILL BAD_TRAP    ; x19
+        .FILL BAD_TRAP    ; x1A
+        .FILL BAD_TRAP    ; x1B
+        .FILL BAD_TRAP    ; x1C
+        .FILL BAD_TRAP    ; x1D
+        .FILL BAD_TRAP    ; x1E
+        .FILL BAD_TRAP    ; x1F
+        .FILL TRAP_GETC   ; x20
+        .FILL TRAP_OUT    ; x21
+        .FILL TRAP_PUTS   ; x22
+        .FILL TRAP_IN     ; x23
+        .FILL TRAP_PUTSP  ; x24
+        .FILL TRAP_HALT   ; x25
+        .FILL TRAP_RDSEC  ; x26
+        .FILL TRAP_WRSEC  ; x27
+        .FILL TRAP_SETVEC ; x28
+        .FILL BAD_TRAP    ; x29
+        .FILL BAD_TRAP    ; x2A
+        .FILL BAD_TRAP    ; x2B
+        .FILL BAD_TRAP    ; x2C
+        .FILL BAD_TRAP    ; x2D
+        .FILL BAD_TRAP    ; x2E
+        .FILL BAD_TRAP    ; x2F
+        .FILL BAD_TRAP    ; x30
+        .FILL BAD_TRAP    ; x31
+        .FILL BAD_TRAP    ; x32
+        .FILL BAD_TRAP    ; x33
+        .FILL BAD_TRAP    ; x34
+        .FILL BAD_TRAP    ; x35
+        .FILL BAD_TRAP    ; x36
+        .FILL BAD_TRAP    ; x37
+        .FILL BAD_TRAP    ; x38
+        .FILL BAD_TRAP    ; x39
+        .FILL BAD_TRAP    ; x3A
+        .FILL BAD_TRAP    ; x3B
+        .FILL BAD_TRAP    ; x3C
+        .FILL BAD_TRAP    ; x3D
+        .FILL BAD_TRAP    ; x3E
+        .FILL BAD_TRAP    ; x3F
+        .FILL BAD_TRAP    ; x40
+        .FILL BAD_TRAP    ; x41
+        .FILL BAD_TRAP    ; x42
+        .FILL BAD_TRAP    ; x43
+        .FILL BAD_TRAP    ; x44
+        .FILL BAD_TRAP    ; x45
+        .FILL BAD_TRAP    ; x46
+        .FILL BAD_TRAP    ; x47
+        .FILL BAD_TRAP    ; x48
+        .FILL BAD_TRAP    ; x49
+        .FILL BAD_TRAP    ; x4A
+        .FILL BAD_TRAP    ; x4B
+        .FILL BAD_TRAP    ; x4C
+        .FILL BAD_TRAP    ; x4D
+        .FILL BAD_TRAP    ; x4E
+        .FILL BAD_TRAP    ; x4F
+        .FILL BAD_TRAP    ; x50
+        .FILL BAD_TRAP    ; x51
+        .FILL BAD_TRAP    ; x52
+        .FILL BAD_TRAP    ; x53
+        .FILL BAD_TRAP    ; x54
+        .FILL BAD_TRAP    ; x55
+        .FILL BAD_TRAP    ; x56
+        .FILL BAD_TRAP    ; x57
+        .FILL BAD_TRAP    ; x58
+        .FILL BAD_TRAP    ; x59
+        .FILL BAD_TRAP    ; x5A
+        .FILL BAD_TRAP    ; x5B
+        .FILL BAD_TRAP    ; x5C
+        .FILL BAD_TRAP    ; x5D
+        .FILL BAD_TRAP    ; x5E
+        .FILL BAD_TRAP    ; x5F
+        .FILL BAD_TRAP    ; x60
+        .FILL BAD_TRAP    ; x61
+        .FILL BAD_TRAP    ; x62
+        .FILL BAD_TRAP    ; x63
+        .FILL BAD_TRAP    ; x64
+        .FILL BAD_TRAP    ; x65
+        .FILL BAD_TRAP    ; x66
+        .FILL BAD_TRAP    ; x67
+        .FILL BAD_TRAP    ; x68
+        .FILL BAD_TRAP    ; x69
+        .FILL BAD_TRAP    ; x6A
+        .FILL BAD_TRAP    ; x6B
+        .FILL BAD_TRAP    ; x6C
+        .FILL BAD_TRAP    ; x6D
+        .FILL BAD_TRAP    ; x6E
+        .FILL BAD_TRAP    ; x6F
+        .FILL BAD_TRAP    ; x70
+        .FILL BAD_TRAP    ; x71
+        .FILL BAD_TRAP    ; x72
+        .FILL BAD_TRAP    ; x73
+        .FILL BAD_TRAP    ; x74
+        .FILL BAD_TRAP    ; x75
+        .FILL BAD_TRAP    ; x76
+        .FILL BAD_TRAP    ; x77
+        .FILL BAD_TRAP    ; x78
+        .FILL BAD_TRAP    ; x79
+        .FILL BAD_TRAP    ; x7A
+        .FILL BAD_TRAP    ; x7B
+        .FILL BAD_TRAP    ; x7C
+        .FILL BAD_TRAP    ; x7D
+        .FILL BAD_TRAP    ; x7E
+        .FILL BAD_TRAP    ; x7F
+        .FILL BAD_TRAP    ; x80
+        .FILL BAD_TRAP    ; x81
+        .FILL BAD_TRAP    ; x82
+        .FILL BAD_TRAP    ; x83
+        .FILL BAD_TRAP    ; x84
+        .FILL BAD_TRAP    ; x85
+        .FILL BAD_TRAP    ; x86
+        .FILL BAD_TRAP    ; x87
+        .FILL BAD_TRAP    ; x88
+        .FILL BAD_TRAP    ; x89
+        .FILL BAD_TRAP    ; x8A
+        .FILL BAD_TRAP    ; x8B
+        .FILL BAD_TRAP    ; x8C
+        .FILL BAD_TRAP    ; x8D
+        .FILL BAD_TRAP    ; x8E
+        .FILL BAD_TRAP    ; x8F
+        .FILL BAD_TRAP    ; x90
+        .FILL BAD_TRAP    ; x91
+        .FILL BAD_TRAP    ; x92
+        .FILL BAD_TRAP    ; x93
+        .FILL BAD_TRAP    ; x94
+        .FILL BAD_TRAP    ; x95
+        .FILL BAD_TRAP    ; x96
+        .FILL BAD_TRAP    ; x97
+        .FILL BAD_TRAP    ; x98
+        .FILL BAD_TRAP    ; x99
+        .FILL BAD_TRAP    ; x9A
+        .FILL BAD_TRAP    ; x9B
+        .FILL BAD_TRAP    ; x9C
+        .FILL BAD_TRAP    ; x9D
+        .FILL BAD_TRAP    ; x9E
+        .FILL BAD_TRAP    ; x9F
+        .FILL BAD_TRAP    ; xA0
+        .FILL BAD_TRAP    ; xA1
+        .FILL BAD_TRAP    ; xA2
+        .FILL BAD_TRAP    ; xA3
+        .FILL BAD_TRAP    ; xA4
+        .FILL BAD_TRAP    ; xA5
+        .FILL BAD_TRAP    ; xA6
+        .FILL BAD_TRAP    ; xA7
+        .FILL BAD_TRAP    ; xA8
+        .FILL BAD_TRAP    ; xA9
+        .FILL BAD_TRAP    ; xAA
+        .FILL BAD_TRAP    ; xAB
+        .FILL BAD_TRAP    ; xAC
+        .FILL BAD_TRAP    ; xAD
+        .FILL BAD_TRAP    ; xAE
+        .FILL BAD_TRAP    ; xAF
+        .FILL BAD_TRAP    ; xB0
+        .FILL BAD_TRAP    ; xB1
+        .FILL BAD_TRAP    ; xB2
+        .FILL BAD_TRAP    ; xB3
+        .FILL BAD_TRAP    ; xB4
+        .FILL BAD_TRAP    ; xB5
+        .FILL BAD_TRAP    ; xB6
+        .FILL BAD_TRAP    ; xB7
+        .FILL BAD_TRAP    ; xB8
+        .FILL BAD_TRAP    ; xB9
+        .FILL BAD_TRAP    ; xBA
+        .FILL BAD_TRAP    ; xBB
+        .FILL BAD_TRAP    ; xBC
+        .FILL BAD_TRAP    ; xBD
+        .FILL BAD_TRAP    ; xBE
+        .FILL BAD_TRAP    ; xBF
+        .FILL BAD_TRAP    ; xC0
+        .FILL BAD_TRAP    ; xC1
+        .FILL BAD_TRAP    ; xC2
+        .FILL BAD_TRAP    ; xC3
+        .FILL BAD_TRAP    ; xC4
+        .FILL BAD_TRAP    ; xC5
+        .FILL BAD_TRAP    ; xC6
+        .FILL BAD_TRAP    ; xC7
+        .FILL BAD_TRAP    ; xC8
+        .FILL BAD_TRAP    ; xC9
+        .FILL BAD_TRAP    ; xCA
+        .FILL BAD_TRAP    ; xCB
+        .FILL BAD_TRAP    ; xCC
+        .FILL BAD_TRAP    ; xCD
+        .FILL BAD_TRAP    ; xCE
+        .FILL BAD_TRAP    ; xCF
+        .FILL BAD_TRAP    ; xD0
+        .FILL BAD_TRAP    ; xD1
+        .FILL BAD_TRAP    ; xD2
+        .FILL BAD_TRAP    ; xD3
+        .FILL BAD_TRAP    ; xD4
+        .FILL BAD_TRAP    ; xD5
+        .FILL BAD_TRAP    ; xD6
+        .FILL BAD_TRAP    ; xD7
+        .FILL BAD_TRAP    ; xD8
+        .FILL BAD_TRAP    ; xD9
+        .FILL BAD_TRAP    ; xDA
+        .FILL BAD_TRAP    ; xDB
+        .FILL BAD_TRAP    ; xDC
+        .FILL BAD_TRAP    ; xDD
+        .FILL BAD_TRAP    ; xDE
+        .FILL BAD_TRAP    ; xDF
+        .FILL BAD_TRAP    ; xE0
+        .FILL BAD_TRAP    ; xE1
+        .FILL BAD_TRAP    ; xE2
+        .FILL BAD_TRAP    ; xE3
+        .FILL BAD_TRAP    ; xE4
+        .FILL BAD_TRAP    ; xE5
+        .FILL BAD_TRAP    ; xE6
+        .FILL BAD_TRAP    ; xE7
+        .FILL BAD_TRAP    ; xE8
+        .FILL BAD_TRAP    ; xE9
+        .FILL BAD_TRAP    ; xEA
+        .FILL BAD_TRAP    ; xEB
+        .FILL BAD_TRAP    ; xEC
+        .FILL BAD_TRAP    ; xED
+        .FILL BAD_TRAP    ; xEE
+        .FILL BAD_TRAP    ; xEF
+        .FILL BAD_TRAP    ; xF0
+        .FILL BAD_TRAP    ; xF1
+        .FILL BAD_TRAP    ; xF2
+        .FILL BAD_TRAP    ; xF3
+        .FILL BAD_TRAP    ; xF4
+        .FILL BAD_TRAP    ; xF5
+        .FILL BAD_TRAP    ; xF6
+        .FILL BAD_TRAP    ; xF7
+        .FILL BAD_TRAP    ; xF8
+        .FILL BAD_TRAP    ; xF9
+        .FILL BAD_TRAP    ; xFA
+        .FILL BAD_TRAP    ; xFB
+        .FILL BAD_TRAP    ; xFC
+        .FILL BAD_TRAP    ; xFD
+        .FILL BAD_TRAP    ; xFE
+        .FILL BAD_TRAP    ; xFF
+
+; Interrupt vector table, x0100 to x01FF
+        .FILL PRIVILEGE_VIOLATION ; x00
+        .FILL ILLEGAL_OPCODE      ; x01
+        .FILL ACCESS_VIOLATION    ; x02
+        .FILL BAD_INTERRUPT       ; x03
+        .FILL BAD_INTERRUPT       ; x04
+        .FILL BAD_INTERRUPT       ; x05
+        .FILL BAD_INTERRUPT       ; x06
+        .FILL BAD_INTERRUPT       ; x07
+        .FILL BAD_INTERRUPT       ; x08
+        .FILL BAD_INTERRUPT       ; x09
+        .FILL BAD_INTERRUPT       ; x0A
+        .FILL BAD_INTERRUPT       ; x0B
+        .FILL BAD_INTERRUPT       ; x0C
+        .FILL BAD_INTERRUPT       ; x0D
+        .FILL BAD_INTERRUPT       ; x0E
+        .FILL BAD_INTERRUPT       ; x0F
+        .FILL BAD_INTERRUPT       ; x10
+        .FILL BAD_INTERRUPT       ; x11
+        .FILL BAD_INTERRUPT       ; x12
+        .FILL BAD_INTERRUPT       ; x13
+        .FILL BAD_INTERRUPT       ; x14
+        .FILL BAD_INTERRUPT       ; x15
+        .FILL BAD_INTERRUPT       ; x16
+        .FILL BAD_INTERRUPT       ; x17
+        .FILL BAD_INTERRUPT       ; x18
+        .FILL BAD_INTERRUPT       ; x19
+        .FILL BAD_INTERRUPT       ; x1A
+        .FILL BAD_INTERRUPT       ; x1B
+        .FILL BAD_INTERRUPT       ; x1C
+        .FILL BAD_INTERRUPT       ; x1D
+        .FILL BAD_INTERRUPT       ; x1E
+        .FILL BAD_INTERRUPT       ; x1F
+        .FILL BAD_INTERRUPT       ; x20
+        .FILL BAD_INTERRUPT       ; x21
+        .FILL BAD_INTERRUPT       ; x22
+        .FILL BAD_INTERRUPT       ; x23
+        .FILL BAD_INTERRUPT       ; x24
+        .FILL BAD_INTERRUPT       ; x25
+        .FILL BAD_INTERRUPT       ; x26
+        .FILL BAD_INTERRUPT       ; x27
+        .FILL BAD_INTERRUPT       ; x28
+        .FILL BAD_INTERRUPT       ; x29
+        .FILL BAD_INTERRUPT       ; x2A
+        .FILL BAD_INTERRUPT       ; x2B
+        .FILL BAD_INTERRUPT       ; x2C
+        .FILL BAD_INTERRUPT       ; x2D
+        .FILL BAD_INTERRUPT       ; x2E
+        .FILL BAD_INTERRUPT       ; x2F
+        .FILL BAD_INTERRUPT       ; x30
+        .FILL BAD_INTERRUPT       ; x31
+        .FILL BAD_INTERRUPT       ; x32
+        .FILL BAD_INTERRUPT       ; x33
+        .FILL BAD_INTERRUPT       ; x34
+        .FILL BAD_INTERRUPT       ; x35
+        .FILL BAD_INTERRUPT       ; x36
+        .FILL BAD_INTERRUPT       ; x37
+        .FILL BAD_INTERRUPT       ; x38
+        .FILL BAD_INTERRUPT       ; x39
+        .FILL BAD_INTERRUPT       ; x3A
+        .FILL BAD_INTERRUPT       ; x3B
+        .FILL BAD_INTERRUPT       ; x3C
+        .FILL BAD_INTERRUPT       ; x3D
+        .FILL BAD_INTERRUPT       ; x3E
+        .FILL BAD_INTERRUPT       ; x3F
+        .FILL BAD_INTERRUPT       ; x40
+        .FILL BAD_INTERRUPT       ; x41
+        .FILL BAD_INTERRUPT       ; x42
+        .FILL BAD_INTERRUPT       ; x43
+        .FILL BAD_INTERRUPT       ; x44
+        .FILL BAD_INTERRUPT       ; x45
+        .FILL BAD_INTERRUPT       ; x46
+        .FILL BAD_INTERRUPT       ; x47
+        .FILL BAD_INTERRUPT       ; x48
+        .FILL BAD_INTERRUPT       ; x49
+        .FILL BAD_INTERRUPT       ; x4A
+        .FILL BAD_INTERRUPT       ; x4B
+        .FILL BAD_INTERRUPT       ; x4C
+        .FILL BAD_INTERRUPT       ; x4D
+        .FILL BAD_INTERRUPT       ; x4E
+        .FILL BAD_INTERRUPT       ; x4F
+        .FILL BAD_INTERRUPT       ; x50
+        .FILL BAD_INTERRUPT       ; x51
+        .FILL BAD_INTERRUPT       ; x52
+        .FILL BAD_INTERRUPT       ; x53
+        .FILL BAD_INTERRUPT       ; x54
+        .FILL BAD_INTERRUPT       ; x55
+        .FILL BAD_INTERRUPT       ; x56
+        .FILL BAD_INTERRUPT       ; x57
+        .FILL BAD_INTERRUPT       ; x58
+        .FILL BAD_INTERRUPT       ; x59
+        .FILL BAD_INTERRUPT       ; x5A
+        .FILL BAD_INTERRUPT       ; x5B
+        .FILL BAD_INTERRUPT       ; x5C
+        .FILL BAD_INTERRUPT       ; x5D
+        .FILL BAD_INTERRUPT       ; x5E
+        .FILL BAD_INTERRUPT       ; x5F
+        .FILL BAD_INTERRUPT       ; x60
+        .FILL BAD_INTERRUPT       ; x61
+        .FILL BAD_INTERRUPT       ; x62
+        .FILL BAD_INTERRUPT       ; x63
+        .FILL BAD_INTERRUPT       ; x64
+        .FILL BAD_INTERRUPT       ; x65
+        .FILL BAD_INTERRUPT       ; x66
+        .FILL BAD_INTERRUPT       ; x67
+        .FILL BAD_INTERRUPT       ; x68
+        .FILL BAD_INTERRUPT       ; x69
+        .FILL BAD_INTERRUPT       ; x6A
+        .FILL BAD_INTERRUPT       ; x6B
+        .FILL BAD_INTERRUPT       ; x6C
+        .FILL BAD_INTERRUPT       ; x6D
+        .FILL BAD_INTERRUPT       ; x6E
+        .FILL BAD_INTERRUPT       ; x6F
+        .FILL BAD_INTERRUPT       ; x70
+        .FILL BAD_INTERRUPT       ; x71
+        .FILL BAD_INTERRUPT       ; x72
+        .FILL BAD_INTERRUPT       ; x73
+        .FILL BAD_INTERRUPT       ; x74
+        .FILL BAD_INTERRUPT       ; x75
+        .FILL BAD_INTERRUPT       ; x76
+        .FILL BAD_INTERRUPT       ; x77
+        .FILL BAD_INTERRUPT       ; x78
+        .FILL BAD_INTERRUPT       ; x79
+        .FILL BAD_INTERRUPT       ; x7A
+        .FILL BAD_INTERRUPT       ; x7B
+        .FILL BAD_INTERRUPT       ; x7C
+        .FILL BAD_INTERRUPT       ; x7D
+        .FILL BAD_INTERRUPT       ; x7E
+        .FILL BAD_INTERRUPT       ; x7F
+        .FILL BAD_INTERRUPT       ; x80
+        .FILL BAD_INTERRUPT       ; x81
+        .FILL BAD_INTERRUPT       ; x82
+        .FILL BAD_INTERRUPT       ; x83
+        .FILL BAD_INTERRUPT       ; x84
+        .FILL BAD_INTERRUPT       ; x85
+        .FILL BAD_INTERRUPT       ; x86
+        .FILL BAD_INTERRUPT       ; x87
+        .FILL BAD_INTERRUPT       ; x88
+        .FILL BAD_INTERRUPT       ; x89
+        .FILL BAD_INTERRUPT       ; x8A
+        .FILL BAD_INTERRUPT       ; x8B
+        .FILL BAD_INTERRUPT       ; x8C
+        .FILL BAD_INTERRUPT       ; x8D
+        .FILL BAD_INTERRUPT       ; x8E
+        .FILL BAD_INTERRUPT       ; x8F
+        .FILL BAD_INTERRUPT       ; x90
+        .FILL BAD_INTERRUPT       ; x91
+        .FILL BAD_INTERRUPT       ; x92
+        .FILL BAD_INTERRUPT       ; x93
+        .FILL BAD_INTERRUPT       ; x94
+        .FILL BAD_INTERRUPT       ; x95
+        .FILL BAD_INTERRUPT       ; x96
+        .FILL BAD_INTERRUPT       ; x97
+        .FILL BAD_INTERRUPT       ; x98
+        .FILL BAD_INTERRUPT       ; x99
+        .FILL BAD_INTERRUPT       ; x9A
+        .FILL BAD_INTERRUPT       ; x9B
+        .FILL BAD_INTERRUPT       ; x9C
+        .FILL BAD_INTERRUPT       ; x9D
+        .FILL BAD_INTERRUPT       ; x9E
+        .FILL BAD_INTERRUPT       ; x9F
+        .FILL BAD_INTERRUPT       ; xA0
+        .FILL BAD_INTERRUPT       ; xA1
+        .FILL BAD_INTERRUPT       ; xA2
+        .FILL BAD_INTERRUPT       ; xA3
+        .FILL BAD_INTERRUPT       ; xA4
+        .FILL BAD_INTERRUPT       ; xA5
+        .FILL BAD_INTERRUPT       ; xA6
+        .FILL BAD_INTERRUPT       ; xA7
+        .FILL BAD_INTERRUPT       ; xA8
+        .FILL BAD_INTERRUPT       ; xA9
+        .FILL BAD_INTERRUPT       ; xAA
+        .FILL BAD_INTERRUPT       ; xAB
+        .FILL BAD_INTERRUPT       ; xAC
+        .FILL BAD_INTERRUPT       ; xAD
+        .FILL BAD_INTERRUPT       ; xAE
+        .FILL BAD_INTERRUPT       ; xAF
+        .FILL BAD_INTERRUPT       ; xB0
+        .FILL BAD_INTERRUPT       ; xB1
+        .FILL BAD_INTERRUPT       ; xB2
+        .FILL BAD_INTERRUPT       ; xB3
+        .FILL BAD_INTERRUPT       ; xB4
+        .FILL BAD_INTERRUPT       ; xB5
+        .FILL BAD_INTERRUPT       ; xB6
+        .FILL BAD_INTERRUPT       ; xB7
+        .FILL BAD_INTERRUPT       ; xB8
+        .FILL BAD_INTERRUPT       ; xB9
+        .FILL BAD_INTERRUPT       ; xBA
+        .FILL BAD_INTERRUPT       ; xBB
+        .FILL BAD_INTERRUPT       ; xBC
+        .FILL BAD_INTERRUPT       ; xBD
+        .FILL BAD_INTERRUPT       ; xBE
+        .FILL BAD_INTERRUPT       ; xBF
+        .FILL BAD_INTERRUPT       ; xC0
+        .FILL BAD_INTERRUPT       ; xC1
+        .FILL BAD_INTERRUPT       ; xC2
+        .FILL BAD_INTERRUPT       ; xC3
+        .FILL BAD_INTERRUPT       ; xC4
+        .FILL BAD_INTERRUPT       ; xC5
+        .FILL BAD_INTERRUPT       ; xC6
+        .FILL BAD_INTERRUPT       ; xC7
+        .FILL BAD_INTERRUPT       ; xC8
+        .FILL BAD_INTERRUPT       ; xC9
+        .FILL BAD_INTERRUPT       ; xCA
+        .FILL BAD_INTERRUPT       ; xCB
+        .FILL BAD_INTERRUPT       ; xCC
+        .FILL BAD_INTERRUPT       ; xCD
+        .FILL BAD_INTERRUPT       ; xCE
+        .FILL BAD_INTERRUPT       ; xCF
+        .FILL BAD_INTERRUPT       ; xD0
+        .FILL BAD_INTERRUPT       ; xD1
+        .FILL BAD_INTERRUPT       ; xD2
+        .FILL BAD_INTERRUPT       ; xD3
+        .FILL BAD_INTERRUPT       ; xD4
+        .FILL BAD_INTERRUPT       ; xD5
+        .FILL BAD_INTERRUPT       ; xD6
+        .FILL BAD_INTERRUPT       ; xD7
+        .FILL BAD_INTERRUPT       ; xD8
+        .FILL BAD_INTERRUPT       ; xD9
+        .FILL BAD_INTERRUPT       ; xDA
+        .FILL BAD_INTERRUPT       ; xDB
+        .FILL BAD_INTERRUPT       ; xDC
+        .FILL BAD_INTERRUPT       ; xDD
+        .FILL BAD_INTERRUPT       ; xDE
+        .FILL BAD_INTERRUPT       ; xDF
+        .FILL BAD_INTERRUPT       ; xE0
+        .FILL BAD_INTERRUPT       ; xE1
+        .FILL BAD_INTERRUPT       ; xE2
+        .FILL BAD_INTERRUPT       ; xE3
+        .FILL BAD_INTERRUPT       ; xE4
+        .FILL BAD_INTERRUPT       ; xE5
+        .FILL BAD_INTERRUPT       ; xE6
+        .FILL BAD_INTERRUPT       ; xE7
+        .FILL BAD_INTERRUPT       ; xE8
+        .FILL BAD_INTERRUPT       ; xE9
+        .FILL BAD_INTERRUPT       ; xEA
+        .FILL BAD_INTERRUPT       ; xEB
+        .FILL BAD_INTERRUPT       ; xEC
+        .FILL BAD_INTERRUPT       ; xED
+        .FILL BAD_INTERRUPT       ; xEE
+        .FILL BAD_INTERRUPT       ; xEF
+        .FILL BAD_INTERRUPT       ; xF0
+        .FILL BAD_INTERRUPT       ; xF1
+        .FILL BAD_INTERRUPT       ; xF2
+        .FILL BAD_INTERRUPT       ; xF3
+        .FILL BAD_INTERRUPT       ; xF4
+        .FILL BAD_INTERRUPT       ; xF5
+        .FILL BAD_INTERRUPT       ; xF6
+        .FILL BAD_INTERRUPT       ; xF7
+        .FILL BAD_INTERRUPT       ; xF8
+        .FILL BAD_INTERRUPT       ; xF9
+        .FILL BAD_INTERRUPT       ; xFA
+        .FILL BAD_INTERRUPT       ; xFB
+        .FILL BAD_INTERRUPT       ; xFC
+        .FILL BAD_INTERRUPT       ; xFD
+        .FILL BAD_INTERRUPT       ; xFE
+        .FILL BAD_INTERRUPT       ; xFF
+
+; Service routines, starting at x0200
+
+; Reads a character from the keyboard into R0 without echoing it
+TRAP_GETC
+        LDI R0, OS_KBSR
+        BRzp TRAP_GETC
+        LDI R0, OS_KBDR
+        RTI
+
+; Writes the character in R0 to the display
+TRAP_OUT
+        ST R1, OUT_R1
+OUT_WAIT
+        LDI R1, OS_DSR
+        BRzp OUT_WAIT
+        STI R0, OS_DDR
+        LD R1, OUT_R1
+        RTI
+
+; Writes the null terminated string starting at the address in R0, one character per word
+TRAP_PUTS
+        ST R0, PUTS_R0
+        ST R1, PUTS_R1
+        ST R7, PUTS_R7
+        ADD R1, R0, #0
+PUTS_LOOP
+        LDR R0, R1, #0
+        BRz PUTS_DONE
+        OUT
+        ADD R1, R1, #1
+        BR PUTS_LOOP
+PUTS_DONE
+        LD R0, PUTS_R0
+        LD R1, PUTS_R1
+        LD R7, PUTS_R7
+        RTI
+
+; Prompts for a character, echoes it and leaves it in R0
+TRAP_IN
+        ST R7, IN_R7
+        LEA R0, IN_PROMPT
+        PUTS
+        GETC
+        OUT
+        LD R7, IN_R7
+        RTI
+
+; Writes the null terminated string starting at the address in R0, two characters per word
+; with the first one in the low byte
+TRAP_PUTSP
+        ST R0, PUTSP_R0
+        ST R1, PUTSP_R1
+        ST R2, PUTSP_R2
+        ST R3, PUTSP_R3
+        ST R7, PUTSP_R7
+        ADD R1, R0, #0
+PUTSP_LOOP
+        LDR R2, R1, #0
+        BRz PUTSP_DONE
+        LD R3, LOW_BYTE
+        AND R0, R2, R3
+        OUT
+        ; Shift the high byte down by moving the top 8 bits of R2 into R0, one at a time
+        AND R0, R0, #0
+        AND R3, R3, #0
+        ADD R3, R3, #8
+PUTSP_SHIFT
+        ADD R0, R0, R0
+        ADD R2, R2, #0
+        BRzp PUTSP_ZERO_BIT
+        ADD R0, R0, #1
+PUTSP_ZERO_BIT
+        ADD R2, R2, R2
+        ADD R3, R3, #-1
+        BRp PUTSP_SHIFT
+        ADD R0, R0, #0
+        BRz PUTSP_DONE
+        OUT
+        ADD R1, R1, #1
+        BR PUTSP_LOOP
+PUTSP_DONE
+        LD R0, PUTSP_R0
+        LD R1, PUTSP_R1
+        LD R2, PUTSP_R2
+        LD R3, PUTSP_R3
+        LD R7, PUTSP_R7
+        RTI
+
+; Stops the machine by clearing the clock enable bit of the machine control register
+TRAP_HALT
+        ST R0, HALT_R0
+        ST R1, HALT_R1
+        LDI R0, OS_MCR
+        LD R1, CLOCK_MASK
+        AND R0, R0, R1
+        STI R0, OS_MCR
+        LD R0, HALT_R0
+        LD R1, HALT_R1
+        RTI
+
+; Reads the block storage sector in R0 into the 256 word buffer at the address in R1,
+; R0 is set to 0 on success and to -1 on failure
+TRAP_RDSEC
+        ST R2, SECTOR_R2
+        AND R2, R2, #0
+        ADD R2, R2, #1
+        BR SECTOR_TRANSFER
+
+; Writes the 256 word buffer at the address in R1 to the block storage sector in R0,
+; R0 is set to 0 on success and to -1 on failure
+TRAP_WRSEC
+        ST R2, SECTOR_R2
+        AND R2, R2, #0
+        ADD R2, R2, #2
+
+; Runs the command in R2, a clear ready bit means there is no block storage. The buffer of a
+; user mode caller is refused when it overlaps a protected range or wraps around memory
+SECTOR_TRANSFER
+        ST R3, SECTOR_R3
+        ST R4, SECTOR_R4
+        ST R5, SECTOR_R5
+        ST R7, SECTOR_R7
+        ; The PSR of the caller is below the return address on the supervisor stack
+        LDR R3, R6, #1
+        BRzp SECTOR_ALLOWED
+        ST R0, SECTOR_NUMBER
+        ST R2, SECTOR_COMMAND
+        LD R4, SECTOR_LAST
+        ADD R4, R1, R4
+        ST R4, SECTOR_END
+        ADD R3, R1, #0
+        JSR UNSIGNED_LE
+        BRz SECTOR_FAILED
+        LD R2, OS_PROTECTED_COUNT
+        LEA R0, OS_PROTECTED_RANGES
+SECTOR_CHECK
+        ADD R2, R2, #-1
+        BRn SECTOR_CHECKED
+        ; The buffer overlaps the range when the range starts before the buffer ends and ends
+        ; after the buffer starts
+        LDR R3, R0, #0
+        LD R4, SECTOR_END
+        JSR UNSIGNED_LE
+        BRz SECTOR_NEXT
+        ADD R3, R1, #0
+        LDR R4, R0, #1
+        JSR UNSIGNED_LE
+        BRp SECTOR_FAILED
+SECTOR_NEXT
+        ADD R0, R0, #2
+        BR SECTOR_CHECK
+SECTOR_CHECKED
+        LD R0, SECTOR_NUMBER
+        LD R2, SECTOR_COMMAND
+SECTOR_ALLOWED
+        STI R0, OS_BLKSEC
+        STI R1, OS_BLKADDR
+        STI R2, OS_BLKCMD
+        LDI R2, OS_BLKSR
+        BRzp SECTOR_FAILED
+        AND R2, R2, #1
+        BRnp SECTOR_FAILED
+        AND R0, R0, #0
+        BR SECTOR_DONE
+SECTOR_FAILED
+        AND R0, R0, #0
+        ADD R0, R0, #-1
+SECTOR_DONE
+        LD R2, SECTOR_R2
+        LD R3, SECTOR_R3
+        LD R4, SECTOR_R4
+        LD R5, SECTOR_R5
+        LD R7, SECTOR_R7
+        RTI
+
+; Sets R5 to 1 when R3 is lower than or equal to R4 as unsigned numbers and to 0 otherwise,
+; the condition codes are set by R5
+UNSIGNED_LE
+        ADD R3, R3, #0
+        BRn ULE_HIGH
+        ADD R4, R4, #0
+        BRn ULE_TRUE
+        BR ULE_SUBTRACT
+ULE_HIGH
+        ADD R4, R4, #0
+        BRzp ULE_FALSE
+ULE_SUBTRACT
+        ; Both numbers are in the same half of the range, so the difference can't overflow
+        NOT R5, R4
+        ADD R5, R5, #1
+        ADD R5, R3, R5
+        BRp ULE_FALSE
+ULE_TRUE
+        AND R5, R5, #0
+        ADD R5, R5, #1
+        RET
+ULE_FALSE
+        AND R5, R5, #0
+        RET
+
+; Installs the handler at the address in R1 into the vector table entry at the address in R0,
+; either a trap vector (x0000 to x00FF) or a device interrupt vector (x0180 to x01FF). The trap
+; vectors of the OS (x0020 to x002F) and the exception vectors are kept. R0 is set to 0 on
+; success and to -1 for any other entry. The handler runs in supervisor mode and returns with RTI
+TRAP_SETVEC
+        ST R2, SETVEC_R2
+        ; Entries from x8000 up would look negative to the checks below
+        ADD R0, R0, #0
+        BRn SETVEC_FAILED
+        LD R2, NEG_OS_TRAPS
+        ADD R2, R0, R2
+        BRn SETVEC_INSTALL
+        ADD R2, R2, #-16
+        BRn SETVEC_FAILED
+        LD R2, NEG_TRAP_END
+        ADD R2, R0, R2
+        BRn SETVEC_INSTALL
+        LD R2, NEG_DEVICE_VECTORS
+        ADD R2, R0, R2
+        BRn SETVEC_FAILED
+        LD R2, NEG_VECTOR_END
+        ADD R2, R0, R2
+        BRzp SETVEC_FAILED
+SETVEC_INSTALL
+        STR R1, R0, #0
+        AND R0, R0, #0
+        BR SETVEC_DONE
+SETVEC_FAILED
+        AND R0, R0, #0
+        ADD R0, R0, #-1
+SETVEC_DONE
+        LD R2, SETVEC_R2
+        RTI
+
+; Reports a trap without a service routine and stops with a fault
+BAD_TRAP
+        LEA R0, BAD_TRAP_MESSAGE
+        PUTS
+        BR FAULT
+
+; Reports an RTI executed in user mode and stops with a fault
+PRIVILEGE_VIOLATION
+        LEA R0, PRIVILEGE_VIOLATION_MESSAGE
+        PUTS
+        BR FAULT
+
+; Reports an instruction with a reserved opcode and stops with a fault
+ILLEGAL_OPCODE
+        LEA R0, ILLEGAL_OPCODE_MESSAGE
+        PUTS
+        BR FAULT
+
+; Reports an access to protected memory and stops with a fault
+ACCESS_VIOLATION
+        LEA R0, ACCESS_VIOLATION_MESSAGE
+        PUTS
+        BR FAULT
+
+; Reports an interrupt without a service routine and stops with a fault
+BAD_INTERRUPT
+        LEA R0, BAD_INTERRUPT_MESSAGE
+        PUTS
+
+; Stops the machine like HALT, but also sets the fault bit (0) of the machine control register
+; so the host can tell the program failed. A machine resumed after a fault stops again
+FAULT
+        LDI R0, OS_MCR
+        LD R1, CLOCK_MASK
+        AND R0, R0, R1
+        AND R1, R0, #1
+        BRp FAULT_STOP
+        ADD R0, R0, #1
+FAULT_STOP
+        STI R0, OS_MCR
+        BR FAULT
+
+OS_KBSR     .FILL xFE00
+OS_KBDR     .FILL xFE02
+OS_DSR      .FILL xFE04
+OS_DDR      .FILL xFE06
+OS_BLKCMD   .FILL xFE10
+OS_BLKSEC   .FILL xFE12
+OS_BLKADDR  .FILL xFE14
+OS_BLKSR    .FILL xFE16
+OS_MCR      .FILL xFFFE
+LOW_BYTE    .FILL x00FF
+SECTOR_LAST .FILL #255
+NEG_OS_TRAPS        .FILL xFFE0
+NEG_TRAP_END        .FILL xFF00
+NEG_DEVICE_VECTORS  .FILL xFE80
+NEG_VECTOR_END      .FILL xFE00
+CLOCK_MASK  .FILL x7FFF
+
+OUT_R1      .BLKW 1
+PUTS_R0     .BLKW 1
+PUTS_R1     .BLKW 1
+PUTS_R7     .BLKW 1
+IN_R7       .BLKW 1
+PUTSP_R0    .BLKW 1
+PUTSP_R1    .BLKW 1
+PUTSP_R2    .BLKW 1
+PUTSP_R3    .BLKW 1
+PUTSP_R7    .BLKW 1
+HALT_R0     .BLKW 1
+HALT_R1     .BLKW 1
+SECTOR_R2   .BLKW 1
+SECTOR_R3   .BLKW 1
+SECTOR_R4   .BLKW 1
+SECTOR_R5   .BLKW 1
+SECTOR_R7   .BLKW 1
+SECTOR_NUMBER   .BLKW 1
+SECTOR_COMMAND  .BLKW 1
+SECTOR_END  .BLKW 1
+SETVEC_R2   .BLKW 1
+
+; Memory protected from user mode, kept up to date by the VM: the number of ranges followed by
+; the first and last address of each one, with room for 16 ranges
+OS_PROTECTED_COUNT  .FILL #2
+OS_PROTECTED_RANGES .FILL x0000
+                    .FILL x2FFF
+                    .FILL xFE00
+                    .FILL xFFFF
+                    .BLKW 28
+
+IN_PROMPT           .STRINGZ "Enter a character: "
+BAD_TRAP_MESSAGE    .STRINGZ "\nIllegal trap executed\n"
+PRIVILEGE_VIOLATION_MESSAGE .STRINGZ "\nPrivilege mode violation\n"
+ILLEGAL_OPCODE_MESSAGE      .STRINGZ "\nIllegal opcode\n"
+ACCESS_VIOLATION_MESSAGE    .STRINGZ "\nAccess control violation\n"
+BAD_INTERRUPT_MESSAGE       .STRINGZ "\nUnexpected interrupt\n"
+
+        .END
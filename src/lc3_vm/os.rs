@@ -0,0 +1,41 @@
+use super::assembler::{self, AssemblerError, Program};
+
+/// Source of the bundled operating system image
+pub const SOURCE: &str = include_str!("os.asm");
+
+/// Assembles the bundled operating system image
+pub fn image() -> Result<Program, AssemblerError> {
+    assembler::assemble(SOURCE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vector_table_points_to_service_routines() -> Result<(), Box<dyn std::error::Error>> {
+        let program = image()?;
+        assert_eq!(0x0000, program.origin);
+        for (vector, label) in [
+            (0x20, "TRAP_GETC"),
+            (0x21, "TRAP_OUT"),
+            (0x22, "TRAP_PUTS"),
+            (0x23, "TRAP_IN"),
+            (0x24, "TRAP_PUTSP"),
+            (0x25, "TRAP_HALT"),
+            (0x26, "TRAP_RDSEC"),
+            (0x27, "TRAP_WRSEC"),
+            (0x28, "TRAP_SETVEC"),
+            (0x00, "BAD_TRAP"),
+            (0xFF, "BAD_TRAP"),
+        ] {
+            assert_eq!(
+                program.symbols.get(label),
+                program.words.get(vector),
+                "vector x{vector:02X}"
+            );
+        }
+        assert_eq!(Some(&0x0200), program.symbols.get("TRAP_GETC"));
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use super::{opcodes::Opcode, symbols::location, virtual_machine::VM};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+};
+
+/// How a function on the shadow call stack was entered, so it is only left by the matching
+/// return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    /// JSR or JSRR, left with JMP R7
+    Subroutine,
+    /// TRAP into an OS service routine, interrupt or exception, left with RTI
+    Trap,
+}
+
+/// Counts executed instructions per address and per opcode, and reconstructs the call graph
+/// from JSR/JSRR calls and JMP R7 returns. TRAPs into the OS image, interrupts and exceptions
+/// count as calls of their handlers that return with RTI. Fed by `record` after every instruction, for example from `VM::run_with`
+pub struct Profiler {
+    counts: Vec<u64>,
+    opcodes: BTreeMap<&'static str, u64>,
+    /// Entry address and kind of every function that hasn't returned yet, the outermost one
+    /// is where the program started
+    stack: Vec<(u16, Entry)>,
+    /// Instructions executed with exactly this stack of function entry addresses
+    stacks: BTreeMap<Vec<u16>, u64>,
+    calls: BTreeMap<(u16, u16), u64>,
+    total: u64,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; 1 << 16],
+            opcodes: BTreeMap::new(),
+            stack: Vec::new(),
+            stacks: BTreeMap::new(),
+            calls: BTreeMap::new(),
+            total: 0,
+        }
+    }
+}
+
+impl Profiler {
+    /// Counts the instruction the VM just executed, after the interrupt handler entered before
+    /// it and before the exception handler it entered
+    pub fn record(&mut self, vm: &VM) {
+        let entries = vm.last_entries();
+        if let Some(handler) = entries.interrupt {
+            self.call(handler, Entry::Trap);
+        }
+        if let Some((address, instruction)) = vm.last_instruction() {
+            self.count_instruction(vm, address, instruction, entries.exception.is_none());
+        }
+        if let Some(handler) = entries.exception {
+            self.call(handler, Entry::Trap);
+        }
+    }
+
+    /// Counts an executed instruction and follows the call or return it made, unless it raised
+    /// an exception instead of completing
+    fn count_instruction(&mut self, vm: &VM, address: u16, instruction: u16, completed: bool) {
+        self.total = self.total.wrapping_add(1);
+        if let Some(count) = self.counts.get_mut(usize::from(address)) {
+            *count = count.wrapping_add(1);
+        }
+        if self.stack.is_empty() {
+            self.stack.push((address, Entry::Subroutine));
+        }
+        let functions: Vec<u16> = self.stack.iter().map(|(function, _)| *function).collect();
+        let count = self.stacks.entry(functions).or_default();
+        *count = count.wrapping_add(1);
+
+        let Ok(opcode) = Opcode::try_from(instruction) else {
+            return;
+        };
+        let count = self.opcodes.entry(opcode.name()).or_default();
+        *count = count.wrapping_add(1);
+        if !completed {
+            return;
+        }
+        match opcode {
+            Opcode::JSR { .. } => self.call(vm.pc(), Entry::Subroutine),
+            Opcode::TRAP { .. } if vm.os_loaded() => self.call(vm.pc(), Entry::Trap),
+            Opcode::JMP { base_r: 7 } => self.ret(Entry::Subroutine),
+            Opcode::RTI {} => self.ret(Entry::Trap),
+            _ => {}
+        }
+    }
+
+    fn call(&mut self, callee: u16, entry: Entry) {
+        if let Some((caller, _)) = self.stack.last() {
+            let count = self.calls.entry((*caller, callee)).or_default();
+            *count = count.wrapping_add(1);
+        }
+        self.stack.push((callee, entry));
+    }
+
+    /// Leaves the innermost function if it was entered the way the instruction returns, the
+    /// outermost function is never left
+    fn ret(&mut self, entry: Entry) {
+        if self.stack.len() > 1 && self.stack.last().is_some_and(|(_, kind)| *kind == entry) {
+            self.stack.pop();
+        }
+    }
+
+    /// Number of instructions recorded
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of times the instruction at the address was executed
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts
+            .get(usize::from(address))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Number of times `caller` called `callee`
+    pub fn calls(&self, caller: u16, callee: u16) -> u64 {
+        self.calls
+            .get(&(caller, callee))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Renders the flat profile of the hottest addresses, the executed opcodes and the call
+    /// graph. Addresses are named after the labels in `symbols`, instructions are read from
+    /// the VM memory
+    pub fn report(&self, vm: &VM, symbols: &BTreeMap<String, u16>) -> String {
+        let mut report = format!("Flat profile, {} instructions\n", self.total);
+        let _ = writeln!(
+            report,
+            "{:>10}  {:>7}  {:<7}  {:<16}  instruction",
+            "count", "%", "address", "location"
+        );
+        let mut hot: Vec<(u16, u64)> = (0..=u16::MAX)
+            .map(|address| (address, self.count(address)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        hot.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        for (address, count) in hot {
+            let instruction = Opcode::try_from(vm.peek_word(address))
+                .map(|opcode| opcode.to_assembly_with_symbols(address, symbols))
+                .unwrap_or_default();
+            let _ = writeln!(
+                report,
+                "{:>10}  {:>7}  x{:04X}    {:<16}  {}",
+                count,
+                self.percent(count),
+                address,
+                location(symbols, address).unwrap_or_default(),
+                instruction
+            );
+        }
+
+        let _ = writeln!(report, "\nOpcodes");
+        let _ = writeln!(report, "{:>10}  {:>7}  opcode", "count", "%");
+        let mut opcodes: Vec<(&str, u64)> = self
+            .opcodes
+            .iter()
+            .map(|(name, count)| (*name, *count))
+            .collect();
+        opcodes.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        for (name, count) in opcodes {
+            let _ = writeln!(
+                report,
+                "{:>10}  {:>7}  {}",
+                count,
+                self.percent(count),
+                name
+            );
+        }
+
+        let _ = writeln!(report, "\nCall graph");
+        let _ = writeln!(
+            report,
+            "{:>10}  {:>10}  {:>7}  function",
+            "self", "inclusive", "calls"
+        );
+        let mut functions: BTreeMap<u16, (u64, u64)> = BTreeMap::new();
+        for (stack, count) in &self.stacks {
+            if let Some(function) = stack.last() {
+                let (own, _) = functions.entry(*function).or_default();
+                *own = own.wrapping_add(*count);
+            }
+            // Recursive functions appear several times in a stack but are counted once
+            for function in stack.iter().collect::<BTreeSet<_>>() {
+                let (_, inclusive) = functions.entry(*function).or_default();
+                *inclusive = inclusive.wrapping_add(*count);
+            }
+        }
+        let mut functions: Vec<(u16, u64, u64)> = functions
+            .into_iter()
+            .map(|(function, (own, inclusive))| (function, own, inclusive))
+            .collect();
+        functions.sort_by(|(a, _, a_inclusive), (b, _, b_inclusive)| {
+            b_inclusive.cmp(a_inclusive).then(a.cmp(b))
+        });
+        for (function, own, inclusive) in functions {
+            let calls: u64 = self
+                .calls
+                .iter()
+                .filter(|((_, callee), _)| *callee == function)
+                .map(|(_, count)| *count)
+                .sum();
+            let _ = writeln!(
+                report,
+                "{:>10}  {:>10}  {:>7}  {}",
+                own,
+                inclusive,
+                calls,
+                name(symbols, function)
+            );
+            for ((caller, callee), count) in &self.calls {
+                if *caller == function {
+                    let _ = writeln!(
+                        report,
+                        "{:>10}  {:>10}  {:>7}    -> {}",
+                        "",
+                        "",
+                        count,
+                        name(symbols, *callee)
+                    );
+                }
+            }
+        }
+        report
+    }
+
+    /// Renders one line per call stack with the number of instructions executed in it, in the
+    /// folded format read by flame graph tools
+    pub fn folded(&self, symbols: &BTreeMap<String, u16>) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, count)| {
+                let frames: Vec<String> = stack
+                    .iter()
+                    .map(|function| name(symbols, *function))
+                    .collect();
+                format!("{} {}\n", frames.join(";"), count)
+            })
+            .collect()
+    }
+
+    /// Share of the recorded instructions, with two decimals
+    fn percent(&self, count: u64) -> String {
+        let hundredths = count
+            .saturating_mul(10_000)
+            .saturating_add(self.total / 2)
+            .checked_div(self.total)
+            .unwrap_or_default();
+        format!("{}.{:02}%", hundredths / 100, hundredths % 100)
+    }
+}
+
+/// Names a function after the label at its entry address
+fn name(symbols: &BTreeMap<String, u16>, address: u16) -> String {
+    symbols
+        .iter()
+        .find(|(_, label_address)| **label_address == address)
+        .map(|(label, _)| label.clone())
+        .unwrap_or_else(|| format!("x{address:04X}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lc3_vm::{assembler::assemble, io_device::BufferIo};
+
+    #[test]
+    fn calls_are_counted_per_function() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+    MAIN    AND R1, R1, #0
+            ADD R1, R1, #2
+    LOOP    JSR DOUBLE
+            ADD R1, R1, #-1
+            BRp LOOP
+            HALT
+    DOUBLE  ST R7, SAVED
+            ADD R0, R0, R0
+            JSR INC
+            LD R7, SAVED
+            RET
+    INC     ADD R0, R0, #1
+            RET
+    SAVED   .BLKW 1
+            .END
+        ";
+        let program = assemble(source)?;
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&program.to_bytes())?;
+        let mut profiler = Profiler::default();
+        vm.run_with(|vm| profiler.record(vm))?;
+
+        assert_eq!(23, profiler.total());
+        assert_eq!(2, profiler.count(0x3002));
+        assert_eq!(2, profiler.calls(0x3000, 0x3006));
+        assert_eq!(2, profiler.calls(0x3006, 0x300B));
+        assert_eq!(
+            "MAIN 9\nMAIN;DOUBLE 10\nMAIN;DOUBLE;INC 4\n",
+            profiler.folded(&program.symbols)
+        );
+        let report = profiler.report(&vm, &program.symbols);
+        assert!(report.starts_with("Flat profile, 23 instructions\n"));
+        assert!(
+            report.contains("         2    8.70%  x3008    DOUBLE+2          JSR INC (x300B)\n")
+        );
+        assert!(report.contains("         7   30.43%  ADD\n"));
+        assert!(report.contains("        10          14        2  DOUBLE\n"));
+        assert!(report.contains("                              2    -> INC\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn interrupt_handlers_are_called_by_the_interrupted_function(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // The key press interrupts WAIT, the handler returns to it with RTI
+        let source = "
+            .ORIG x3000
+    MAIN    LEA R0, HANDLER
+            STI R0, VECTOR
+            JSR WAIT
+            HALT
+    WAIT    LD R0, ENABLE
+            STI R0, KBSR
+    LOOP    LD R1, KEY
+            BRz LOOP
+            RET
+    HANDLER LDI R1, KBDR
+            ST R1, KEY
+            RTI
+    VECTOR  .FILL x0180
+    ENABLE  .FILL x4000
+    KBSR    .FILL xFE00
+    KBDR    .FILL xFE02
+    KEY     .FILL #0
+            .END
+        ";
+        let program = assemble(source)?;
+        let label = |name: &str| program.symbols.get(name).copied().unwrap_or_default();
+        let mut vm = VM::new(Box::new(BufferIo::with_input(b"k".to_vec())));
+        vm.load_bytes(&program.to_bytes())?;
+        let mut profiler = Profiler::default();
+        vm.run_with(|vm| profiler.record(vm))?;
+
+        assert_eq!(1, profiler.calls(label("WAIT"), label("HANDLER")));
+        let folded = profiler.folded(&program.symbols);
+        assert!(folded.contains("MAIN;WAIT;HANDLER 3\n"));
+        // The handler returned, so the rest of WAIT and MAIN isn't attributed to it
+        assert!(folded.starts_with("MAIN 4\n"));
+        Ok(())
+    }
+}
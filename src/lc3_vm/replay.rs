@@ -0,0 +1,208 @@
+use super::io_device::IoDevice;
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+/// First line of an input log, with the version of its format
+const LOG_HEADER: &str = "lc3-input 1";
+
+/// A key read by the program and the number of the instruction that read it, counted from 0
+/// since the machine was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub instruction: u64,
+    pub key: u8,
+}
+
+impl InputEvent {
+    /// Formats the event as a line of an input log, like `1523 x61`
+    fn to_line(self) -> String {
+        format!("{} x{:02X}", self.instruction, self.key)
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let (instruction, key) = line.split_once(' ')?;
+        Some(Self {
+            instruction: instruction.parse().ok()?,
+            key: u8::from_str_radix(key.strip_prefix('x')?, 16).ok()?,
+        })
+    }
+}
+
+/// Reads the events of an input log written by `RecordingIo`
+pub fn read_input_log(reader: impl Read) -> io::Result<Vec<InputEvent>> {
+    let mut lines = BufReader::new(reader).lines();
+    if lines.next().transpose()?.as_deref() != Some(LOG_HEADER) {
+        return Err(invalid_log(String::from("not an input log")));
+    }
+    lines
+        .enumerate()
+        .map(|(index, line)| {
+            let line = line?;
+            InputEvent::from_line(&line).ok_or_else(|| {
+                invalid_log(format!(
+                    "invalid event on line {}: {line}",
+                    index.saturating_add(2)
+                ))
+            })
+        })
+        .collect()
+}
+
+fn invalid_log(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Passes everything through to another device and logs every key the program reads, with the
+/// instruction that read it, so the run can be replayed with `ReplayIo`
+pub struct RecordingIo {
+    io: Box<dyn IoDevice>,
+    log: Box<dyn Write>,
+    instruction: u64,
+}
+
+impl RecordingIo {
+    /// Starts the log by writing its header
+    pub fn new(io: Box<dyn IoDevice>, mut log: Box<dyn Write>) -> io::Result<Self> {
+        writeln!(log, "{LOG_HEADER}")?;
+        log.flush()?;
+        Ok(Self {
+            io,
+            log,
+            instruction: 0,
+        })
+    }
+}
+
+impl IoDevice for RecordingIo {
+    fn key_available(&mut self) -> io::Result<bool> {
+        self.io.key_available()
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        let key = self.io.read_key()?;
+        let event = InputEvent {
+            instruction: self.instruction,
+            key,
+        };
+        // Every event is flushed so the log is complete even if the program is killed
+        writeln!(self.log, "{}", event.to_line())?;
+        self.log.flush()?;
+        Ok(key)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.io.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+
+    fn clock(&mut self, instruction: u64) {
+        self.instruction = instruction;
+    }
+}
+
+/// Feeds the keys of an input log to the program at the instructions that read them when it
+/// was recorded, so the run executes exactly like the recorded one. Output goes to another
+/// device, whose input is never read. Reading a key at any other instruction is an error, the
+/// run has diverged from the recorded one
+pub struct ReplayIo {
+    io: Box<dyn IoDevice>,
+    events: VecDeque<InputEvent>,
+    instruction: u64,
+}
+
+impl ReplayIo {
+    pub fn new(io: Box<dyn IoDevice>, events: Vec<InputEvent>) -> Self {
+        Self {
+            io,
+            events: events.into(),
+            instruction: 0,
+        }
+    }
+
+    /// Events that haven't been replayed yet
+    pub fn remaining(&self) -> usize {
+        self.events.len()
+    }
+}
+
+impl IoDevice for ReplayIo {
+    fn key_available(&mut self) -> io::Result<bool> {
+        Ok(self
+            .events
+            .front()
+            .is_some_and(|event| event.instruction <= self.instruction))
+    }
+
+    fn read_key(&mut self) -> io::Result<u8> {
+        let event = self.events.pop_front().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the input log has no keys left",
+            )
+        })?;
+        if event.instruction != self.instruction {
+            return Err(io::Error::other(format!(
+                "replay diverged, key recorded at instruction {} read at instruction {}",
+                event.instruction, self.instruction
+            )));
+        }
+        Ok(event.key)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.io.write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+
+    fn clock(&mut self, instruction: u64) {
+        self.instruction = instruction;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lc3_vm::io_device::{BufferIo, SharedBuffer};
+
+    #[test]
+    fn recorded_keys_are_replayed_at_the_same_instructions(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let log = SharedBuffer::default();
+        let mut recording = RecordingIo::new(
+            Box::new(BufferIo::with_input(b"ab".to_vec())),
+            Box::new(log.clone()),
+        )?;
+        recording.clock(3);
+        assert!(recording.key_available()?);
+        assert_eq!(b'a', recording.read_key()?);
+        recording.clock(42);
+        assert_eq!(b'b', recording.read_key()?);
+        assert_eq!(
+            "lc3-input 1\n3 x61\n42 x62\n",
+            String::from_utf8(log.contents())?
+        );
+
+        let events = read_input_log(log.contents().as_slice())?;
+        let mut replay = ReplayIo::new(Box::new(BufferIo::with_input(Vec::new())), events);
+        replay.clock(2);
+        assert!(!replay.key_available()?);
+        replay.clock(3);
+        assert!(replay.key_available()?);
+        assert_eq!(b'a', replay.read_key()?);
+        replay.clock(41);
+        assert!(replay.read_key().is_err());
+        assert_eq!(0, replay.remaining());
+
+        assert!(read_input_log(&b"lc3-input 1\n3 61\n"[..]).is_err());
+        assert!(read_input_log(&b"3 x61\n"[..]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use super::{
+    exceptions::ExceptionMode,
+    memory_map::{MemoryMap, Segment},
+};
+use std::ops::RangeInclusive;
+use thiserror::Error;
+
+/// Format version written into snapshot files, files with another version are rejected
+pub const SNAPSHOT_VERSION: u16 = 3;
+const MAGIC: [u8; 4] = *b"LC3S";
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SnapshotError {
+    #[error("not a snapshot file")]
+    Magic,
+    #[error("unsupported snapshot version {0}, expected {SNAPSHOT_VERSION}")]
+    Version(u16),
+    #[error("snapshot is truncated")]
+    Truncated,
+    #[error("snapshot checksum mismatch, the file is corrupted")]
+    Checksum,
+    #[error("invalid snapshot: {0}")]
+    Invalid(String),
+}
+
+/// Complete state of a machine: memory, registers, configuration and the state of every
+/// device on its bus, in bus order. Taken with `VM::snapshot` and applied with `VM::restore`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub memory: Vec<u16>,
+    pub registers: [u16; 8],
+    pub pc: u16,
+    pub psr: u16,
+    /// Stack pointers of the supervisor and user modes, the one of the running mode is in R6
+    pub saved_ssp: u16,
+    pub saved_usp: u16,
+    pub running: bool,
+    /// Number of instructions executed by the machine, see `VM::instructions`
+    pub instructions: u64,
+    pub os_loaded: bool,
+    pub exception_mode: ExceptionMode,
+    pub protected: Vec<RangeInclusive<u16>>,
+    pub memory_map: MemoryMap,
+    pub devices: Vec<Vec<u16>>,
+}
+
+impl Snapshot {
+    /// Serializes the snapshot: the `LC3S` magic, the format version and every field as
+    /// big-endian words, lists prefixed by their length, followed by a checksum of everything
+    /// before it
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words = vec![
+            SNAPSHOT_VERSION,
+            self.pc,
+            self.psr,
+            self.saved_ssp,
+            self.saved_usp,
+            self.running.into(),
+            self.os_loaded.into(),
+            match self.exception_mode {
+                ExceptionMode::Stop => 0,
+                ExceptionMode::Trap => 1,
+            },
+        ];
+        words.extend(self.registers);
+        let [a, b, c, d, e, f, g, h] = self.instructions.to_be_bytes();
+        words.extend([
+            u16::from_be_bytes([a, b]),
+            u16::from_be_bytes([c, d]),
+            u16::from_be_bytes([e, f]),
+            u16::from_be_bytes([g, h]),
+        ]);
+        push_list(&mut words, &self.memory);
+        let protected: Vec<u16> = self
+            .protected
+            .iter()
+            .flat_map(|range| [*range.start(), *range.end()])
+            .collect();
+        push_list(&mut words, &protected);
+        let segments = self.memory_map.segments();
+        words.push(u16::try_from(segments.len()).unwrap_or(u16::MAX));
+        for segment in segments {
+            words.extend([segment.range.start, segment.range.end]);
+            let name: Vec<u16> = segment.name.bytes().map(u16::from).collect();
+            push_list(&mut words, &name);
+        }
+        words.push(u16::try_from(self.devices.len()).unwrap_or(u16::MAX));
+        for device in &self.devices {
+            push_list(&mut words, device);
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+        bytes.extend(checksum(&bytes).to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let body_length = bytes.len().checked_sub(4).ok_or(SnapshotError::Truncated)?;
+        let (body, sum) = bytes.split_at(body_length);
+        let magic = body.get(..MAGIC.len()).ok_or(SnapshotError::Truncated)?;
+        if magic != MAGIC {
+            return Err(SnapshotError::Magic);
+        }
+        let mut reader = Reader {
+            bytes: body.get(MAGIC.len()..).unwrap_or_default(),
+        };
+        let version = reader.word()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+        let sum = <[u8; 4]>::try_from(sum).map_err(|_| SnapshotError::Truncated)?;
+        if u32::from_be_bytes(sum) != checksum(body) {
+            return Err(SnapshotError::Checksum);
+        }
+
+        let pc = reader.word()?;
+        let psr = reader.word()?;
+        let saved_ssp = reader.word()?;
+        let saved_usp = reader.word()?;
+        let running = reader.word()? != 0;
+        let os_loaded = reader.word()? != 0;
+        let exception_mode = match reader.word()? {
+            0 => ExceptionMode::Stop,
+            1 => ExceptionMode::Trap,
+            mode => {
+                return Err(SnapshotError::Invalid(format!(
+                    "unknown exception mode {mode}"
+                )))
+            }
+        };
+        let mut registers = [0; 8];
+        for register in registers.iter_mut() {
+            *register = reader.word()?;
+        }
+        let [a, b] = reader.word()?.to_be_bytes();
+        let [c, d] = reader.word()?.to_be_bytes();
+        let [e, f] = reader.word()?.to_be_bytes();
+        let [g, h] = reader.word()?.to_be_bytes();
+        let instructions = u64::from_be_bytes([a, b, c, d, e, f, g, h]);
+        let memory = reader.list()?;
+        let protected = reader
+            .list()?
+            .chunks(2)
+            .map(|range| match range {
+                [start, end] if start <= end => Ok(*start..=*end),
+                _ => Err(SnapshotError::Invalid(String::from(
+                    "invalid protected range",
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        let mut memory_map = MemoryMap::default();
+        for _ in 0..reader.word()? {
+            let range = reader.word()?..reader.word()?;
+            let name = reader
+                .list()?
+                .into_iter()
+                .map(u8::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .and_then(|name| String::from_utf8(name).ok())
+                .ok_or_else(|| SnapshotError::Invalid(String::from("invalid segment name")))?;
+            memory_map
+                .insert(Segment { name, range })
+                .map_err(SnapshotError::Invalid)?;
+        }
+        let device_count = reader.word()?;
+        let devices = (0..device_count)
+            .map(|_| reader.list())
+            .collect::<Result<_, _>>()?;
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::Invalid(String::from(
+                "unexpected data after the devices",
+            )));
+        }
+        Ok(Self {
+            memory,
+            registers,
+            pc,
+            psr,
+            saved_ssp,
+            saved_usp,
+            running,
+            instructions,
+            os_loaded,
+            exception_mode,
+            protected,
+            memory_map,
+            devices,
+        })
+    }
+}
+
+/// Appends a list prefixed by its length. The length takes two words because memory holds one
+/// word more than a word can count
+fn push_list(words: &mut Vec<u16>, list: &[u16]) {
+    let [a, b, c, d] = u32::try_from(list.len()).unwrap_or(u32::MAX).to_be_bytes();
+    words.extend([u16::from_be_bytes([a, b]), u16::from_be_bytes([c, d])]);
+    words.extend_from_slice(list);
+}
+
+/// FNV-1a hash of the bytes
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811C_9DC5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn word(&mut self) -> Result<u16, SnapshotError> {
+        let (word, rest) = self
+            .bytes
+            .split_first_chunk::<2>()
+            .ok_or(SnapshotError::Truncated)?;
+        self.bytes = rest;
+        Ok(u16::from_be_bytes(*word))
+    }
+
+    fn list(&mut self) -> Result<Vec<u16>, SnapshotError> {
+        let [a, b] = self.word()?.to_be_bytes();
+        let [c, d] = self.word()?.to_be_bytes();
+        (0..u32::from_be_bytes([a, b, c, d]))
+            .map(|_| self.word())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snapshots_round_trip_and_detect_corruption() -> Result<(), Box<dyn std::error::Error>> {
+        let mut memory_map = MemoryMap::default();
+        memory_map.insert(Segment {
+            name: String::from("OS"),
+            range: 0x0000..0x0200,
+        })?;
+        memory_map.insert(Segment {
+            name: String::from("rogue.obj"),
+            range: 0x3000..0x3400,
+        })?;
+        let snapshot = Snapshot {
+            memory: vec![0x1234; 1 << 16],
+            registers: [1, 2, 3, 4, 5, 6, 7, 8],
+            pc: 0x3005,
+            psr: 0x8002,
+            saved_ssp: 0x3000,
+            saved_usp: 0,
+            running: true,
+            instructions: 0x0001_2345_6789_ABCD,
+            os_loaded: true,
+            exception_mode: ExceptionMode::Trap,
+            protected: vec![0x0000..=0x2FFF, 0xFE00..=0xFFFF],
+            memory_map,
+            devices: vec![vec![0x8000, 0x61], Vec::new()],
+        };
+        let mut bytes = snapshot.to_bytes();
+        assert_eq!(Some(&b"LC3S\x00\x03"[..]), bytes.get(..6));
+        let restored = Snapshot::from_bytes(&bytes)?;
+        assert_eq!(0x0001_2345_6789_ABCD, restored.instructions);
+        assert_eq!(
+            snapshot.memory_map.segments(),
+            restored.memory_map.segments()
+        );
+        assert_eq!(snapshot, restored);
+
+        if let Some(byte) = bytes.get_mut(100) {
+            *byte ^= 1;
+        }
+        assert_eq!(Err(SnapshotError::Checksum), Snapshot::from_bytes(&bytes));
+        if let Some(byte) = bytes.get_mut(5) {
+            *byte = 9;
+        }
+        assert_eq!(Err(SnapshotError::Version(9)), Snapshot::from_bytes(&bytes));
+        assert_eq!(
+            Err(SnapshotError::Magic),
+            Snapshot::from_bytes(b"LC3X\x00\x01\x00\x00")
+        );
+        assert_eq!(Err(SnapshotError::Truncated), Snapshot::from_bytes(b"LC3"));
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, fmt::Write as _};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SymbolError {
+    #[error("line {0}: expected a symbol table comment, found {1}")]
+    InvalidLine(usize, String),
+}
+
+/// Reads a symbol file in the format written by lc3as: comment lines, some of them with a
+/// label and its address in hexadecimal
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //    Symbol Name       Page Address
+/// //    ----------------  ------------
+/// //    LOOP              3001
+/// ```
+pub fn parse_symbol_file(text: &str) -> Result<BTreeMap<String, u16>, SymbolError> {
+    let mut symbols = BTreeMap::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(comment) = line.trim_start().strip_prefix("//") else {
+            return Err(SymbolError::InvalidLine(
+                index.saturating_add(1),
+                line.to_string(),
+            ));
+        };
+        // The header lines never end with a hexadecimal number, so they are skipped too
+        let fields: Vec<&str> = comment.split_whitespace().collect();
+        if let [label, address] = fields.as_slice() {
+            if let Ok(address) = u16::from_str_radix(address, 16) {
+                symbols.insert(label.to_string(), address);
+            }
+        }
+    }
+    Ok(symbols)
+}
+
+/// Renders a symbol file in the lc3as format, sorted by address
+pub fn symbol_file(symbols: &BTreeMap<String, u16>) -> String {
+    let mut sorted: Vec<(&String, &u16)> = symbols.iter().collect();
+    sorted.sort_by_key(|(label, address)| (**address, *label));
+    let mut file = String::from(
+        "// Symbol table\n// Scope level 0:\n//\tSymbol Name       Page Address\n//\t----------------  ------------\n",
+    );
+    for (label, address) in sorted {
+        let _ = writeln!(file, "//\t{label:<16}  {address:04X}");
+    }
+    file
+}
+
+/// Names an address after the closest label at or before it, like `LOOP+2`. Labels only name
+/// addresses of their own region of the memory map, so the last label of the OS doesn't name
+/// the whole user space
+pub fn location(symbols: &BTreeMap<String, u16>, address: u16) -> Option<String> {
+    symbols
+        .iter()
+        .filter(|(_, label_address)| {
+            **label_address <= address && region(**label_address) == region(address)
+        })
+        .max_by_key(|(_, label_address)| **label_address)
+        .map(
+            |(label, label_address)| match address.wrapping_sub(*label_address) {
+                0 => label.clone(),
+                offset => format!("{label}+{offset}"),
+            },
+        )
+}
+
+/// System space, user space or device registers
+fn region(address: u16) -> u8 {
+    match address {
+        0x0000..=0x2FFF => 0,
+        0x3000..=0xFDFF => 1,
+        0xFE00..=0xFFFF => 2,
+    }
+}
+
+/// Renders an address with its location when a label comes before it, like `LOOP+2 (x3003)`
+pub fn describe(symbols: &BTreeMap<String, u16>, address: u16) -> String {
+    match location(symbols, address) {
+        Some(location) => format!("{location} (x{address:04X})"),
+        None => format!("x{address:04X}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn symbol_files_are_read_and_written() -> Result<(), Box<dyn std::error::Error>> {
+        let text = "\
+// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tMAIN              3000
+//\tLOOP              3001
+//\tDATA              3010
+
+";
+        let symbols = parse_symbol_file(text)?;
+        assert_eq!(
+            BTreeMap::from([
+                (String::from("DATA"), 0x3010),
+                (String::from("LOOP"), 0x3001),
+                (String::from("MAIN"), 0x3000),
+            ]),
+            symbols
+        );
+        assert_eq!(text.trim_end(), symbol_file(&symbols).trim_end());
+        assert_eq!(
+            Err(SymbolError::InvalidLine(2, String::from("LOOP 3001"))),
+            parse_symbol_file("// Symbol table\nLOOP 3001\n")
+        );
+
+        assert_eq!("MAIN (x3000)", describe(&symbols, 0x3000));
+        assert_eq!("LOOP+2 (x3003)", describe(&symbols, 0x3003));
+        assert_eq!("x2FFF", describe(&symbols, 0x2FFF));
+        assert_eq!("xFE00", describe(&symbols, 0xFE00));
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use super::{
+    flags::ConditionFlags, opcodes::Opcode, registers::Register, virtual_machine::MemoryAccess,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+/// How trace entries are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// One aligned line per instruction, meant to be read
+    #[default]
+    Text,
+    /// One JSON object per line, meant to be diffed and processed by other tools
+    JsonLines,
+}
+
+/// A register whose value was changed by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub register: Register,
+    pub previous: u16,
+    pub value: u16,
+}
+
+/// Effects of one executed instruction
+#[derive(Debug, PartialEq)]
+pub struct TraceEntry {
+    /// Number of instructions executed before this one since tracing started
+    pub step: u64,
+    pub pc: u16,
+    /// Label the PC is at or after, like `LOOP+2`, when the program has symbols
+    pub location: Option<String>,
+    pub instruction: u16,
+    /// None when the word isn't a valid instruction
+    pub opcode: Option<Opcode>,
+    pub registers: Vec<RegisterChange>,
+    /// Condition codes after the instruction
+    pub condition: ConditionFlags,
+    /// Only the `MemoryAccess::Write` accesses of the instruction
+    pub writes: Vec<MemoryAccess>,
+}
+
+impl TraceEntry {
+    /// Formats the entry as a single line without the line break
+    pub fn format(&self, format: TraceFormat) -> String {
+        match format {
+            TraceFormat::Text => self.text(),
+            TraceFormat::JsonLines => self.json(),
+        }
+    }
+
+    fn assembly(&self) -> String {
+        match &self.opcode {
+            Some(opcode) => opcode.to_string(),
+            None => String::from("(illegal)"),
+        }
+    }
+
+    fn text(&self) -> String {
+        let pc = match &self.location {
+            Some(location) => format!("{location} (x{:04X})", self.pc),
+            None => format!("x{:04X}", self.pc),
+        };
+        let mut line = format!(
+            "{:>8}  {}  x{:04X}  {:<20}  {}",
+            self.step,
+            pc,
+            self.instruction,
+            self.assembly(),
+            self.condition
+        );
+        for change in &self.registers {
+            let _ = write!(
+                line,
+                "  {} x{:04X}->x{:04X}",
+                change.register, change.previous, change.value
+            );
+        }
+        for write in &self.writes {
+            if let MemoryAccess::Write {
+                address,
+                previous,
+                value,
+            } = write
+            {
+                let _ = write!(line, "  [x{address:04X}] x{previous:04X}->x{value:04X}");
+            }
+        }
+        line
+    }
+
+    fn json(&self) -> String {
+        let opcode = match &self.opcode {
+            Some(opcode) => json_string(&opcode.to_string()),
+            None => String::from("null"),
+        };
+        let location = match &self.location {
+            Some(location) => json_string(location),
+            None => String::from("null"),
+        };
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .map(|change| {
+                format!(
+                    "\"{}\":[{},{}]",
+                    change.register, change.previous, change.value
+                )
+            })
+            .collect();
+        let writes: Vec<String> = self
+            .writes
+            .iter()
+            .filter_map(|write| match write {
+                MemoryAccess::Write {
+                    address,
+                    previous,
+                    value,
+                } => Some(format!(
+                    "{{\"address\":{address},\"previous\":{previous},\"value\":{value}}}"
+                )),
+                MemoryAccess::Read { .. } => None,
+            })
+            .collect();
+        format!(
+            "{{\"step\":{},\"pc\":{},\"location\":{},\"instruction\":{},\"opcode\":{},\"registers\":{{{}}},\"condition\":\"{}\",\"writes\":[{}]}}",
+            self.step,
+            self.pc,
+            location,
+            self.instruction,
+            opcode,
+            registers.join(","),
+            self.condition,
+            writes.join(",")
+        )
+    }
+}
+
+/// Quotes a string for JSON output
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            _ if character.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", u32::from(character));
+            }
+            _ => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes a trace entry for every instruction the VM executes, optionally only for
+/// instructions inside an address range or a window of steps. Attached with `VM::set_trace`
+pub struct Trace {
+    output: Box<dyn Write>,
+    format: TraceFormat,
+    range: Option<RangeInclusive<u16>>,
+    window: Option<RangeInclusive<u64>>,
+    step: u64,
+}
+
+impl Trace {
+    pub fn new(output: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            output,
+            format,
+            range: None,
+            window: None,
+            step: 0,
+        }
+    }
+
+    /// Only traces instructions whose address is inside the range
+    pub fn set_range(&mut self, range: Option<RangeInclusive<u16>>) {
+        self.range = range;
+    }
+
+    /// Only traces the steps inside the window. Steps count every instruction executed since
+    /// the trace was attached, starting at 0
+    pub fn set_window(&mut self, window: Option<RangeInclusive<u64>>) {
+        self.window = window;
+    }
+
+    /// Step number of the next instruction
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// Counts an executed instruction and writes its entry when it passes the filters.
+    /// `entry` is only called for instructions that are written
+    pub(super) fn record(
+        &mut self,
+        pc: u16,
+        entry: impl FnOnce(u64) -> TraceEntry,
+    ) -> io::Result<()> {
+        let step = self.step;
+        self.step = self.step.wrapping_add(1);
+        let in_range = self.range.as_ref().is_none_or(|range| range.contains(&pc));
+        let in_window = self
+            .window
+            .as_ref()
+            .is_none_or(|window| window.contains(&step));
+        if !in_range || !in_window {
+            return Ok(());
+        }
+        writeln!(self.output, "{}", entry(step).format(self.format))
+    }
+
+    pub(super) fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entries_are_formatted_as_text_and_json() -> Result<(), Box<dyn std::error::Error>> {
+        let entry = TraceEntry {
+            step: 3,
+            pc: 0x3001,
+            location: Some(String::from("LOOP+1")),
+            instruction: 0x7040,
+            opcode: Some(Opcode::try_from(0x7040)?),
+            registers: vec![RegisterChange {
+                register: Register::R6,
+                previous: 0x4000,
+                value: 0x3FFF,
+            }],
+            condition: ConditionFlags::POS,
+            writes: vec![MemoryAccess::Write {
+                address: 0x4000,
+                previous: 0,
+                value: 0x0041,
+            }],
+        };
+        assert_eq!(
+            "       3  LOOP+1 (x3001)  x7040  STR R0, R1, #0        p  R6 x4000->x3FFF  [x4000] x0000->x0041",
+            entry.format(TraceFormat::Text)
+        );
+        assert_eq!(
+            "{\"step\":3,\"pc\":12289,\"location\":\"LOOP+1\",\"instruction\":28736,\"opcode\":\"STR R0, R1, #0\",\"registers\":{\"R6\":[16384,16383]},\"condition\":\"p\",\"writes\":[{\"address\":16384,\"previous\":0,\"value\":65}]}",
+            entry.format(TraceFormat::JsonLines)
+        );
+        assert_eq!("\"a\\\"b\\u000a\"", json_string("a\"b\n"));
+        Ok(())
+    }
+}
@@ -13,6 +13,9 @@ pub enum Trap {
     In,
     Putsp,
     Halt,
+    ReadSector,
+    WriteSector,
+    SetVector,
 }
 
 impl TryFrom<u8> for Trap {
@@ -24,9 +27,28 @@ impl TryFrom<u8> for Trap {
             0x23 => Ok(Trap::In),
             0x24 => Ok(Trap::Putsp),
             0x25 => Ok(Trap::Halt),
+            0x26 => Ok(Trap::ReadSector),
+            0x27 => Ok(Trap::WriteSector),
+            0x28 => Ok(Trap::SetVector),
             _ => Err(TrapError::InvalidTrap(value)),
         }
     }
 
     type Error = TrapError;
 }
+
+impl From<Trap> for u8 {
+    fn from(trap: Trap) -> Self {
+        match trap {
+            Trap::GetC => 0x20,
+            Trap::Out => 0x21,
+            Trap::Puts => 0x22,
+            Trap::In => 0x23,
+            Trap::Putsp => 0x24,
+            Trap::Halt => 0x25,
+            Trap::ReadSector => 0x26,
+            Trap::WriteSector => 0x27,
+            Trap::SetVector => 0x28,
+        }
+    }
+}
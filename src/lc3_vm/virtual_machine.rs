@@ -1,23 +1,50 @@
 use super::{
-    flags::ConditionFlags,
+    block_storage::{
+        COMMAND_READ, COMMAND_WRITE, MR_BLKADDR, MR_BLKCMD, MR_BLKSEC, MR_BLKSR, STATUS_ERROR,
+    },
+    devices::{
+        Device, DeviceContext, Display, InterruptRequest, Keyboard, MachineControl, Timer,
+        DEVICE_PAGE, STATUS_READY,
+    },
+    disassembler::{self, DisassembledWord},
+    exceptions::{Exception, ExceptionMode},
+    flags::{ConditionFlags, Privilege, PSR_CONDITION, PSR_PRIORITY, PSR_USER},
+    history::{History, ReverseStop, Undo},
+    io_device::{IoDevice, TerminalIo},
+    memory_map::{MemoryMap, Segment},
     opcodes::{Opcode, OpcodeError},
+    os,
+    registers::Register,
+    snapshot::Snapshot,
+    symbols::{describe, location},
+    trace::{RegisterChange, Trace, TraceEntry},
     traps::Trap,
 };
-use nix::sys::{
-    select,
-    time::{TimeVal, TimeValLike},
-};
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fmt::Debug,
-    fs::File,
-    io::{self, Read, Write},
-    os::fd::AsFd,
+    ops::{Range, RangeInclusive},
 };
 use thiserror::Error;
 
 const MEMORY_MAX: usize = 1 << 16;
-const MR_KBSR: u16 = 0xFE00;
-const MR_KBDR: u16 = 0xFE02;
+const MR_MCR: u16 = 0xFFFE;
+/// Clock enable bit of the machine control register, the machine stops when it is cleared
+const MCR_CLOCK_ENABLE: u16 = 0b1000_0000_0000_0000;
+/// Fault bit of the machine control register, the OS sets it when it stops the machine because
+/// of an exception or a trap or interrupt without a service routine
+const MCR_FAULT: u16 = 0b0000_0000_0000_0001;
+/// Memory that user mode programs can't access by default: system space and the device
+/// register page
+const PROTECTED_RANGES: [RangeInclusive<u16>; 2] = [0x0000..=0x2FFF, 0xFE00..=0xFFFF];
+/// Interrupt vector table entries of device interrupts, the ones below belong to exceptions
+const DEVICE_VECTORS: RangeInclusive<u16> = 0x0180..=0x01FF;
+/// Offset of the last word of a sector buffer from its start
+const SECTOR_LAST: u16 = 255;
+/// Number of protected ranges the table of the OS image has room for
+const OS_PROTECTED_CAPACITY: usize = 16;
+/// Initial supervisor stack pointer, the stack grows down from the start of user space
+const INITIAL_SSP: u16 = 0x3000;
 
 #[derive(Error, Debug)]
 pub enum VMError {
@@ -39,6 +66,52 @@ pub enum VMError {
     Execute(String),
     #[error("Memory failure: {0}")]
     Memory(String),
+    #[error("Failed to service interrupt: {0}")]
+    Interrupt(String),
+    #[error("Device failure: {0}")]
+    Device(String),
+    #[error("Exception: {0}")]
+    Exception(String),
+    #[error("Failed to write trace: {0}")]
+    Trace(String),
+    #[error("Failed to restore snapshot: {0}")]
+    Snapshot(String),
+    #[error("Failed to step back: {0}")]
+    History(String),
+}
+
+/// Snapshot of the processor registers
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuState {
+    pub registers: [u16; 8],
+    pub pc: u16,
+    pub condition: ConditionFlags,
+    pub psr: u16,
+    pub running: bool,
+}
+
+/// A data access performed by the last executed instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryAccess {
+    Read {
+        address: u16,
+        value: u16,
+    },
+    Write {
+        address: u16,
+        previous: u16,
+        value: u16,
+    },
+}
+
+/// Handlers the last step entered without its instruction calling them, by the address of
+/// their first instruction
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(super) struct Entries {
+    /// Device interrupt taken before the instruction was fetched
+    pub(super) interrupt: Option<u16>,
+    /// Exception raised by the instruction, or by its fetch
+    pub(super) exception: Option<u16>,
 }
 
 pub struct VM {
@@ -52,12 +125,39 @@ pub struct VM {
     r6: u16,
     r7: u16,
     pc: u16,
-    cond: u16,
+    psr: u16,
+    /// R6 of the mode that isn't running, swapped in when the privilege mode changes
+    saved_ssp: u16,
+    saved_usp: u16,
+    accesses: Vec<MemoryAccess>,
+    io: Box<dyn IoDevice>,
+    devices: Vec<Box<dyn Device>>,
+    os_loaded: bool,
+    exception_mode: ExceptionMode,
+    protected: Vec<RangeInclusive<u16>>,
+    /// Address and word of the last fetched instruction
+    fetched: Option<(u16, u16)>,
+    entries: Entries,
+    trace: Option<Trace>,
+    history: Option<History>,
+    /// Number of the next instruction, counting every instruction executed so far
+    instructions: u64,
+    /// Labels used to name addresses in error messages and traces
+    symbols: BTreeMap<String, u16>,
+    memory_map: MemoryMap,
     pub running: bool,
 }
 
 impl Default for VM {
     fn default() -> Self {
+        Self::new(Box::new(TerminalIo))
+    }
+}
+
+impl VM {
+    /// Creates a VM whose keyboard and console are backed by the given device, with the
+    /// keyboard, display, timer and machine control register on its device bus
+    pub fn new(io: Box<dyn IoDevice>) -> Self {
         Self {
             memory: [0; MEMORY_MAX],
             r0: 0,
@@ -66,24 +166,80 @@ impl Default for VM {
             r3: 0,
             r4: 0,
             r5: 0,
-            r6: 0,
+            // The machine starts in supervisor mode, so R6 is the supervisor stack pointer
+            r6: INITIAL_SSP,
             r7: 0,
             pc: 0x3000,
-            cond: 0,
+            // The machine starts in supervisor mode with the Z flag set
+            psr: ConditionFlags::ZRO.into(),
+            saved_ssp: INITIAL_SSP,
+            saved_usp: 0,
+            accesses: Vec::new(),
+            io,
+            devices: vec![
+                Box::new(Keyboard::default()),
+                Box::new(Display::default()),
+                Box::new(Timer::default()),
+                Box::new(MachineControl::default()),
+            ],
+            os_loaded: false,
+            exception_mode: ExceptionMode::default(),
+            protected: PROTECTED_RANGES.to_vec(),
+            fetched: None,
+            entries: Entries::default(),
+            trace: None,
+            history: None,
+            instructions: 0,
+            symbols: BTreeMap::new(),
+            memory_map: MemoryMap::default(),
             running: false,
         }
     }
-}
 
-impl VM {
-    pub fn load_program(&mut self, file_name: &str) -> Result<(), VMError> {
+    /// Connects a peripheral to the device bus. Its registers must be in the device page and
+    /// can't overlap the ones of another device
+    pub fn add_device(&mut self, device: Box<dyn Device>) -> Result<(), VMError> {
+        let registers = device.registers();
+        if !DEVICE_PAGE.contains(registers.start()) || !DEVICE_PAGE.contains(registers.end()) {
+            return Err(VMError::Device(format!(
+                "registers x{:04X}-x{:04X} are outside the device page",
+                registers.start(),
+                registers.end()
+            )));
+        }
+        let overlaps = self.devices.iter().any(|other| {
+            let other = other.registers();
+            registers.start() <= other.end() && other.start() <= registers.end()
+        });
+        if overlaps {
+            return Err(VMError::Device(format!(
+                "registers x{:04X}-x{:04X} are already in use",
+                registers.start(),
+                registers.end()
+            )));
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    /// Loads an object file into memory and returns the range of addresses it occupies. It is
+    /// named after the file in the memory map
+    pub fn load_program(&mut self, file_name: &str) -> Result<Range<u16>, VMError> {
         let bytes = &std::fs::read(file_name)
             .map_err(|err| VMError::LoadProgram(format!("failed to read file: {}", err)))?;
-        self.load_bytes(bytes)?;
-        Ok(())
+        self.load_segment(file_name, bytes)
+    }
+
+    /// Loads the contents of an object file into memory and returns the range of addresses it
+    /// occupies
+    pub fn load_bytes(&mut self, bytes: &[u8]) -> Result<Range<u16>, VMError> {
+        self.load_segment("program", bytes)
     }
 
-    fn load_bytes(&mut self, bytes: &[u8]) -> Result<(), VMError> {
+    /// Loads the contents of an object file into memory under the given name in the memory
+    /// map and returns the range of addresses it occupies. Several object files can be loaded,
+    /// but loading one over memory already loaded from another is an error
+    pub fn load_segment(&mut self, name: &str, bytes: &[u8]) -> Result<Range<u16>, VMError> {
         let mut loaded_memory = Vec::new();
         let mut memory_chunks = bytes.chunks_exact(2);
 
@@ -113,15 +269,253 @@ impl VM {
                     "not enough memory to load the program",
                 )))?;
 
+        let range = origin..last_memory_position;
+        self.memory_map
+            .insert(Segment {
+                name: name.to_string(),
+                range: range.clone(),
+            })
+            .map_err(VMError::LoadProgram)?;
+        self.write_memory(origin, &loaded_memory).map_err(|err| {
+            VMError::LoadProgram(format!("failed to write into VM memory: {}", err))
+        })?;
+        Ok(range)
+    }
+
+    /// Segments loaded into memory, by address
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.memory_map
+    }
+
+    /// Loads the bundled operating system image. From then on TRAP enters supervisor mode and
+    /// jumps to the service routine in the trap vector table instead of being handled natively,
+    /// programs run in user mode and exceptions are handled by the OS
+    pub fn load_os(&mut self) -> Result<(), VMError> {
+        let image = os::image()
+            .map_err(|err| VMError::LoadProgram(format!("failed to assemble OS: {}", err)))?;
+        self.load_segment("OS", &image.to_bytes())?;
+        self.share_protected_ranges(&image.symbols)?;
+        self.set_privilege(Privilege::User);
+        // The OS hands the machine over with an empty supervisor stack
+        self.saved_ssp = INITIAL_SSP;
+        self.exception_mode = ExceptionMode::Trap;
+        self.os_loaded = true;
+        Ok(())
+    }
+
+    /// Returns true when traps are handled by the service routines of the OS image
+    pub fn os_loaded(&self) -> bool {
+        self.os_loaded
+    }
+
+    /// Returns true when the machine was stopped with the fault bit of the machine control
+    /// register set, like the OS does for exceptions, instead of halting normally
+    pub fn faulted(&self) -> bool {
+        self.peek_word(MR_MCR) & MCR_FAULT != 0
+    }
+
+    pub fn exception_mode(&self) -> ExceptionMode {
+        self.exception_mode
+    }
+
+    pub fn set_exception_mode(&mut self, mode: ExceptionMode) {
+        self.exception_mode = mode;
+    }
+
+    /// Ranges of memory that raise an access control violation when accessed in user mode
+    pub fn protected_ranges(&self) -> &[RangeInclusive<u16>] {
+        &self.protected
+    }
+
+    /// Replaces the protected ranges, an empty list lets user mode access all of memory
+    pub fn set_protected_ranges(&mut self, ranges: Vec<RangeInclusive<u16>>) {
+        self.protected = ranges;
+        if self.os_loaded {
+            if let Ok(image) = os::image() {
+                // The table is part of the image, so the labels are always found
+                self.share_protected_ranges(&image.symbols).ok();
+            }
+        }
+    }
+
+    /// Copies the protected ranges into the table of the OS image, so its service routines can
+    /// refuse buffers of user mode programs in protected memory. When the ranges don't fit all
+    /// of memory is marked as protected
+    fn share_protected_ranges(&mut self, symbols: &BTreeMap<String, u16>) -> Result<(), VMError> {
+        let missing = || VMError::LoadProgram(String::from("OS has no protected range table"));
+        let count = *symbols.get("OS_PROTECTED_COUNT").ok_or_else(missing)?;
+        let table = *symbols.get("OS_PROTECTED_RANGES").ok_or_else(missing)?;
+        let ranges = if self.protected.len() <= OS_PROTECTED_CAPACITY {
+            self.protected.clone()
+        } else {
+            vec![0x0000..=0xFFFF]
+        };
+        let words: Vec<u16> = ranges
+            .iter()
+            .flat_map(|range| [*range.start(), *range.end()])
+            .collect();
+        self.write_memory(count, &[u16::try_from(ranges.len()).unwrap_or_default()])?;
+        self.write_memory(table, &words)
+    }
+
+    pub fn reg(&self, register: Register) -> u16 {
+        self.get_register_value(register.into()).unwrap_or_default()
+    }
+
+    pub fn set_reg(&mut self, register: Register, value: u16) {
+        if let Ok(register_value) = self.get_register(register.into()) {
+            *register_value = value;
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value;
+    }
+
+    pub fn condition(&self) -> ConditionFlags {
+        self.psr.into()
+    }
+
+    /// Processor status register: privilege mode, priority level and condition codes
+    pub fn psr(&self) -> u16 {
+        self.psr
+    }
+
+    /// Replaces the processor status register, swapping the stack pointers when the privilege
+    /// mode changes
+    pub fn set_psr(&mut self, value: u16) {
+        self.set_privilege(value.into());
+        self.psr = value;
+    }
+
+    pub fn privilege(&self) -> Privilege {
+        self.psr.into()
+    }
+
+    pub fn priority(&self) -> u16 {
+        (self.psr & PSR_PRIORITY) >> 8
+    }
+
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            registers: Register::ALL.map(|register| self.reg(register)),
+            pc: self.pc,
+            condition: self.condition(),
+            psr: self.psr,
+            running: self.running,
+        }
+    }
+
+    /// Captures the complete state of the machine, including its configuration and devices
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.to_vec(),
+            registers: Register::ALL.map(|register| self.reg(register)),
+            pc: self.pc,
+            psr: self.psr,
+            saved_ssp: self.saved_ssp,
+            saved_usp: self.saved_usp,
+            running: self.running,
+            instructions: self.instructions,
+            os_loaded: self.os_loaded,
+            exception_mode: self.exception_mode,
+            protected: self.protected.clone(),
+            memory_map: self.memory_map.clone(),
+            devices: self.devices.iter().map(|device| device.save()).collect(),
+        }
+    }
+
+    /// Returns the machine to a snapshot. The snapshot must come from a machine with the same
+    /// devices, its state is left untouched when it doesn't
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), VMError> {
+        if snapshot.memory.len() != MEMORY_MAX {
+            return Err(VMError::Snapshot(format!(
+                "expected {MEMORY_MAX} words of memory, found {}",
+                snapshot.memory.len()
+            )));
+        }
+        if snapshot.devices.len() != self.devices.len() {
+            return Err(VMError::Snapshot(format!(
+                "expected {} devices, found {}",
+                self.devices.len(),
+                snapshot.devices.len()
+            )));
+        }
+        // Devices are restored first, they are the only part that can reject its state
+        let saved: Vec<Vec<u16>> = self.devices.iter().map(|device| device.save()).collect();
+        let failed = self
+            .devices
+            .iter_mut()
+            .zip(snapshot.devices.iter())
+            .enumerate()
+            .find_map(|(index, (device, state))| {
+                device.restore(state).err().map(|err| {
+                    let message = format!("device at x{:04X}: {}", device.registers().start(), err);
+                    (index, message)
+                })
+            });
+        if let Some((index, message)) = failed {
+            for (device, state) in self.devices.iter_mut().zip(saved.iter()).take(index) {
+                device.restore(state).ok();
+            }
+            return Err(VMError::Snapshot(message));
+        }
+        self.memory.copy_from_slice(&snapshot.memory);
+        for (register, value) in Register::ALL.iter().zip(snapshot.registers) {
+            self.set_reg(*register, value);
+        }
+        self.pc = snapshot.pc;
+        self.psr = snapshot.psr;
+        self.saved_ssp = snapshot.saved_ssp;
+        self.saved_usp = snapshot.saved_usp;
+        self.running = snapshot.running;
+        self.instructions = snapshot.instructions;
+        self.os_loaded = snapshot.os_loaded;
+        self.exception_mode = snapshot.exception_mode;
+        self.protected = snapshot.protected.clone();
+        self.memory_map = snapshot.memory_map.clone();
+        Ok(())
+    }
+
+    /// Reads `length` words of memory starting at `start`, without triggering memory mapped
+    /// devices
+    pub fn read_memory(&self, start: u16, length: usize) -> Result<&[u16], VMError> {
+        let start = usize::from(start);
+        let end = start
+            .checked_add(length)
+            .ok_or(VMError::Memory(String::from("range overflows memory")))?;
         self.memory
-            .get_mut(origin.into()..last_memory_position.into())
-            .ok_or(VMError::LoadProgram(String::from(
-                "failed to write into VM memory",
-            )))?
-            .copy_from_slice(&loaded_memory);
+            .get(start..end)
+            .ok_or(VMError::Memory(String::from("range outside of memory")))
+    }
+
+    /// Writes consecutive words into memory starting at `start`, without triggering memory
+    /// mapped devices
+    pub fn write_memory(&mut self, start: u16, words: &[u16]) -> Result<(), VMError> {
+        let start = usize::from(start);
+        let end = start
+            .checked_add(words.len())
+            .ok_or(VMError::Memory(String::from("range overflows memory")))?;
+        self.memory
+            .get_mut(start..end)
+            .ok_or(VMError::Memory(String::from("range outside of memory")))?
+            .copy_from_slice(words);
         Ok(())
     }
 
+    /// Disassembles the words stored in the given range of memory
+    pub fn disassemble(&self, range: Range<u16>) -> Vec<DisassembledWord> {
+        let words = self
+            .memory
+            .get(usize::from(range.start)..usize::from(range.end))
+            .unwrap_or_default();
+        disassembler::disassemble(range.start, words)
+    }
+
     fn join_bytes(bytes: &[u8]) -> Option<u16> {
         let first_byte = bytes.first()?;
         let first_byte: u16 = (*first_byte).into();
@@ -134,53 +528,440 @@ impl VM {
         Some(joined_bytes)
     }
 
+    /// Runs the loaded program until it halts
+    pub fn run(&mut self) -> Result<(), VMError> {
+        self.run_with(|_| {})
+    }
+
+    /// Runs the loaded program until it halts, calling `observer` after every executed
+    /// instruction. The observer can stop the machine by clearing `running`
+    pub fn run_with(&mut self, mut observer: impl FnMut(&mut VM)) -> Result<(), VMError> {
+        self.running = true;
+        // Setting the clock enable bit again resumes a machine that was halted, a fault belongs
+        // to the previous run
+        let control = (self.peek_word(MR_MCR) | MCR_CLOCK_ENABLE) & !MCR_FAULT;
+        self.write_device(MR_MCR, control)?;
+        while self.running {
+            self.next_instruction()?;
+            observer(self);
+        }
+        Ok(())
+    }
+
+    /// Writes a trace entry for every executed instruction from now on, None stops tracing
+    pub fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    /// Names addresses in error messages and traces after these labels, like `LOOP+2 (x3003)`
+    pub fn set_symbols(&mut self, symbols: BTreeMap<String, u16>) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &BTreeMap<String, u16> {
+        &self.symbols
+    }
+
+    /// Records every executed instruction from now on so it can be undone, None stops
+    /// recording
+    pub fn set_history(&mut self, history: Option<History>) {
+        self.history = history;
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Undoes up to `count` of the last executed instructions and returns how many were undone,
+    /// fewer when the history runs out
+    pub fn step_back(&mut self, count: u64) -> Result<u64, VMError> {
+        let history = self
+            .history
+            .as_mut()
+            .ok_or(VMError::History(String::from("no history is recorded")))?;
+        let mut undone: u64 = 0;
+        if let Some((snapshot, skipped)) = history.rewind(count) {
+            // Undone instructions still count as executed
+            let instructions = self.instructions;
+            self.restore(&snapshot)?;
+            self.instructions = instructions;
+            undone = skipped;
+        }
+        while undone < count {
+            let Some(undo) = self.history.as_mut().and_then(History::pop) else {
+                break;
+            };
+            self.undo(undo)?;
+            undone = undone.saturating_add(1);
+        }
+        Ok(undone)
+    }
+
+    /// Undoes instructions until the PC reaches one of the breakpoints, the instruction that
+    /// wrote one of the watched addresses is undone, or the history runs out
+    pub fn reverse_continue(
+        &mut self,
+        breakpoints: &BTreeSet<u16>,
+        watchpoints: &BTreeSet<u16>,
+    ) -> Result<ReverseStop, VMError> {
+        if self.history.is_none() {
+            return Err(VMError::History(String::from("no history is recorded")));
+        }
+        loop {
+            let Some(undo) = self.history.as_mut().and_then(History::pop) else {
+                return Ok(ReverseStop::Start);
+            };
+            let watched = undo
+                .memory
+                .iter()
+                .map(|(address, _)| *address)
+                .find(|address| watchpoints.contains(address));
+            self.undo(undo)?;
+            if let Some(address) = watched {
+                return Ok(ReverseStop::Watchpoint(address));
+            }
+            if breakpoints.contains(&self.pc) {
+                return Ok(ReverseStop::Breakpoint(self.pc));
+            }
+        }
+    }
+
+    /// Puts the machine back as it was before the instruction of the entry executed
+    fn undo(&mut self, undo: Undo) -> Result<(), VMError> {
+        for (address, previous) in undo.memory.iter().rev() {
+            if let Some(word) = self.memory.get_mut(usize::from(*address)) {
+                *word = *previous;
+            }
+        }
+        for (index, state) in &undo.devices {
+            if let Some(device) = self.devices.get_mut(*index) {
+                device
+                    .restore(state)
+                    .map_err(|err| VMError::History(err.to_string()))?;
+            }
+        }
+        for (register, value) in Register::ALL.iter().zip(undo.registers) {
+            self.set_reg(*register, value);
+        }
+        self.pc = undo.pc;
+        self.psr = undo.psr;
+        self.saved_ssp = undo.saved_ssp;
+        self.saved_usp = undo.saved_usp;
+        self.running = undo.running;
+        self.fetched = None;
+        self.entries = Entries::default();
+        self.accesses.clear();
+        Ok(())
+    }
+
+    /// Fetches, decodes and executes the instruction the PC points to
     pub fn next_instruction(&mut self) -> Result<(), VMError> {
+        self.begin_undo();
+        self.io.clock(self.instructions);
+        let result = self.traced_step();
+        self.instructions = self.instructions.wrapping_add(1);
+        if let Some(history) = self.history.as_mut() {
+            history.finish();
+        }
+        result
+    }
+
+    /// Number of instructions executed since the machine was created, undone ones included
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Starts the history entry of the next instruction, taking a checkpoint first when one is
+    /// due
+    fn begin_undo(&mut self) {
+        let Some(history) = self.history.as_ref() else {
+            return;
+        };
+        if history.checkpoint_due() {
+            let snapshot = self.snapshot();
+            if let Some(history) = self.history.as_mut() {
+                history.checkpoint(snapshot);
+            }
+        }
+        let undo = Undo {
+            registers: Register::ALL.map(|register| self.reg(register)),
+            pc: self.pc,
+            psr: self.psr,
+            saved_ssp: self.saved_ssp,
+            saved_usp: self.saved_usp,
+            running: self.running,
+            memory: Vec::new(),
+            devices: Vec::new(),
+        };
+        if let Some(history) = self.history.as_mut() {
+            history.begin(undo);
+        }
+    }
+
+    /// Adds the state of the device at the bus index to the history entry, before the
+    /// instruction changes it
+    fn device_changing(&mut self, index: usize) {
+        if let (Some(history), Some(device)) = (self.history.as_mut(), self.devices.get(index)) {
+            history.device_changing(index, || device.save());
+        }
+    }
+
+    fn traced_step(&mut self) -> Result<(), VMError> {
+        if self.trace.is_none() {
+            return self.step();
+        }
+        let before = self.state();
+        let result = self.step();
+        self.record_trace(&before, result.is_err())?;
+        result
+    }
+
+    fn step(&mut self) -> Result<(), VMError> {
+        self.fetched = None;
+        self.entries = Entries::default();
+        self.poll_interrupts()
+            .map_err(|err| VMError::Interrupt(err.to_string()))?;
         let pc = self.get_pc()?;
+        if self.access_violation(pc) {
+            self.increment_pc();
+            self.accesses.clear();
+            return self.raise(Exception::AccessViolation);
+        }
         let instruction = self
             .read_word(pc)
             .map_err(|err| VMError::Fetch(format!("failed to read: {}", err)))?
             .ok_or(VMError::Fetch(String::from("invalid Opcode")))?;
-        let opcode = Self::decode(instruction).map_err(|err| VMError::Decode(err.to_string()))?;
+        self.fetched = Some((pc, instruction));
+        let opcode = Self::decode(instruction);
         self.increment_pc();
-        self.execute(opcode)?;
+        // Only the accesses made while executing the instruction are recorded, not the fetch
+        self.accesses.clear();
+        match opcode {
+            Ok(opcode) => self.execute(opcode).map_err(|err| match err {
+                VMError::Execute(message) => {
+                    VMError::Execute(format!("{message} at {}", describe(&self.symbols, pc)))
+                }
+                err => err,
+            }),
+            Err(_) => self.raise(Exception::IllegalOpcode),
+        }
+    }
 
+    /// Writes the trace entry of the instruction that was just executed, given the state
+    /// before it. Nothing is traced when the fetch itself failed. The trace is flushed when
+    /// the machine stops or the instruction failed
+    fn record_trace(&mut self, before: &CpuState, failed: bool) -> Result<(), VMError> {
+        let after = self.state();
+        let (Some((pc, instruction)), Some(trace)) = (self.fetched, self.trace.as_mut()) else {
+            return Ok(());
+        };
+        let accesses = &self.accesses;
+        let symbols = &self.symbols;
+        let entry = |step| TraceEntry {
+            step,
+            pc,
+            location: location(symbols, pc),
+            instruction,
+            opcode: Self::decode(instruction).ok(),
+            registers: Register::ALL
+                .iter()
+                .zip(before.registers.iter().zip(after.registers.iter()))
+                .filter(|(_, (previous, value))| previous != value)
+                .map(|(register, (previous, value))| RegisterChange {
+                    register: *register,
+                    previous: *previous,
+                    value: *value,
+                })
+                .collect(),
+            condition: after.condition,
+            writes: accesses
+                .iter()
+                .filter(|access| matches!(access, MemoryAccess::Write { .. }))
+                .copied()
+                .collect(),
+        };
+        trace
+            .record(pc, entry)
+            .map_err(|err| VMError::Trace(err.to_string()))?;
+        if failed || !after.running {
+            trace
+                .flush()
+                .map_err(|err| VMError::Trace(err.to_string()))?;
+        }
         Ok(())
     }
 
+    /// Address and word of the last executed instruction, None when fetching it failed
+    pub fn last_instruction(&self) -> Option<(u16, u16)> {
+        self.fetched
+    }
+
+    /// Interrupt and exception handlers entered by the last step
+    pub(super) fn last_entries(&self) -> Entries {
+        self.entries
+    }
+
+    /// Memory reads and writes performed by the last executed instruction
+    pub(super) fn last_accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
     fn read_word(&mut self, address: u16) -> Result<Option<u16>, VMError> {
-        if address == MR_KBSR {
-            if let Ok(true) = check_key() {
-                let mut char_buffer: [u8; 1] = [0; 1];
-                std::io::stdin()
-                    .read_exact(&mut char_buffer)
-                    .map_err(|err| VMError::Memory(format!("failed to read keyboard: {}", err)))?;
-                let char = char_buffer
-                    .first()
-                    .ok_or(VMError::Memory(String::from("failed to read char")))?;
-                self.store_word(MR_KBSR, 0b1000_0000_0000_0000)
-                    .map_err(|err| VMError::Memory(format!("memory mapped MR_KBSR: {}", err)))?;
-                self.store_word(MR_KBDR, (*char).into())
-                    .map_err(|err| VMError::Memory(format!("memory mapped MR_KBDR: {}", err)))?;
-            } else {
-                self.store_word(MR_KBSR, 0x0000)
-                    .map_err(|err| VMError::Memory(format!("memory mapped MR_KBSR: {}", err)))?;
-                return Ok(None);
-            }
+        let word = match self.read_device(address)? {
+            Some(word) => word,
+            None => match self.memory.get::<usize>(address.into()) {
+                Some(word) => *word,
+                None => return Ok(None),
+            },
+        };
+        self.accesses.push(MemoryAccess::Read {
+            address,
+            value: word,
+        });
+        Ok(Some(word))
+    }
+
+    /// Reads memory without triggering memory mapped devices
+    pub fn peek_word(&self, address: u16) -> u16 {
+        if let Some(device) = self.device(address) {
+            return device.peek(address);
         }
+        self.memory
+            .get::<usize>(address.into())
+            .copied()
+            .unwrap_or_default()
+    }
 
-        if let Some(word) = self.memory.get::<usize>(address.into()) {
-            Ok(Some(*word))
-        } else {
-            Ok(None)
+    /// Writes memory without triggering memory mapped devices, used by debuggers. Device
+    /// registers can't be written this way, writing them has side effects
+    pub fn poke_word(&mut self, address: u16, value: u16) -> Result<(), VMError> {
+        if self.device(address).is_some() {
+            return Err(VMError::Memory(format!(
+                "x{address:04X} is a device register"
+            )));
         }
+        self.write_memory(address, &[value])
     }
 
     fn store_word(&mut self, address: u16, value: u16) -> Result<(), VMError> {
-        let memory = self
-            .memory
-            .get_mut::<usize>(address.into())
-            .ok_or(VMError::Memory(String::from("invalid memory address")))?;
-        *memory = value;
+        let previous = self.peek_word(address);
+        if !self.write_device(address, value)? {
+            let memory = self
+                .memory
+                .get_mut::<usize>(address.into())
+                .ok_or(VMError::Memory(String::from("invalid memory address")))?;
+            *memory = value;
+            if let Some(history) = self.history.as_mut() {
+                history.written(address, previous);
+            }
+        }
+        self.accesses.push(MemoryAccess::Write {
+            address,
+            previous,
+            value,
+        });
+        Ok(())
+    }
+
+    fn device(&self, address: u16) -> Option<&dyn Device> {
+        self.devices
+            .iter()
+            .find(|device| device.registers().contains(&address))
+            .map(|device| device.as_ref())
+    }
+
+    /// Bus index of the device that handles the address
+    fn device_index(&self, address: u16) -> Option<usize> {
+        self.devices
+            .iter()
+            .position(|device| device.registers().contains(&address))
+    }
+
+    /// Reads a device register, returns None when no device handles the address
+    fn read_device(&mut self, address: u16) -> Result<Option<u16>, VMError> {
+        let Some(index) = self.device_index(address) else {
+            return Ok(None);
+        };
+        self.device_changing(index);
+        let Some(device) = self.devices.get_mut(index) else {
+            return Ok(None);
+        };
+        let mut context = DeviceContext::new(self.io.as_mut(), &mut self.memory, &mut self.running);
+        let value = device
+            .read(address, &mut context)
+            .map(Some)
+            .map_err(|err| VMError::Device(format!("read x{:04X}: {}", address, err)));
+        let written = context.into_written();
+        self.device_written(written);
+        value
+    }
+
+    /// Writes a device register, returns false when no device handles the address
+    fn write_device(&mut self, address: u16, value: u16) -> Result<bool, VMError> {
+        let Some(index) = self.device_index(address) else {
+            return Ok(false);
+        };
+        self.device_changing(index);
+        let Some(device) = self.devices.get_mut(index) else {
+            return Ok(false);
+        };
+        let mut context = DeviceContext::new(self.io.as_mut(), &mut self.memory, &mut self.running);
+        let result = device
+            .write(address, value, &mut context)
+            .map(|_| true)
+            .map_err(|err| VMError::Device(format!("write x{:04X}: {}", address, err)));
+        let written = context.into_written();
+        self.device_written(written);
+        result
+    }
+
+    /// Adds the memory devices wrote, like a DMA transfer, to the history entry
+    fn device_written(&mut self, written: Vec<(u16, u16)>) {
+        if let Some(history) = self.history.as_mut() {
+            for (address, previous) in written {
+                history.written(address, previous);
+            }
+        }
+    }
+
+    /// Lets every device run for one instruction, and interrupts the program with the request
+    /// of highest priority when it is higher than the one of the running program
+    fn poll_interrupts(&mut self) -> Result<(), VMError> {
+        for index in 0..self.devices.len() {
+            if self
+                .devices
+                .get(index)
+                .is_some_and(|device| device.active())
+            {
+                self.device_changing(index);
+            }
+        }
+        let mut context = DeviceContext::new(self.io.as_mut(), &mut self.memory, &mut self.running);
+        let mut highest: Option<InterruptRequest> = None;
+        let mut failed = None;
+        for device in self.devices.iter_mut() {
+            match device.tick(&mut context) {
+                Ok(Some(request)) => {
+                    if highest.is_none_or(|highest| request.priority > highest.priority) {
+                        highest = Some(request);
+                    }
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    failed = Some(VMError::Device(err.to_string()));
+                    break;
+                }
+            }
+        }
+        let written = context.into_written();
+        self.device_written(written);
+        if let Some(err) = failed {
+            return Err(err);
+        }
+        if let Some(request) = highest.filter(|request| request.priority > self.priority()) {
+            self.interrupt(request.vector, Some(request.priority))?;
+            self.entries.interrupt = Some(self.pc);
+        }
         Ok(())
     }
 
@@ -195,9 +976,9 @@ impl VM {
                     .get_flags()
                     .map_err(|err| VMError::Execute(format!("BR {}", err)))?;
                 let offset = sign_extend_9_bits(offset);
-                if (n && flags_value == ConditionFlags::NEG.into())
-                    || (z && flags_value == ConditionFlags::ZRO.into())
-                    || (p && flags_value == ConditionFlags::POS.into())
+                if (n && flags_value & u16::from(ConditionFlags::NEG) != 0)
+                    || (z && flags_value & u16::from(ConditionFlags::ZRO) != 0)
+                    || (p && flags_value & u16::from(ConditionFlags::POS) != 0)
                 {
                     self.add_to_pc(offset);
                 }
@@ -228,6 +1009,9 @@ impl VM {
                 // The address of the value is calculated by adding the incremented PC to the
                 // sign extended offset
                 let address = pc_value.wrapping_add(offset);
+                if self.access_violation(address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 // Read the word from the memory address
                 let word = self
                     .read_word(address)
@@ -253,6 +1037,9 @@ impl VM {
                 // The address of the value is calculated by adding the incremented PC to the
                 // sign extended offset
                 let address = pc_value.wrapping_add(offset);
+                if self.access_violation(address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 // Store the word into the calculated memory address
                 self.store_word(address, word)
                     .map_err(|err| VMError::Execute(format!("ST: {}", err)))?;
@@ -276,8 +1063,7 @@ impl VM {
                         .map_err(|err| VMError::Execute(format!("JSR: {}", err)))?
                 };
                 // Jump PC
-                self.set_pc(new_pc_value)
-                    .map_err(|err| VMError::Execute(format!("JSR: {}", err)))?;
+                self.set_pc(new_pc_value);
             }
             Opcode::AND { dr, sr1, mode, sr2 } => {
                 let source_register_1 = self
@@ -306,6 +1092,9 @@ impl VM {
                 let offset = sign_extend_6_bits(offset);
                 // Address is calculated by adding the base register value with sign extended offset
                 let address = base_register_value.wrapping_add(offset);
+                if self.access_violation(address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 // Read word from calculated address
                 let word = self
                     .read_word(address)
@@ -325,6 +1114,9 @@ impl VM {
                 let offset = sign_extend_6_bits(offset);
                 // Address is calculated by adding the base register value with sign extended offset
                 let address = base_register_value.wrapping_add(offset);
+                if self.access_violation(address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 // Get word from regsiter
                 let word = self
                     .get_register_value(sr.into())
@@ -334,8 +1126,20 @@ impl VM {
                     .map_err(|err| VMError::Execute(format!("STR: {}", err)))?;
             }
             Opcode::RTI {} => {
-                // This opcode is unused
-                println!("unused")
+                if self.privilege() == Privilege::User {
+                    // Only the supervisor can return from a service routine
+                    return self.raise(Exception::PrivilegeViolation);
+                }
+                // Pop the PC and then the PSR of the interrupted program off the supervisor
+                // stack
+                let pc_value = self
+                    .pop()
+                    .map_err(|err| VMError::Execute(format!("RTI: {}", err)))?;
+                let psr_value = self
+                    .pop()
+                    .map_err(|err| VMError::Execute(format!("RTI: {}", err)))?;
+                self.set_pc(pc_value);
+                self.set_psr(psr_value);
             }
             Opcode::NOT { dr, sr } => {
                 let source_register = self
@@ -358,6 +1162,9 @@ impl VM {
                 // The address of the address where the value we need to load is calculated
                 // by adding the incremented PC to the sign extended offset
                 let address_of_address = pc_value.wrapping_add(offset);
+                if self.access_violation(address_of_address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 // Using the previous address we read the final address where the target word is stored
                 let address = self
                     .read_word(address_of_address)
@@ -365,6 +1172,9 @@ impl VM {
                     .ok_or(VMError::Execute(String::from(
                         "LDI: couldn't read first_address",
                     )))?;
+                if self.access_violation(address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 // Read the word from the final address
                 let word = self
                     .read_word(address)
@@ -385,12 +1195,18 @@ impl VM {
                 // The address of the address where the value we need to store is calculated
                 // by adding the incremented PC to the sign extended offset
                 let address_of_address = pc_value.wrapping_add(offset);
+                if self.access_violation(address_of_address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 let address = self
                     .read_word(address_of_address)
                     .map_err(|err| VMError::Execute(format!("STI: {}", err)))?
                     .ok_or(VMError::Execute(String::from(
                         "STI: couldn't read first_address",
                     )))?;
+                if self.access_violation(address) {
+                    return self.raise(Exception::AccessViolation);
+                }
                 // Get the word from the register
                 let word = self
                     .get_register_value(sr.into())
@@ -404,12 +1220,11 @@ impl VM {
                     .get_register_value(base_r.into())
                     .map_err(|err| VMError::Execute(format!("JMP: {}", err)))?;
                 // Unconditionaly set the PC to the value in the base register
-                self.set_pc(offset)
-                    .map_err(|err| VMError::Execute(format!("JMP: {}", err)))?;
+                self.set_pc(offset);
             }
             Opcode::RES {} => {
-                // This opcode is unused
-                println!("unused");
+                // This opcode is reserved
+                return self.raise(Exception::IllegalOpcode);
             }
             Opcode::LEA { dr, offset } => {
                 let pc_value = self
@@ -425,22 +1240,31 @@ impl VM {
                 self.update_flags(dr.into())
                     .map_err(|err| VMError::Execute(format!("LEA: {}", err)))?;
             }
+            Opcode::TRAP { trap_vec } if self.os_loaded => {
+                let pc_value = self
+                    .get_pc()
+                    .map_err(|err| VMError::Execute(format!("TRAP: {}", err)))?;
+                // The return address is also kept in R7, like in earlier revisions of the ISA
+                self.update_register(7, pc_value)
+                    .map_err(|err| VMError::Execute(format!("TRAP: {}", err)))?;
+                // Service routines run in supervisor mode and return with RTI, the address of
+                // the routine is read from the trap vector table
+                self.interrupt(trap_vec.into(), None)
+                    .map_err(|err| VMError::Execute(format!("TRAP: {}", err)))?;
+            }
             Opcode::TRAP { trap_vec } => {
                 let trap_code = Trap::try_from(trap_vec)
                     .map_err(|err| VMError::Execute(format!("TRAP: {}", err)))?;
 
                 match trap_code {
                     Trap::GetC => {
-                        // Read char from stdin into buffer
-                        let mut char_buffer: [u8; 1] = [0; 1];
-                        std::io::stdin()
-                            .read_exact(&mut char_buffer)
+                        // Read char from the keyboard
+                        let read_char = self
+                            .io
+                            .read_key()
                             .map_err(|err| VMError::Execute(format!("TRAP GETC: {}", err)))?;
-                        let read_char = char_buffer.first().ok_or(VMError::Execute(
-                            String::from("TRAP GETC: failed to read char"),
-                        ))?;
                         // Save char into R0
-                        self.update_register(0, (*read_char).into())
+                        self.update_register(0, read_char.into())
                             .map_err(|err| VMError::Execute(format!("TRAP GETC: {}", err)))?;
 
                         self.update_flags(0)
@@ -456,10 +1280,9 @@ impl VM {
                             .try_into()
                             .map_err(|err| VMError::Execute(format!("TRAP OUT: {}", err)))?;
 
-                        let read_char: char = read_char.into();
-                        print!("{read_char}");
-                        io::stdout()
-                            .flush()
+                        self.io
+                            .write(&[read_char])
+                            .and_then(|_| self.io.flush())
                             .map_err(|err| VMError::Execute(format!("TRAP OUT: {}", err)))?;
                     }
                     Trap::Puts => {
@@ -467,6 +1290,7 @@ impl VM {
                         let mut char_address = self
                             .get_register_value(0)
                             .map_err(|err| VMError::Execute(format!("TRAP PUTS: {}", err)))?;
+                        let mut string = Vec::new();
                         while let Ok(Some(c)) = self.read_word(char_address) {
                             // The string ends when the read word is 0x0000
                             if c == 0x0000 {
@@ -475,78 +1299,214 @@ impl VM {
                             let c: u8 = c
                                 .try_into()
                                 .map_err(|err| VMError::Execute(format!("TRAP PUTS: {}", err)))?;
-                            let c: char = c.into();
-                            print!("{}", c);
+                            string.push(c);
                             // Increment the memory address
                             char_address = char_address.wrapping_add(1);
                         }
-                        io::stdout()
-                            .flush()
+                        self.io
+                            .write(&string)
+                            .and_then(|_| self.io.flush())
                             .map_err(|err| VMError::Execute(format!("TRAP PUTS: {}", err)))?;
                     }
                     Trap::In => {
                         // Prompt the user for a char
-                        print!("Enter a character: ");
-                        let mut char_buffer: [u8; 1] = [0; 1];
-                        // Read char into buffer
-                        std::io::stdin()
-                            .read_exact(&mut char_buffer)
+                        self.io
+                            .write(b"Enter a character: ")
+                            .and_then(|_| self.io.flush())
+                            .map_err(|err| VMError::Execute(format!("TRAP IN: {}", err)))?;
+                        let read_char = self
+                            .io
+                            .read_key()
                             .map_err(|err| VMError::Execute(format!("TRAP IN: {}", err)))?;
-
-                        let c_u8 = char_buffer.first().ok_or(VMError::Execute(String::from(
-                            "TRAP IN: failed to read char",
-                        )))?;
-                        let c_char: char = (*c_u8).into();
                         // Echo the character
-                        print!("{c_char}");
+                        self.io
+                            .write(&[read_char])
+                            .and_then(|_| self.io.flush())
+                            .map_err(|err| VMError::Execute(format!("TRAP IN: {}", err)))?;
                         // Save char into R0
-                        self.update_register(0, (*c_u8).into())
+                        self.update_register(0, read_char.into())
                             .map_err(|err| VMError::Execute(format!("TRAP IN: {}", err)))?;
 
                         self.update_flags(0)
                             .map_err(|err| VMError::Execute(format!("TRAP IN: {}", err)))?;
-                        io::stdout()
-                            .flush()
-                            .map_err(|err| VMError::Execute(format!("TRAP IN: {}", err)))?;
                     }
                     Trap::Putsp => {
                         // Get starting address of first two chars
                         let mut char_address = self
                             .get_register_value(0)
                             .map_err(|err| VMError::Execute(format!("TRAP PUTSP: {}", err)))?;
+                        let mut string = Vec::new();
                         while let Ok(Some(c)) = self.read_word(char_address) {
                             // The string ends when the read word is 0x0000
                             if c == 0x0000 {
                                 break;
                             }
-                            // Get the first char
-                            let c1: u8 = ((c & 0b_1111_1111_0000_0000) >> 8)
-                                .try_into()
-                                .map_err(|err| VMError::Execute(format!("TRAP PUSTP: {}", err)))?;
-                            // Get the second char
-                            let c2: u8 = (c & 0b_0000_0000_1111_1111)
-                                .try_into()
-                                .map_err(|err| VMError::Execute(format!("TRAP PUSTP: {}", err)))?;
-
-                            print!("{}", c1);
-                            print!("{}", c2);
+                            // The first char is stored in the low byte and the second one in
+                            // the high byte, which is zero when the string has an odd length
+                            let [c2, c1] = c.to_be_bytes();
+                            string.push(c1);
+                            if c2 != 0 {
+                                string.push(c2);
+                            }
                             // Increment memory address
                             char_address = char_address.wrapping_add(1);
                         }
-                        io::stdout()
-                            .flush()
+                        self.io
+                            .write(&string)
+                            .and_then(|_| self.io.flush())
                             .map_err(|err| VMError::Execute(format!("TRAP PUTSP: {}", err)))?;
                     }
                     Trap::Halt => {
-                        // Stop vm execution
-                        self.running = false;
+                        // Stop vm execution by clearing the clock enable bit, like the OS does
+                        let control = self.peek_word(MR_MCR) & !MCR_CLOCK_ENABLE;
+                        self.store_word(MR_MCR, control)
+                            .map_err(|err| VMError::Execute(format!("TRAP HALT: {}", err)))?;
                     }
+                    Trap::ReadSector => self
+                        .transfer_sector(COMMAND_READ)
+                        .map_err(|err| VMError::Execute(format!("TRAP RDSEC: {}", err)))?,
+                    Trap::WriteSector => self
+                        .transfer_sector(COMMAND_WRITE)
+                        .map_err(|err| VMError::Execute(format!("TRAP WRSEC: {}", err)))?,
+                    Trap::SetVector => self
+                        .set_vector()
+                        .map_err(|err| VMError::Execute(format!("TRAP SETVEC: {}", err)))?,
                 }
             }
         };
         Ok(())
     }
 
+    /// Returns true when the running program isn't allowed to access the address
+    fn access_violation(&self, address: u16) -> bool {
+        self.privilege() == Privilege::User
+            && self.protected.iter().any(|range| range.contains(&address))
+    }
+
+    /// Handles an exception caused by the instruction that was just executed, either by jumping
+    /// to its handler or by stopping with an error, depending on the exception mode
+    fn raise(&mut self, exception: Exception) -> Result<(), VMError> {
+        match self.exception_mode {
+            ExceptionMode::Trap => {
+                self.interrupt(exception.vector(), None)
+                    .map_err(|err| VMError::Exception(format!("{}: {}", exception, err)))?;
+                self.entries.exception = Some(self.pc);
+                Ok(())
+            }
+            ExceptionMode::Stop => Err(VMError::Exception(format!(
+                "{} at {}",
+                exception,
+                describe(&self.symbols, self.pc.wrapping_sub(1))
+            ))),
+        }
+    }
+
+    /// Saves the PSR and PC of the running program on the supervisor stack, switches to
+    /// supervisor mode and jumps to the routine found in the given trap or interrupt vector table
+    /// entry. Device interrupts also raise the priority level to the one of the device, traps and
+    /// exceptions keep it
+    fn interrupt(&mut self, vector: u16, priority: Option<u16>) -> Result<(), VMError> {
+        let psr_value = self.psr;
+        let pc_value = self.pc;
+        self.set_privilege(Privilege::Supervisor);
+        self.push(psr_value)?;
+        self.push(pc_value)?;
+        if let Some(priority) = priority {
+            self.psr = (self.psr & !PSR_PRIORITY) | ((priority << 8) & PSR_PRIORITY);
+        }
+        let routine = self
+            .read_word(vector)?
+            .ok_or(VMError::Memory(String::from("invalid interrupt vector")))?;
+        self.set_pc(routine);
+        Ok(())
+    }
+
+    /// Switches privilege mode, saving R6 as the stack pointer of the mode being left and
+    /// loading the one of the mode being entered
+    fn set_privilege(&mut self, privilege: Privilege) {
+        match (self.privilege(), privilege) {
+            (Privilege::User, Privilege::Supervisor) => {
+                self.saved_usp = self.r6;
+                self.r6 = self.saved_ssp;
+                self.psr &= !PSR_USER;
+            }
+            (Privilege::Supervisor, Privilege::User) => {
+                self.saved_ssp = self.r6;
+                self.r6 = self.saved_usp;
+                self.psr |= PSR_USER;
+            }
+            _ => {}
+        }
+    }
+
+    /// Pushes a word on the stack at R6. A stack that would grow into the device page is refused
+    /// instead of writing device registers
+    fn push(&mut self, value: u16) -> Result<(), VMError> {
+        let address = self.r6.wrapping_sub(1);
+        if DEVICE_PAGE.contains(&address) {
+            return Err(VMError::Memory(format!(
+                "push: stack pointer x{:04X} reaches the device page",
+                self.r6
+            )));
+        }
+        self.r6 = address;
+        self.store_word(self.r6, value)
+            .map_err(|err| VMError::Memory(format!("push: {}", err)))
+    }
+
+    fn pop(&mut self) -> Result<u16, VMError> {
+        let value = self
+            .read_word(self.r6)
+            .map_err(|err| VMError::Memory(format!("pop: {}", err)))?
+            .ok_or(VMError::Memory(String::from("pop: invalid stack address")))?;
+        self.r6 = self.r6.wrapping_add(1);
+        Ok(value)
+    }
+
+    /// Transfers the sector in R0 from or to the buffer at the address in R1 through the block
+    /// storage registers, like the OS routine does. R0 is set to 0 on success and to -1 when
+    /// the transfer fails or there is no block storage
+    fn transfer_sector(&mut self, command: u16) -> Result<(), VMError> {
+        let sector = self.get_register_value(0)?;
+        let address = self.get_register_value(1)?;
+        // Buffers of user mode programs can't reach protected memory or wrap around memory
+        if self.privilege() == Privilege::User {
+            let refused = match address.checked_add(SECTOR_LAST) {
+                Some(last) => (address..=last).any(|word| self.access_violation(word)),
+                None => true,
+            };
+            if refused {
+                return self.update_register(0, 0xFFFF);
+            }
+        }
+        self.store_word(MR_BLKSEC, sector)?;
+        self.store_word(MR_BLKADDR, address)?;
+        self.store_word(MR_BLKCMD, command)?;
+        let status = self.read_word(MR_BLKSR)?.unwrap_or_default();
+        let result = if status & STATUS_READY != 0 && status & STATUS_ERROR == 0 {
+            0
+        } else {
+            0xFFFF
+        };
+        self.update_register(0, result)
+    }
+
+    /// Installs the handler at the address in R1 into the device interrupt vector table entry
+    /// at the address in R0, like the OS routine does. Traps are handled natively without the
+    /// OS, so trap vectors can't be installed. R0 is set to 0 on success and to -1 when R0
+    /// isn't a device interrupt vector
+    fn set_vector(&mut self) -> Result<(), VMError> {
+        let entry = self.get_register_value(0)?;
+        let handler = self.get_register_value(1)?;
+        let result = if DEVICE_VECTORS.contains(&entry) {
+            self.store_word(entry, handler)?;
+            0
+        } else {
+            0xFFFF
+        };
+        self.update_register(0, result)
+    }
+
     fn increment_pc(&mut self) {
         self.pc = self.pc.wrapping_add(1);
     }
@@ -555,19 +1515,20 @@ impl VM {
         let register_value = self
             .get_register(register)
             .map_err(|err| VMError::Flags(format!("read flags: {}", err)))?;
-        let new_value = if (*register_value) == 0 {
+        let condition: u16 = if (*register_value) == 0 {
             ConditionFlags::ZRO.into()
         } else if ((*register_value) >> 15) == 1 {
             ConditionFlags::NEG.into()
         } else {
             ConditionFlags::POS.into()
         };
+        let new_value = (self.psr & !PSR_CONDITION) | condition;
         self.update_register(9, new_value)
             .map_err(|err| VMError::Flags(format!("update flags: {}", err)))?;
         Ok(true)
     }
 
-    fn update_register(&mut self, register: u16, value: u16) -> Result<(), VMError> {
+    pub(super) fn update_register(&mut self, register: u16, value: u16) -> Result<(), VMError> {
         let register_value = self.get_register(register)?;
         *register_value = value;
         Ok(())
@@ -584,13 +1545,13 @@ impl VM {
             6 => &mut self.r6,
             7 => &mut self.r7,
             8 => &mut self.pc,
-            9 => &mut self.cond,
+            9 => &mut self.psr,
             _ => return Err(VMError::GetRegister(format!("{register}"))),
         };
         Ok(register_value)
     }
 
-    fn get_register_value(&self, register: u16) -> Result<u16, VMError> {
+    pub(super) fn get_register_value(&self, register: u16) -> Result<u16, VMError> {
         let register_value: u16 = match register {
             0 => self.r0,
             1 => self.r1,
@@ -601,7 +1562,7 @@ impl VM {
             6 => self.r6,
             7 => self.r7,
             8 => self.pc,
-            9 => self.cond,
+            9 => self.psr,
             _ => return Err(VMError::ReadRegister(format!("{register}"))),
         };
         Ok(register_value)
@@ -620,14 +1581,9 @@ impl VM {
         self.get_register_value(8)
             .map_err(|err| VMError::ProgramCounter(format!("get PC: {}", err)))
     }
-
-    fn set_pc(&mut self, value: u16) -> Result<(), VMError> {
-        self.update_register(8, value)
-            .map_err(|err| VMError::ProgramCounter(format!("set PC: {}", err)))
-    }
 }
 
-fn sign_extend_5_bits(num: u8) -> u16 {
+pub(super) fn sign_extend_5_bits(num: u8) -> u16 {
     let mut num: u16 = num.into();
     if (num >> 4) == 1 {
         num |= 0b1111_1111_1110_0000;
@@ -635,7 +1591,7 @@ fn sign_extend_5_bits(num: u8) -> u16 {
     num
 }
 
-fn sign_extend_6_bits(num: u8) -> u16 {
+pub(super) fn sign_extend_6_bits(num: u8) -> u16 {
     let mut num: u16 = num.into();
     if (num >> 5) == 1 {
         num |= 0b1111_1111_1100_0000;
@@ -643,32 +1599,29 @@ fn sign_extend_6_bits(num: u8) -> u16 {
     num
 }
 
-fn sign_extend_9_bits(mut num: u16) -> u16 {
+pub(super) fn sign_extend_9_bits(mut num: u16) -> u16 {
     if (num >> 8) == 1 {
         num |= 0b1111_1110_0000_0000;
     }
     num
 }
 
-fn sign_extend_11_bits(mut num: u16) -> u16 {
+pub(super) fn sign_extend_11_bits(mut num: u16) -> u16 {
     if (num >> 10) == 1 {
         num |= 0b1111_1000_0000_0000;
     }
     num
 }
 
-fn check_key() -> Result<bool, VMError> {
-    let stdin_fd = File::open("/dev/stdin")
-        .map_err(|err| VMError::Execute(format!("Failed to open stdin: {}", err)))?;
-    let mut fd = select::FdSet::new();
-    fd.insert(AsFd::as_fd(&stdin_fd));
-    let mut timeout = TimeVal::seconds(0);
-    Ok(select::select(None, &mut fd, None, None, &mut timeout).is_ok())
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::lc3_vm::{
+        assembler::assemble,
+        block_storage::BlockStorage,
+        io_device::{BufferIo, SharedBuffer},
+        trace::TraceFormat,
+    };
 
     #[test]
     fn sign_extend_5_bits_positive() {
@@ -687,11 +1640,11 @@ mod test {
         let mut vm = VM::default();
         vm.load_program("./test-programs/add_overflow.obj")?;
         vm.next_instruction()?;
-        assert_eq!(0b_1111_1111_1111_1111, vm.r0);
+        assert_eq!(0b_1111_1111_1111_1111, vm.reg(Register::R0));
         vm.next_instruction()?;
-        assert_eq!(0b_0000_0000_0000_0001, vm.r1);
+        assert_eq!(0b_0000_0000_0000_0001, vm.reg(Register::R1));
         vm.next_instruction()?;
-        assert_eq!(0b_0000_0000_0000_0000, vm.r1);
+        assert_eq!(0b_0000_0000_0000_0000, vm.reg(Register::R1));
         Ok(())
     }
 
@@ -705,7 +1658,848 @@ mod test {
             vm.next_instruction()?;
             vm.next_instruction()?;
         }
-        assert_eq!(10, vm.r0);
+        assert_eq!(10, vm.reg(Register::R0));
+        Ok(())
+    }
+
+    fn run_with_input(source: &str, input: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+        let io = BufferIo::with_input(input.to_vec());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.run()?;
+        Ok(String::from_utf8(output.contents())?)
+    }
+
+    #[test]
+    fn console_traps_use_io_device() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+            GETC
+            OUT
+            LEA R0, HELLO
+            PUTS
+            LEA R0, PACKED
+            PUTSP
+            HALT
+    HELLO   .STRINGZ \"ello\"
+    PACKED  .FILL x2121
+            .FILL x003F
+            .FILL x0000
+            .END
+        ";
+        assert_eq!("hello!!?", run_with_input(source, b"h")?);
+        Ok(())
+    }
+
+    #[test]
+    fn keyboard_status_register_polls_io_device() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+    POLL    LDI R1, KBSR
+            BRzp POLL
+            LDI R0, KBDR
+            OUT
+            BR POLL
+    KBSR    .FILL xFE00
+    KBDR    .FILL xFE02
+            .END
+        ";
+        // The program polls forever, so it is stepped a fixed number of times
+        let io = BufferIo::with_input(b"ab".to_vec());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        for _ in 0..20 {
+            vm.next_instruction()?;
+        }
+        assert_eq!(b"ab".to_vec(), output.contents());
+        Ok(())
+    }
+
+    #[test]
+    fn getc_fails_when_input_runs_out() -> Result<(), Box<dyn std::error::Error>> {
+        let source = ".ORIG x3000\nGETC\nHALT\n.END";
+        assert!(run_with_input(source, b"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn os_service_routines_match_native_traps() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+            IN
+            LEA R0, HELLO
+            PUTS
+            LEA R0, PACKED
+            PUTSP
+            GETC
+            OUT
+            HALT
+    HELLO   .STRINGZ \"ello\"
+    PACKED  .FILL x2121
+            .FILL x003F
+            .FILL x0000
+            .END
+        ";
+        let io = BufferIo::with_input(b"hx".to_vec());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_os()?;
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.run()?;
+        let expected = run_with_input(source, b"hx")?;
+        assert_eq!("Enter a character: hello!!?x", expected);
+        assert_eq!(expected, String::from_utf8(output.contents())?);
+        assert!(!vm.running);
+        Ok(())
+    }
+
+    #[test]
+    fn os_traps_jump_through_vector_table() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+            LEA R1, HANDLER
+            STI R1, VECTOR
+            TRAP x40
+            ADD R2, R2, #1
+            HALT
+    HANDLER ADD R2, R2, #1
+            RTI
+    VECTOR  .FILL x0040
+            .END
+        ";
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_os()?;
+        // The trap vector table is in system space, so user mode can only install a handler
+        // when protection is relaxed
+        vm.set_protected_ranges(Vec::new());
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.run()?;
+        assert_eq!(2, vm.reg(Register::R2));
+        assert_eq!(Privilege::Supervisor, vm.privilege());
+        // HALT went through the vector table too, saving the return address
+        assert_eq!(0x3005, vm.reg(Register::R7));
+        Ok(())
+    }
+
+    #[test]
+    fn rti_returns_to_user_mode() -> Result<(), Box<dyn std::error::Error>> {
+        // The supervisor stack holds the PC and PSR to return to, as left by an interrupt
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&assemble(".ORIG x3000\nRTI\n.END")?.to_bytes())?;
+        vm.write_memory(0x2FFE, &[0x3010, 0x8001])?;
+        vm.set_reg(Register::R6, 0x2FFE);
+        vm.next_instruction()?;
+        assert_eq!(0x3010, vm.pc());
+        assert_eq!(0x8001, vm.psr());
+        assert_eq!(Privilege::User, vm.privilege());
+        assert_eq!(ConditionFlags::POS, vm.condition());
+        // R6 now holds the user stack pointer, the supervisor one is saved for later
+        assert_eq!(0, vm.reg(Register::R6));
+        vm.set_psr(0x0002);
+        assert_eq!(0x3000, vm.reg(Register::R6));
+        Ok(())
+    }
+
+    #[test]
+    fn rti_in_user_mode_is_a_privilege_violation() -> Result<(), Box<dyn std::error::Error>> {
+        let source = ".ORIG x3000\nLD R6, STACK\nRTI\nSTACK .FILL x4000\n.END";
+        let io = BufferIo::with_input(Vec::new());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_os()?;
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.next_instruction()?;
+        vm.next_instruction()?;
+        assert_eq!(Privilege::Supervisor, vm.privilege());
+        // The PSR and PC of the program were pushed onto the supervisor stack
+        assert_eq!(0x2FFE, vm.reg(Register::R6));
+        assert_eq!(&[0x3002, 0x8001], vm.read_memory(0x2FFE, 2)?);
+        vm.run()?;
+        assert_eq!(
+            "\nPrivilege mode violation\n",
+            String::from_utf8(output.contents())?
+        );
+        Ok(())
+    }
+
+    const KEYBOARD_INTERRUPT_PROGRAM: &str = "
+            .ORIG x3000
+            LD R6, STACK
+            LEA R0, HANDLER
+            STI R0, VECTOR
+            LD R0, ENABLE
+            STI R0, KBSR
+    WAIT    LD R0, KEY
+            BRz WAIT
+            OUT
+            HALT
+    HANDLER ST R0, SAVED
+            LDI R0, KBDR
+            ST R0, KEY
+            LD R0, SAVED
+            RTI
+    STACK   .FILL x4000
+    VECTOR  .FILL x0180
+    ENABLE  .FILL x4000
+    KBSR    .FILL xFE00
+    KBDR    .FILL xFE02
+    KEY     .FILL #0
+    SAVED   .BLKW 1
+            .END
+        ";
+
+    #[test]
+    fn keyboard_interrupt_runs_handler() -> Result<(), Box<dyn std::error::Error>> {
+        let output = run_with_input(KEYBOARD_INTERRUPT_PROGRAM, b"k")?;
+        assert_eq!("k", output);
+        Ok(())
+    }
+
+    #[test]
+    fn interrupts_without_os_use_the_initial_supervisor_stack(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Nothing sets up R6, so the PSR and PC are pushed right below the program
+        let source = KEYBOARD_INTERRUPT_PROGRAM.replace("LD R6, STACK", "");
+        let mut vm = VM::new(Box::new(BufferIo::with_input(b"k".to_vec())));
+        let program = assemble(&source)?;
+        vm.load_bytes(&program.to_bytes())?;
+        vm.run()?;
+        assert_eq!(u16::from(b'k'), vm.reg(Register::R0));
+        assert_eq!(INITIAL_SSP, vm.reg(Register::R6));
+        let wait = program.symbols.get("WAIT").copied().unwrap_or_default();
+        assert!([wait, wait.wrapping_add(1)].contains(&vm.peek_word(0x2FFE)));
+
+        // A stack pointer that would wrap around into the device page is refused
+        vm.r6 = 0x0000;
+        assert!(matches!(vm.push(0x1234), Err(VMError::Memory(_))));
+        assert_eq!(0x0000, vm.r6);
+        Ok(())
+    }
+
+    #[test]
+    fn setvec_installs_handlers_under_the_os() -> Result<(), Box<dyn std::error::Error>> {
+        // The program can't write the vector table, and reaches KBSR because only system space
+        // is protected
+        let source = "
+            .ORIG x3000
+            LD R6, STACK
+            LD R0, BAD_VECTOR
+            LEA R1, HANDLER
+            SETVEC
+            ST R0, RESULT
+            LD R0, VECTOR
+            SETVEC
+            LD R0, ENABLE
+            STI R0, KBSR
+    WAIT    LD R0, KEY
+            BRz WAIT
+            OUT
+            HALT
+    HANDLER LDI R0, KBDR
+            ST R0, KEY
+            RTI
+    STACK   .FILL x4000
+    BAD_VECTOR .FILL x0100
+    VECTOR  .FILL x0180
+    ENABLE  .FILL x4000
+    KBSR    .FILL xFE00
+    KBDR    .FILL xFE02
+    KEY     .FILL #0
+    RESULT  .BLKW 1
+            .END
+        ";
+        let program = assemble(source)?;
+        for os in [true, false] {
+            let io = BufferIo::with_input(b"k".to_vec());
+            let output = io.output().clone();
+            let mut vm = VM::new(Box::new(io));
+            if os {
+                vm.load_os()?;
+                vm.set_protected_ranges(vec![0x0000..=0x2FFF]);
+            }
+            vm.load_bytes(&program.to_bytes())?;
+            vm.run()?;
+            assert_eq!(b"k".to_vec(), output.contents());
+            let label = |label: &str| program.symbols.get(label).copied().unwrap_or_default();
+            assert_eq!(0xFFFF, vm.peek_word(label("RESULT")));
+            assert_eq!(label("HANDLER"), vm.peek_word(0x0180));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn setvec_installs_trap_handlers_under_the_os() -> Result<(), Box<dyn std::error::Error>> {
+        // The OS keeps its own trap vectors, so replacing GETC is refused
+        let source = "
+            .ORIG x3000
+            LD R0, VECTOR
+            LEA R1, DOUBLE
+            SETVEC
+            ST R0, RESULT
+            LD R0, OS_VECTOR
+            SETVEC
+            ST R0, OS_RESULT
+            AND R0, R0, #0
+            ADD R0, R0, #3
+            TRAP x30
+            ST R0, DOUBLED
+            HALT
+    DOUBLE  ADD R0, R0, R0
+            RTI
+    VECTOR  .FILL x0030
+    OS_VECTOR .FILL x0020
+    RESULT  .BLKW 1
+    OS_RESULT .BLKW 1
+    DOUBLED .BLKW 1
+            .END
+        ";
+        let program = assemble(source)?;
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_os()?;
+        vm.load_bytes(&program.to_bytes())?;
+        vm.run()?;
+        let label = |label: &str| program.symbols.get(label).copied().unwrap_or_default();
+        assert_eq!(6, vm.peek_word(label("DOUBLED")));
+        assert_eq!(0, vm.peek_word(label("RESULT")));
+        assert_eq!(0xFFFF, vm.peek_word(label("OS_RESULT")));
+        assert_eq!(label("DOUBLE"), vm.peek_word(0x0030));
+        Ok(())
+    }
+
+    #[test]
+    fn keyboard_interrupt_saves_state_and_raises_priority() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let mut vm = VM::new(Box::new(BufferIo::with_input(b"k".to_vec())));
+        vm.load_bytes(&assemble(KEYBOARD_INTERRUPT_PROGRAM)?.to_bytes())?;
+        // Run up to the first instruction of the wait loop, the interrupt is taken before it
+        for _ in 0..6 {
+            vm.next_instruction()?;
+        }
+        assert_eq!(0x300A, vm.pc());
+        assert_eq!(4, vm.priority());
+        assert_eq!(0x3FFE, vm.reg(Register::R6));
+        assert_eq!(&[0x3005, 0x0001], vm.read_memory(0x3FFE, 2)?);
+        Ok(())
+    }
+
+    #[test]
+    fn keyboard_interrupt_is_masked_by_priority() -> Result<(), Box<dyn std::error::Error>> {
+        let mut vm = VM::new(Box::new(BufferIo::with_input(b"k".to_vec())));
+        vm.load_bytes(&assemble(KEYBOARD_INTERRUPT_PROGRAM)?.to_bytes())?;
+        vm.set_psr(0x0402);
+        for _ in 0..20 {
+            vm.next_instruction()?;
+        }
+        assert!((0x3005..0x3007).contains(&vm.pc()));
+        // The key is waiting to be read
+        assert_eq!(0xC000, vm.peek_word(0xFE00));
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_opcode_stops_by_default() -> Result<(), Box<dyn std::error::Error>> {
+        let source = ".ORIG x3000\nADD R0, R0, #1\n.FILL xD000\nHALT\n.END";
+        let err = run_with_input(source, b"")
+            .err()
+            .ok_or("program should fail")?;
+        assert_eq!("Exception: illegal opcode at x3001", err.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn illegal_opcode_traps_to_os_handler() -> Result<(), Box<dyn std::error::Error>> {
+        let source = ".ORIG x3000\n.FILL xD000\nHALT\n.END";
+        let io = BufferIo::with_input(Vec::new());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_os()?;
+        assert_eq!(ExceptionMode::Trap, vm.exception_mode());
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.run()?;
+        assert_eq!("\nIllegal opcode\n", String::from_utf8(output.contents())?);
+        // The handler stops the machine with a fault instead of halting it
+        assert!(vm.faulted());
+
+        vm.set_exception_mode(ExceptionMode::Stop);
+        vm.set_psr(0x8002);
+        vm.set_pc(0x3000);
+        assert!(matches!(vm.run(), Err(VMError::Exception(_))));
+        assert!(!vm.faulted());
+        Ok(())
+    }
+
+    #[test]
+    fn user_mode_access_to_system_space_is_a_violation() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+            LDI R0, KBSR
+            HALT
+    KBSR    .FILL xFE00
+            .END
+        ";
+        let io = BufferIo::with_input(Vec::new());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_os()?;
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.run()?;
+        assert_eq!(
+            "\nAccess control violation\n",
+            String::from_utf8(output.contents())?
+        );
+
+        vm.set_exception_mode(ExceptionMode::Stop);
+        vm.set_psr(0x8002);
+        vm.set_pc(0x2FFF);
+        let err = vm.run().err().ok_or("fetch should fail")?;
+        assert_eq!(
+            "Exception: access control violation at x2FFF",
+            err.to_string()
+        );
+
+        // Relaxing the memory map lets the same program poll the keyboard
+        vm.set_protected_ranges(vec![0x0000..=0x2FFF]);
+        vm.set_pc(0x3000);
+        vm.run()?;
+        assert_eq!(0, vm.reg(Register::R0));
+        Ok(())
+    }
+
+    #[test]
+    fn display_and_machine_control_registers() -> Result<(), Box<dyn std::error::Error>> {
+        // Writes a character without traps and stops the clock itself
+        let source = "
+            .ORIG x3000
+            STI R0, DSR
+    WAIT    LDI R1, DSR
+            BRzp WAIT
+            LD R0, CHAR
+            STI R0, DDR
+            LDI R0, MCR
+            LD R1, MASK
+            AND R0, R0, R1
+            STI R0, MCR
+            ADD R2, R2, #1
+            HALT
+    DSR     .FILL xFE04
+    DDR     .FILL xFE06
+    MCR     .FILL xFFFE
+    CHAR    .FILL x4F4B
+    MASK    .FILL x7FFF
+            .END
+        ";
+        let io = BufferIo::with_input(Vec::new());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.run()?;
+        // Only the low byte of DDR is written and the ready bit of DSR can't be cleared
+        assert_eq!(b"K".to_vec(), output.contents());
+        assert_eq!(0x8000, vm.peek_word(0xFE04));
+        assert_eq!(0x0000, vm.peek_word(0xFFFE));
+        assert_eq!(0, vm.reg(Register::R2));
+        assert_eq!(0x3009, vm.pc());
+
+        // Running again restarts the clock until the program halts
+        vm.run()?;
+        assert_eq!(1, vm.reg(Register::R2));
+        assert_eq!(0x0000, vm.peek_word(0xFFFE));
+        Ok(())
+    }
+
+    #[test]
+    fn timer_interrupts_are_deterministic() -> Result<(), Box<dyn std::error::Error>> {
+        // Prints a dot on every timer interrupt and stops after three
+        let source = "
+            .ORIG x3000
+            LD R6, STACK
+            LEA R0, HANDLER
+            STI R0, VECTOR
+            LD R0, INTERVAL
+            STI R0, TIR
+            LD R0, ENABLE
+            STI R0, TCR
+    WAIT    LD R0, TICKS
+            ADD R0, R0, #-3
+            BRn WAIT
+            HALT
+    HANDLER ST R0, SAVED
+            LDI R0, TSR
+            LD R0, TICKS
+            ADD R0, R0, #1
+            ST R0, TICKS
+            LD R0, DOT
+            OUT
+            LD R0, SAVED
+            RTI
+    STACK   .FILL x4000
+    VECTOR  .FILL x0181
+    INTERVAL .FILL #50
+    ENABLE  .FILL xC000
+    TCR     .FILL xFE08
+    TIR     .FILL xFE0A
+    TSR     .FILL xFE0C
+    TICKS   .FILL #0
+    DOT     .FILL x2E
+    SAVED   .BLKW 1
+            .END
+        ";
+        let io = BufferIo::with_input(Vec::new());
+        let output = io.output().clone();
+        let mut vm = VM::new(Box::new(io));
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        let mut steps: u32 = 0;
+        vm.running = true;
+        while vm.running {
+            vm.next_instruction()?;
+            steps = steps.wrapping_add(1);
+        }
+        assert_eq!(b"...".to_vec(), output.contents());
+        // Counting instructions makes every run take exactly the same number of steps
+        assert_eq!(170, steps);
+        Ok(())
+    }
+
+    #[test]
+    fn display_interrupts_until_disabled() -> Result<(), Box<dyn std::error::Error>> {
+        // The handler prints the next character on every interrupt and disables the display
+        // interrupt at the end of the string
+        let source = "
+            .ORIG x3000
+            LD R6, STACK
+            LEA R0, HANDLER
+            STI R0, VECTOR
+            LEA R0, MESSAGE
+            ST R0, NEXT
+            LD R0, ENABLE
+            STI R0, DSR
+    WAIT    LD R0, NEXT
+            BRnp WAIT
+            HALT
+    HANDLER ST R0, SAVED
+            ST R1, SAVED_R1
+            LD R1, NEXT
+            LDR R0, R1, #0
+            BRz DONE
+            STI R0, DDR
+            ADD R1, R1, #1
+            ST R1, NEXT
+            BR RETURN
+    DONE    STI R0, DSR
+            ST R0, NEXT
+    RETURN  LD R0, SAVED
+            LD R1, SAVED_R1
+            RTI
+    STACK   .FILL x4000
+    VECTOR  .FILL x0182
+    ENABLE  .FILL x4000
+    DSR     .FILL xFE04
+    DDR     .FILL xFE06
+    MESSAGE .STRINGZ \"hi\"
+    NEXT    .BLKW 1
+    SAVED   .BLKW 1
+    SAVED_R1 .BLKW 1
+            .END
+        ";
+        let output = run_with_input(source, b"")?;
+        assert_eq!("hi", output);
+        Ok(())
+    }
+
+    #[test]
+    fn sector_traps_fail_without_block_storage() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+            LEA R1, BUFFER
+            RDSEC
+            ADD R2, R0, #0
+            WRSEC
+            ADD R3, R0, #0
+            HALT
+    BUFFER  .BLKW 256
+            .END
+        ";
+        for os in [false, true] {
+            let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+            if os {
+                vm.load_os()?;
+            }
+            vm.load_bytes(&assemble(source)?.to_bytes())?;
+            vm.run()?;
+            assert_eq!(0xFFFF, vm.reg(Register::R2));
+            assert_eq!(0xFFFF, vm.reg(Register::R3));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sector_buffers_in_protected_memory_are_refused() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+            LD R1, VECTORS
+            RDSEC
+            ADD R2, R0, #0
+            AND R0, R0, #0
+            LD R1, BUFFER
+            RDSEC
+            ADD R3, R0, #0
+            HALT
+    VECTORS .FILL x0100
+    BUFFER  .FILL x4000
+            .END
+        ";
+        for os in [true, false] {
+            let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+            vm.add_device(Box::new(BlockStorage::new(std::io::Cursor::new(vec![
+                0xAB;
+                512
+            ]))))?;
+            if os {
+                vm.load_os()?;
+            } else {
+                vm.set_psr(PSR_USER);
+            }
+            vm.load_bytes(&assemble(source)?.to_bytes())?;
+            let vectors = vm.read_memory(0x0100, 256)?.to_vec();
+            vm.run()?;
+            assert_eq!(0xFFFF, vm.reg(Register::R2));
+            assert_eq!(vectors, vm.read_memory(0x0100, 256)?);
+            assert_eq!(0x0000, vm.reg(Register::R3));
+            assert_eq!(&[0xABAB; 256], vm.read_memory(0x4000, 256)?);
+        }
+
+        // Relaxing the protection reaches the OS, which then allows the transfer
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.add_device(Box::new(BlockStorage::new(std::io::Cursor::new(vec![
+            0xAB;
+            512
+        ]))))?;
+        vm.load_os()?;
+        vm.set_protected_ranges(Vec::new());
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.run()?;
+        assert_eq!(0x0000, vm.reg(Register::R2));
+        Ok(())
+    }
+
+    #[test]
+    fn trace_records_register_and_memory_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let source = "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #5
+            ST R0, VALUE
+            .FILL xD000
+    VALUE   .BLKW 1
+            .END
+        ";
+        let output = SharedBuffer::default();
+        let mut trace = Trace::new(Box::new(output.clone()), TraceFormat::Text);
+        trace.set_window(Some(1..=10));
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.set_trace(Some(trace));
+        // The illegal opcode stops the program and is traced too
+        assert!(vm.run().is_err());
+        let trace = String::from_utf8(output.contents())?;
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            vec![
+                "       1  x3001  x1025  ADD R0, R0, #5        p  R0 x0000->x0005",
+                "       2  x3002  x3001  ST R0, #1             p  [x3004] x0000->x0005",
+                "       3  x3003  xD000  RES                   p",
+            ],
+            lines
+        );
+
+        // Only instructions inside the range are written, in JSON
+        let output = SharedBuffer::default();
+        let mut trace = Trace::new(Box::new(output.clone()), TraceFormat::JsonLines);
+        trace.set_range(Some(0x3002..=0x3002));
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.set_trace(Some(trace));
+        assert!(vm.run().is_err());
+        assert_eq!(
+            "{\"step\":2,\"pc\":12290,\"location\":null,\"instruction\":12289,\"opcode\":\"ST R0, #1\",\"registers\":{},\"condition\":\"p\",\"writes\":[{\"address\":12292,\"previous\":0,\"value\":5}]}\n",
+            String::from_utf8(output.contents())?
+        );
+        Ok(())
+    }
+
+    /// Prints a dot on every timer interrupt and halts after three
+    const TIMER_DOTS: &str = "
+            .ORIG x3000
+            LEA R0, HANDLER
+            STI R0, VECTOR
+            LD R6, STACK
+            LD R0, INTERVAL
+            STI R0, TIR
+            LD R0, ENABLE
+            STI R0, TCR
+    WAIT    LD R1, TICKS
+            ADD R1, R1, #-3
+            BRn WAIT
+            HALT
+    HANDLER ST R0, SAVED
+            LDI R0, TSR
+            LD R0, TICKS
+            ADD R0, R0, #1
+            ST R0, TICKS
+            LD R0, DOT
+            OUT
+            LD R0, SAVED
+            RTI
+    STACK   .FILL x4000
+    VECTOR  .FILL x0181
+    INTERVAL .FILL #50
+    ENABLE  .FILL xC000
+    TCR     .FILL xFE08
+    TIR     .FILL xFE0A
+    TSR     .FILL xFE0C
+    TICKS   .FILL #0
+    DOT     .FILL x2E
+    SAVED   .BLKW 1
+            .END
+        ";
+
+    #[test]
+    fn snapshots_resume_the_machine() -> Result<(), Box<dyn std::error::Error>> {
+        // The timer has to keep its count for the restored machine to print the same dots
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&assemble(TIMER_DOTS)?.to_bytes())?;
+        vm.running = true;
+        for _ in 0..75 {
+            vm.next_instruction()?;
+        }
+        let snapshot = Snapshot::from_bytes(&vm.snapshot().to_bytes())?;
+
+        let io = BufferIo::with_input(Vec::new());
+        let output = io.output().clone();
+        let mut restored = VM::new(Box::new(io));
+        restored.restore(&snapshot)?;
+        assert_eq!(vm.state(), restored.state());
+        assert_eq!(75, restored.instructions());
+        assert_eq!(vm.memory_map(), restored.memory_map());
+        restored.run()?;
+        vm.run()?;
+        assert_eq!(b"..".to_vec(), output.contents());
+        assert_eq!(vm.snapshot(), restored.snapshot());
+
+        // A snapshot of a machine with other devices is rejected and changes nothing
+        let mut other = snapshot.clone();
+        other.devices.pop();
+        assert!(matches!(
+            restored.restore(&other),
+            Err(VMError::Snapshot(_))
+        ));
+        other.devices = snapshot.devices.clone();
+        if let Some(state) = other.devices.first_mut() {
+            state.push(0);
+        }
+        assert!(restored.restore(&other).is_err());
+        assert_eq!(vm.snapshot(), restored.snapshot());
+        Ok(())
+    }
+
+    #[test]
+    fn step_back_undoes_instructions() -> Result<(), Box<dyn std::error::Error>> {
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&assemble(TIMER_DOTS)?.to_bytes())?;
+        let mut history = History::new(100);
+        history.set_checkpoint_interval(16);
+        vm.set_history(Some(history));
+        // The instruction count keeps counting undone instructions
+        let state = |vm: &VM| Snapshot {
+            instructions: 0,
+            ..vm.snapshot()
+        };
+        let mut snapshots = vec![state(&vm)];
+        vm.run_with(|vm| snapshots.push(state(vm)))?;
+        assert_eq!(170, vm.history().map_or(0, History::step));
+
+        // Interrupts, device registers and stack pushes are undone alike, through checkpoints
+        // for longer jumps
+        for (count, step) in [(1, 169), (30, 139), (7, 132)] {
+            assert_eq!(count, vm.step_back(count)?);
+            assert_eq!(snapshots.get(step), Some(&state(&vm)));
+        }
+        assert_eq!(170, vm.instructions());
+        // Running forward again records new instructions
+        vm.next_instruction()?;
+        assert_eq!(snapshots.get(133), Some(&state(&vm)));
+        assert_eq!(171, vm.instructions());
+        // Only the last 100 instructions were kept
+        assert_eq!(63, vm.step_back(1000)?);
+        assert_eq!(snapshots.get(70), Some(&state(&vm)));
+        assert_eq!(0, vm.step_back(1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn history_saves_only_the_devices_an_instruction_uses() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let source = "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #7
+            STI R0, DDR
+            HALT
+    DDR     .FILL xFE06
+            .END
+        ";
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&assemble(source)?.to_bytes())?;
+        vm.set_history(Some(History::default()));
+        vm.run()?;
+        let mut devices = Vec::new();
+        while let Some(undo) = vm.history.as_mut().and_then(History::pop) {
+            devices.push(undo.devices);
+        }
+        // Only the display and MCR, cleared by HALT, were written, the idle keyboard and timer
+        // aren't saved
+        assert_eq!(
+            vec![
+                vec![(3, vec![MCR_CLOCK_ENABLE])],
+                vec![(1, vec![STATUS_READY])],
+                vec![],
+                vec![]
+            ],
+            devices
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_continue_stops_at_writes_and_breakpoints() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let program = assemble(TIMER_DOTS)?;
+        let label = |name: &str| program.symbols.get(name).copied().unwrap_or_default();
+        let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+        vm.load_bytes(&program.to_bytes())?;
+        assert!(matches!(
+            vm.reverse_continue(&BTreeSet::new(), &BTreeSet::new()),
+            Err(VMError::History(_))
+        ));
+        vm.set_history(Some(History::default()));
+        vm.run()?;
+
+        // The instruction that incremented the tick count the last time is the next one to run
+        let watchpoints = BTreeSet::from([label("TICKS")]);
+        let stop = vm.reverse_continue(&BTreeSet::new(), &watchpoints)?;
+        assert_eq!(ReverseStop::Watchpoint(label("TICKS")), stop);
+        assert_eq!(label("HANDLER").wrapping_add(4), vm.pc());
+        assert_eq!(2, vm.peek_word(label("TICKS")));
+
+        // The interrupt and the first instruction of the handler run in the same step
+        let second = label("HANDLER").wrapping_add(1);
+        let stop = vm.reverse_continue(&BTreeSet::from([second]), &BTreeSet::new())?;
+        assert_eq!(ReverseStop::Breakpoint(second), stop);
+        let stop = vm.reverse_continue(&BTreeSet::new(), &BTreeSet::new())?;
+        assert_eq!(ReverseStop::Start, stop);
+        assert_eq!(0x3000, vm.pc());
         Ok(())
     }
 }
@@ -0,0 +1,40 @@
+//! LC-3 virtual machine together with an assembler, a disassembler and debugging front ends.
+//! The modules are private, the public API is the items re-exported at the root of the crate.
+//!
+//! ```no_run
+//! use lc3_rust::{BufferIo, VM};
+//!
+//! let io = BufferIo::with_input(b"y".to_vec());
+//! let output = io.output().clone();
+//! let mut vm = VM::new(Box::new(io));
+//! vm.load_program("program.obj")?;
+//! vm.run()?;
+//! println!("{}", String::from_utf8_lossy(&output.contents()));
+//! # Ok::<(), lc3_rust::VMError>(())
+//! ```
+mod lc3_vm;
+
+pub use lc3_vm::{
+    assembler::{assemble, AssemblerError, AssemblerErrorKind, Program},
+    block_storage::{BlockStorage, SECTOR_WORDS},
+    coverage::{Branch, Coverage},
+    debugger::{Debugger, DebuggerError},
+    devices::{invalid_state, Device, DeviceContext, InterruptRequest, DEVICE_PAGE},
+    disassembler::{disassemble, DisassembledWord},
+    exceptions::{Exception, ExceptionMode},
+    flags::{ConditionFlags, Privilege},
+    gdb::{GdbError, GdbServer},
+    history::{History, ReverseStop, DEFAULT_CHECKPOINT_INTERVAL, DEFAULT_HISTORY_CAPACITY},
+    io_device::{BufferIo, FileIo, IoDevice, SharedBuffer, StreamIo, TerminalIo},
+    memory_map::{MemoryMap, Segment},
+    opcodes::{Opcode, OpcodeError},
+    os::image as os_image,
+    profiler::Profiler,
+    registers::Register,
+    replay::{read_input_log, InputEvent, RecordingIo, ReplayIo},
+    snapshot::{Snapshot, SnapshotError, SNAPSHOT_VERSION},
+    symbols::{describe, location, parse_symbol_file, symbol_file, SymbolError},
+    trace::{RegisterChange, Trace, TraceEntry, TraceFormat},
+    traps::{Trap, TrapError},
+    virtual_machine::{CpuState, MemoryAccess, VMError, VM},
+};
@@ -1,13 +1,25 @@
-mod lc3_vm;
-use lc3_vm::virtual_machine::VM;
+use lc3_rust::{
+    os_image, parse_symbol_file, read_input_log, symbol_file, BlockStorage, Coverage, Debugger,
+    ExceptionMode, GdbServer, IoDevice, Profiler, RecordingIo, ReplayIo, Snapshot, StreamIo,
+    TerminalIo, Trace, TraceFormat, VMError, VM,
+};
 use nix::{
     errno::Errno,
-    sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg, Termios},
+    sys::{
+        signal::{SigSet, Signal},
+        signalfd::{SfdFlags, SignalFd},
+        termios::{tcgetattr, tcsetattr, LocalFlags, SetArg, Termios},
+    },
 };
 use std::{
+    collections::BTreeMap,
     env,
     fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::{Range, RangeInclusive},
     os::fd::{AsFd, BorrowedFd},
+    path::Path,
+    process::ExitCode,
 };
 use thiserror::Error;
 
@@ -15,6 +27,56 @@ use thiserror::Error;
 pub enum MainError {
     #[error("No filename provided")]
     NoFileName,
+    #[error("Failed to read source file: {0}")]
+    ReadSource(String),
+    #[error("Failed to assemble {0}")]
+    Assemble(String),
+    #[error("Failed to write object file: {0}")]
+    WriteObject(String),
+    #[error("Failed to read symbol file {0}")]
+    ReadSymbols(String),
+    #[error("Failed to write symbol file: {0}")]
+    WriteSymbols(String),
+    #[error("Invalid address {0}")]
+    InvalidAddress(String),
+    #[error("Unknown option {0}")]
+    UnknownOption(String),
+    #[error("Missing value for option {0}")]
+    MissingValue(String),
+    #[error("Invalid port {0}")]
+    InvalidPort(String),
+    #[error("Invalid exception mode {0}, expected trap or stop")]
+    InvalidExceptionMode(String),
+    #[error("--exceptions trap needs --os, without it there are no exception handlers")]
+    NoExceptionHandlers,
+    #[error("Invalid address range {0}")]
+    InvalidRange(String),
+    #[error("Failed to open disk image: {0}")]
+    Disk(String),
+    #[error("Invalid trace format {0}, expected text or jsonl")]
+    InvalidTraceFormat(String),
+    #[error("Invalid instruction window {0}")]
+    InvalidWindow(String),
+    #[error("Failed to create trace file: {0}")]
+    TraceFile(String),
+    #[error("Failed to write report: {0}")]
+    Report(String),
+    #[error("lcov output needs the source of the program, run the .asm file or put it next to the .obj file")]
+    NoSource,
+    #[error("Failed to read snapshot: {0}")]
+    ReadSnapshot(String),
+    #[error("Failed to write snapshot: {0}")]
+    WriteSnapshot(String),
+    #[error("Failed to catch Ctrl-C ERRNO: {0}")]
+    Interrupt(String),
+    #[error("Failed to read input log: {0}")]
+    ReadInputLog(String),
+    #[error("Failed to create input log: {0}")]
+    WriteInputLog(String),
+    #[error("Failed to open input file: {0}")]
+    InputFile(String),
+    #[error("Failed to create output file: {0}")]
+    OutputFile(String),
     #[error("Failed to read stdin {0}")]
     Stdin(String),
     #[error("Failed to get termios ERRNO: {0}")]
@@ -25,8 +87,64 @@ pub enum MainError {
     RestoreInputBuffering(String),
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let file_name = env::args().nth(1).ok_or(MainError::NoFileName)?;
+/// Exit status of a run whose program failed before reaching HALT, or that the OS stopped
+/// because of an exception
+const EXIT_PROGRAM_ERROR: u8 = 2;
+
+/// Number of instructions between two checks for Ctrl-C when saving a snapshot on exit
+const INTERRUPT_CHECK_INTERVAL: u32 = 1024;
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let command = env::args().nth(1).ok_or(MainError::NoFileName)?;
+    if command == "asm" {
+        let source_file = env::args().nth(2).ok_or(MainError::NoFileName)?;
+        let object_file = env::args().nth(3);
+        assemble(&source_file, object_file.as_deref())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if command == "disasm" {
+        let object_file = env::args().nth(2).ok_or(MainError::NoFileName)?;
+        let start = env::args()
+            .nth(3)
+            .map(|arg| parse_address(&arg))
+            .transpose()?;
+        let end = env::args()
+            .nth(4)
+            .map(|arg| parse_address(&arg))
+            .transpose()?;
+        disassemble(&object_file, start, end)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let options = RunOptions::parse(env::args().skip(1))?;
+    if options.batch {
+        return run_batch(&options);
+    }
+
+    let mut vm = VM::new(log_input(Box::new(TerminalIo), &options)?);
+    let loaded = start(&mut vm, &options)?;
+
+    if let Some(port) = options.gdb_port {
+        GdbServer::new(vm).listen(port, |address| {
+            eprintln!("Listening for gdb on {address}");
+        })?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if options.debug {
+        // The terminal is left in canonical mode so commands can be edited before sending them
+        let commands = std::iter::from_fn(|| {
+            let mut line = String::new();
+            match std::io::stdin().read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => Some(Ok(line)),
+                Err(err) => Some(Err(err)),
+            }
+        });
+        Debugger::new(vm, loaded.symbols).run(commands, std::io::stdout())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
     let stdin_file = File::open("/dev/stdin").map_err(|err| MainError::Stdin(err.to_string()))?;
     let stdin_fd = AsFd::as_fd(&stdin_file);
     let mut termios =
@@ -34,18 +152,603 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let original_termios = disable_input_buffering(stdin_fd, &mut termios)
         .map_err(|err| MainError::DisableInputBuffering(err.to_string()))?;
 
-    let mut vm = VM::default();
-    vm.load_program(&file_name)?;
-    vm.running = true;
-    while vm.running {
-        vm.next_instruction()?;
-    }
+    let result = run(&mut vm, &options, &loaded);
 
     restore_input_buffering(stdin_fd, original_termios)
         .map_err(|err| MainError::RestoreInputBuffering(err.to_string()))?;
+    result??;
+    if vm.faulted() {
+        return Ok(ExitCode::from(EXIT_PROGRAM_ERROR));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Runs a program without touching the terminal. Keyboard input comes from a file or a string
+/// and console output goes to a file or stdout. Exits successfully only when the program halts
+fn run_batch(options: &RunOptions) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let input: Box<dyn Read> = match &options.input {
+        Some(Input::File(file_name)) => Box::new(BufReader::new(
+            File::open(file_name).map_err(|err| MainError::InputFile(err.to_string()))?,
+        )),
+        Some(Input::Text(text)) => Box::new(io::Cursor::new(text.clone().into_bytes())),
+        None => Box::new(io::empty()),
+    };
+    let output: Box<dyn Write> = match &options.output {
+        Some(file_name) => Box::new(BufWriter::new(
+            File::create(file_name).map_err(|err| MainError::OutputFile(err.to_string()))?,
+        )),
+        None => Box::new(io::stdout()),
+    };
+
+    let mut vm = VM::new(log_input(Box::new(StreamIo::new(input, output)), options)?);
+    let loaded = start(&mut vm, options)?;
+    if let Err(err) = run(&mut vm, options, &loaded)? {
+        eprintln!("{err}");
+        return Ok(ExitCode::from(EXIT_PROGRAM_ERROR));
+    }
+    // The OS has already reported the exception that stopped the program
+    if vm.faulted() {
+        return Ok(ExitCode::from(EXIT_PROGRAM_ERROR));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Records the keys read by the program into an input log, or replays them from one instead
+/// of reading the input of the device
+fn log_input(
+    mut io: Box<dyn IoDevice>,
+    options: &RunOptions,
+) -> Result<Box<dyn IoDevice>, MainError> {
+    if let Some(file_name) = &options.replay_input {
+        let events = File::open(file_name)
+            .and_then(read_input_log)
+            .map_err(|err| MainError::ReadInputLog(format!("{file_name}: {err}")))?;
+        io = Box::new(ReplayIo::new(io, events));
+    }
+    if let Some(file_name) = &options.record_input {
+        let recording = File::create(file_name)
+            .and_then(|log| RecordingIo::new(io, Box::new(BufWriter::new(log))))
+            .map_err(|err| MainError::WriteInputLog(format!("{file_name}: {err}")))?;
+        io = Box::new(recording);
+    }
+    Ok(io)
+}
+
+/// Where and how the executed instructions are traced
+#[derive(Default)]
+struct TraceOptions {
+    file_name: Option<String>,
+    format: TraceFormat,
+    range: Option<RangeInclusive<u16>>,
+    window: Option<RangeInclusive<u64>>,
+}
+
+/// Runs the program, profiling it and measuring its coverage when requested. When a snapshot
+/// is saved on exit, Ctrl-C stops the machine instead of killing the process. The outer error
+/// is a failure to write the reports or the snapshot, the inner one a failure of the program,
+/// which still gets them written
+fn run(
+    vm: &mut VM,
+    options: &RunOptions,
+    loaded: &Loaded,
+) -> Result<Result<(), VMError>, MainError> {
+    let profiling = options.profile.is_some() || options.profile_folded.is_some();
+    let covering = options.coverage.is_some() || options.lcov.is_some();
+    if options.lcov.is_some() && loaded.source.is_none() {
+        return Err(MainError::NoSource);
+    }
+    let interrupt = match options.save_on_exit {
+        Some(_) => Some(catch_interrupt().map_err(|err| MainError::Interrupt(err.to_string()))?),
+        None => None,
+    };
+    let mut profiler = Profiler::default();
+    let mut coverage = Coverage::default();
+    let mut executed: u32 = 0;
+    let mut interrupted = false;
+    let result = vm.run_with(|vm| {
+        if profiling {
+            profiler.record(vm);
+        }
+        if covering {
+            coverage.record(vm);
+        }
+        if let Some(interrupt) = &interrupt {
+            executed = executed.wrapping_add(1);
+            if executed.is_multiple_of(INTERRUPT_CHECK_INTERVAL)
+                && interrupt.read_signal().is_ok_and(|signal| signal.is_some())
+            {
+                interrupted = true;
+                vm.running = false;
+            }
+        }
+    });
+    if let Some(file_name) = &options.save_on_exit {
+        std::fs::write(file_name, vm.snapshot().to_bytes())
+            .map_err(|err| MainError::WriteSnapshot(err.to_string()))?;
+        if interrupted {
+            eprintln!("Interrupted, snapshot saved to {file_name}");
+        }
+    }
+    if let Some(file_name) = &options.profile {
+        write_report(file_name, &profiler.report(vm, &loaded.symbols))?;
+    }
+    if let Some(file_name) = &options.profile_folded {
+        write_report(file_name, &profiler.folded(&loaded.symbols))?;
+    }
+    if let Some(file_name) = &options.coverage {
+        let report = match &loaded.source {
+            Some(source) => coverage.report(vm, &source.file_name, &source.text, &source.lines),
+            None => coverage.listing(vm, loaded.range.clone()),
+        };
+        write_report(file_name, &report)?;
+    }
+    if let (Some(file_name), Some(source)) = (&options.lcov, &loaded.source) {
+        write_report(
+            file_name,
+            &coverage.lcov(vm, &source.file_name, &source.lines),
+        )?;
+    }
+    Ok(result)
+}
+
+/// Blocks SIGINT and returns a descriptor it can be read from without blocking, so Ctrl-C
+/// can be noticed between instructions
+fn catch_interrupt() -> Result<SignalFd, Errno> {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGINT);
+    mask.thread_block()?;
+    SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK)
+}
+
+/// Writes a report to a file, or to stderr when the file name is `-`
+fn write_report(file_name: &str, report: &str) -> Result<(), MainError> {
+    let written = if file_name == "-" {
+        io::stderr().write_all(report.as_bytes())
+    } else {
+        std::fs::write(file_name, report)
+    };
+    written.map_err(|err| MainError::Report(err.to_string()))
+}
+
+/// Keyboard input of a batch run
+enum Input {
+    File(String),
+    Text(String),
+}
+
+/// Options accepted when running a program
+struct RunOptions {
+    /// Object or source files loaded in order, the first one is the program. Optional when a
+    /// snapshot is restored
+    file_names: Vec<String>,
+    /// Address or label execution starts at, instead of the origin of the program
+    entry: Option<String>,
+    /// File the memory map is written to after loading or restoring, `-` is stderr
+    memory_map: Option<String>,
+    debug: bool,
+    gdb_port: Option<u16>,
+    os: bool,
+    exception_mode: Option<ExceptionMode>,
+    protected: Option<Vec<RangeInclusive<u16>>>,
+    disk: Option<String>,
+    trace: Option<TraceOptions>,
+    /// Files the profile report and the folded stacks are written to, `-` is stderr
+    profile: Option<String>,
+    profile_folded: Option<String>,
+    /// Files the coverage report and the lcov tracefile are written to, `-` is stderr
+    coverage: Option<String>,
+    lcov: Option<String>,
+    batch: bool,
+    input: Option<Input>,
+    output: Option<String>,
+    restore: Option<String>,
+    save_on_exit: Option<String>,
+    /// Input log the keys read by the program are written to, or read from
+    record_input: Option<String>,
+    replay_input: Option<String>,
+}
+
+impl RunOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, MainError> {
+        let mut file_names = Vec::new();
+        let mut entry = None;
+        let mut memory_map = None;
+        let mut debug = false;
+        let mut gdb_port = None;
+        let mut os = false;
+        let mut exception_mode = None;
+        let mut protected = None;
+        let mut disk = None;
+        let mut trace: Option<TraceOptions> = None;
+        let mut profile = None;
+        let mut profile_folded = None;
+        let mut coverage = None;
+        let mut lcov = None;
+        let mut batch = false;
+        let mut input = None;
+        let mut output = None;
+        let mut restore = None;
+        let mut save_on_exit = None;
+        let mut record_input = None;
+        let mut replay_input = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--entry" => entry = Some(args.next().ok_or(MainError::MissingValue(arg))?),
+                "--memory-map" => {
+                    memory_map = Some(args.next().ok_or(MainError::MissingValue(arg))?);
+                }
+                "--debug" => debug = true,
+                "--gdb" => {
+                    let port = args.next().ok_or(MainError::MissingValue(arg))?;
+                    gdb_port = Some(port.parse().map_err(|_| MainError::InvalidPort(port))?);
+                }
+                "--os" => os = true,
+                "--exceptions" => {
+                    let mode = args.next().ok_or(MainError::MissingValue(arg))?;
+                    exception_mode = Some(match mode.as_str() {
+                        "trap" => ExceptionMode::Trap,
+                        "stop" => ExceptionMode::Stop,
+                        _ => return Err(MainError::InvalidExceptionMode(mode)),
+                    });
+                }
+                "--protect" => {
+                    let ranges = args.next().ok_or(MainError::MissingValue(arg))?;
+                    protected = Some(parse_ranges(&ranges)?);
+                }
+                "--disk" => disk = Some(args.next().ok_or(MainError::MissingValue(arg))?),
+                "--trace" => {
+                    // `-` traces to stderr, which is also the default
+                    let file_name = args.next().ok_or(MainError::MissingValue(arg))?;
+                    trace.get_or_insert_with(TraceOptions::default).file_name =
+                        Some(file_name).filter(|file_name| file_name != "-");
+                }
+                "--trace-format" => {
+                    let format = args.next().ok_or(MainError::MissingValue(arg))?;
+                    trace.get_or_insert_with(TraceOptions::default).format = match format.as_str() {
+                        "text" => TraceFormat::Text,
+                        "jsonl" => TraceFormat::JsonLines,
+                        _ => return Err(MainError::InvalidTraceFormat(format)),
+                    };
+                }
+                "--trace-range" => {
+                    let range = args.next().ok_or(MainError::MissingValue(arg))?;
+                    trace.get_or_insert_with(TraceOptions::default).range =
+                        Some(parse_range(&range)?);
+                }
+                "--trace-window" => {
+                    let window = args.next().ok_or(MainError::MissingValue(arg))?;
+                    trace.get_or_insert_with(TraceOptions::default).window =
+                        Some(parse_window(&window)?);
+                }
+                "--profile" => profile = Some(args.next().ok_or(MainError::MissingValue(arg))?),
+                "--profile-folded" => {
+                    profile_folded = Some(args.next().ok_or(MainError::MissingValue(arg))?);
+                }
+                "--coverage" => coverage = Some(args.next().ok_or(MainError::MissingValue(arg))?),
+                "--lcov" => lcov = Some(args.next().ok_or(MainError::MissingValue(arg))?),
+                // Giving any input or output implies a batch run
+                "--batch" => batch = true,
+                "--input" => {
+                    input = Some(Input::File(
+                        args.next().ok_or(MainError::MissingValue(arg))?,
+                    ));
+                    batch = true;
+                }
+                "--input-string" => {
+                    input = Some(Input::Text(
+                        args.next().ok_or(MainError::MissingValue(arg))?,
+                    ));
+                    batch = true;
+                }
+                "--output" => {
+                    output = Some(args.next().ok_or(MainError::MissingValue(arg))?);
+                    batch = true;
+                }
+                "--restore" => restore = Some(args.next().ok_or(MainError::MissingValue(arg))?),
+                "--save-on-exit" => {
+                    save_on_exit = Some(args.next().ok_or(MainError::MissingValue(arg))?);
+                }
+                "--record-input" => {
+                    record_input = Some(args.next().ok_or(MainError::MissingValue(arg))?);
+                }
+                "--replay-input" => {
+                    replay_input = Some(args.next().ok_or(MainError::MissingValue(arg))?);
+                }
+                _ if arg.starts_with("--") => return Err(MainError::UnknownOption(arg)),
+                _ => file_names.push(arg),
+            }
+        }
+        if file_names.is_empty() && restore.is_none() {
+            return Err(MainError::NoFileName);
+        }
+        Ok(Self {
+            file_names,
+            entry,
+            memory_map,
+            debug,
+            gdb_port,
+            os,
+            exception_mode,
+            protected,
+            disk,
+            trace,
+            profile,
+            profile_folded,
+            coverage,
+            lcov,
+            batch,
+            input,
+            output,
+            restore,
+            save_on_exit,
+            record_input,
+            replay_input,
+        })
+    }
+}
+
+/// Loads the OS image, sets the exception mode and memory map and attaches the disk image and
+/// trace requested on the command line
+fn configure(vm: &mut VM, options: &RunOptions) -> Result<(), Box<dyn std::error::Error>> {
+    if options.os {
+        vm.load_os()?;
+    }
+    if let Some(mode) = options.exception_mode {
+        if mode == ExceptionMode::Trap && !options.os {
+            return Err(MainError::NoExceptionHandlers.into());
+        }
+        vm.set_exception_mode(mode);
+    }
+    if let Some(ranges) = &options.protected {
+        vm.set_protected_ranges(ranges.clone());
+    }
+    if let Some(disk) = &options.disk {
+        let storage =
+            BlockStorage::open(Path::new(disk)).map_err(|err| MainError::Disk(err.to_string()))?;
+        vm.add_device(Box::new(storage))?;
+    }
+    if let Some(options) = &options.trace {
+        // The trace goes to stderr unless a file is given, stdout belongs to the program
+        let output: Box<dyn Write> = match &options.file_name {
+            Some(file_name) => Box::new(BufWriter::new(
+                File::create(file_name).map_err(|err| MainError::TraceFile(err.to_string()))?,
+            )),
+            None => Box::new(io::stderr()),
+        };
+        let mut trace = Trace::new(output, options.format);
+        trace.set_range(options.range.clone());
+        trace.set_window(options.window.clone());
+        vm.set_trace(Some(trace));
+    }
     Ok(())
 }
 
+/// Configures the machine, loads the program and the other files after it, starts it at its
+/// origin or at the entry point and restores the snapshot over it. Files loaded together with
+/// a snapshot only provide their labels and source
+fn start(vm: &mut VM, options: &RunOptions) -> Result<Loaded, Box<dyn std::error::Error>> {
+    configure(vm, options)?;
+    let mut files = options.file_names.iter();
+    let loaded = files
+        .next()
+        .map(|file_name| load(vm, file_name))
+        .transpose()?;
+    let mut symbols = BTreeMap::new();
+    for file_name in files {
+        symbols.extend(load(vm, file_name)?.symbols);
+    }
+    let loaded = loaded.map(|mut loaded| {
+        loaded.symbols.extend(symbols);
+        loaded
+    });
+    if let Some(loaded) = &loaded {
+        let entry = match &options.entry {
+            Some(entry) => match loaded.symbols.get(entry) {
+                Some(address) => *address,
+                None => parse_address(entry)?,
+            },
+            None => loaded.range.start,
+        };
+        vm.set_pc(entry);
+    }
+    if let Some(file_name) = &options.restore {
+        let bytes =
+            std::fs::read(file_name).map_err(|err| MainError::ReadSnapshot(err.to_string()))?;
+        let snapshot = Snapshot::from_bytes(&bytes)
+            .map_err(|err| MainError::ReadSnapshot(format!("{file_name}: {err}")))?;
+        vm.restore(&snapshot)?;
+    }
+    if let Some(file_name) = &options.memory_map {
+        write_report(file_name, &vm.memory_map().summary())?;
+    }
+    let loaded = match loaded {
+        Some(loaded) => loaded,
+        None => Loaded {
+            symbols: os_symbols(vm)?,
+            source: None,
+            range: vm.pc()..vm.pc(),
+        },
+    };
+    vm.set_symbols(loaded.symbols.clone());
+    Ok(loaded)
+}
+
+/// Labels of the OS image when it is loaded
+fn os_symbols(vm: &VM) -> Result<BTreeMap<String, u16>, Box<dyn std::error::Error>> {
+    if vm.os_loaded() {
+        Ok(os_image()?.symbols)
+    } else {
+        Ok(BTreeMap::new())
+    }
+}
+
+/// Source file a loaded program was assembled from
+struct Source {
+    file_name: String,
+    text: String,
+    /// Source line of every instruction by address
+    lines: BTreeMap<u16, usize>,
+}
+
+/// What is known about a loaded program besides its words
+struct Loaded {
+    /// Labels of the program, of the files loaded after it and of the OS image when it is
+    /// loaded
+    symbols: BTreeMap<String, u16>,
+    source: Option<Source>,
+    /// Addresses the program was loaded into
+    range: Range<u16>,
+}
+
+/// Loads an object file, or assembles and loads a source file. The labels of an object file
+/// come from the symbol file next to it, and together with the source lines from the source
+/// file next to it, when it assembles into the same words
+fn load(vm: &mut VM, file_name: &str) -> Result<Loaded, Box<dyn std::error::Error>> {
+    let mut symbols = os_symbols(vm)?;
+    let path = Path::new(file_name);
+    let (range, source) = if path.extension().is_some_and(|ext| ext == "asm") {
+        let text = std::fs::read_to_string(file_name)
+            .map_err(|err| MainError::ReadSource(err.to_string()))?;
+        let program = lc3_rust::assemble(&text)
+            .map_err(|err| MainError::Assemble(format!("{file_name}: {err}")))?;
+        let range = vm.load_segment(file_name, &program.to_bytes())?;
+        (range, Some((file_name.to_string(), text, program)))
+    } else {
+        let range = vm.load_program(file_name)?;
+        symbols.extend(read_symbols(path)?);
+        let source_file = path.with_extension("asm");
+        let source = std::fs::read_to_string(&source_file)
+            .ok()
+            .and_then(|text| Some((lc3_rust::assemble(&text).ok()?, text)))
+            .filter(|(program, _)| {
+                std::fs::read(path).is_ok_and(|bytes| bytes == program.to_bytes())
+            })
+            .map(|(program, text)| (source_file.display().to_string(), text, program));
+        (range, source)
+    };
+    let source = source.map(|(file_name, text, program)| {
+        symbols.extend(program.symbols);
+        Source {
+            file_name,
+            text,
+            lines: program.lines,
+        }
+    });
+    Ok(Loaded {
+        symbols,
+        source,
+        range,
+    })
+}
+
+/// Reads the symbol file next to an object file, if there is one
+fn read_symbols(object_file: &Path) -> Result<BTreeMap<String, u16>, MainError> {
+    let symbol_file = object_file.with_extension("sym");
+    match std::fs::read_to_string(&symbol_file) {
+        Ok(text) => parse_symbol_file(&text)
+            .map_err(|err| MainError::ReadSymbols(format!("{}: {err}", symbol_file.display()))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(err) => Err(MainError::ReadSymbols(format!(
+            "{}: {err}",
+            symbol_file.display()
+        ))),
+    }
+}
+
+/// Assembles a source file into an object file and writes its labels into a symbol file next
+/// to it
+fn assemble(
+    source_file: &str,
+    object_file: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let source = std::fs::read_to_string(source_file)
+        .map_err(|err| MainError::ReadSource(err.to_string()))?;
+    let program = lc3_rust::assemble(&source)
+        .map_err(|err| MainError::Assemble(format!("{source_file}: {err}")))?;
+    let object_file = match object_file {
+        Some(object_file) => object_file.into(),
+        None => Path::new(source_file).with_extension("obj"),
+    };
+    std::fs::write(&object_file, program.to_bytes())
+        .map_err(|err| MainError::WriteObject(err.to_string()))?;
+    std::fs::write(
+        object_file.with_extension("sym"),
+        symbol_file(&program.symbols),
+    )
+    .map_err(|err| MainError::WriteSymbols(err.to_string()))?;
+    Ok(())
+}
+
+fn disassemble(
+    object_file: &str,
+    start: Option<u16>,
+    end: Option<u16>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut vm = VM::default();
+    let loaded = vm.load_program(object_file)?;
+    let symbols = read_symbols(Path::new(object_file))?;
+    let start = start.unwrap_or(loaded.start);
+    let end = end.unwrap_or(loaded.end);
+    for word in vm.disassemble(start..end) {
+        println!("{}", word.to_string_with_symbols(&symbols));
+    }
+    Ok(())
+}
+
+/// Parses an address written as `x3000`, `0x3000` or in decimal
+fn parse_address(address: &str) -> Result<u16, MainError> {
+    let parsed = match address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix('x'))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => address.parse(),
+    };
+    parsed.map_err(|_| MainError::InvalidAddress(address.to_string()))
+}
+
+/// Parses a single inclusive address range like `x3000-x30FF`
+fn parse_range(range: &str) -> Result<RangeInclusive<u16>, MainError> {
+    match parse_ranges(range)?.as_slice() {
+        [range] => Ok(range.clone()),
+        _ => Err(MainError::InvalidRange(range.to_string())),
+    }
+}
+
+/// Parses an inclusive window of steps like `100-200`
+fn parse_window(window: &str) -> Result<RangeInclusive<u64>, MainError> {
+    let invalid = || MainError::InvalidWindow(window.to_string());
+    let (start, end) = window.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.parse().map_err(|_| invalid())?;
+    let end: u64 = end.parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(start..=end)
+}
+
+/// Parses a comma separated list of inclusive address ranges like `x0000-x2FFF,xFE00-xFFFF`,
+/// `none` is an empty list
+fn parse_ranges(ranges: &str) -> Result<Vec<RangeInclusive<u16>>, MainError> {
+    if ranges == "none" {
+        return Ok(Vec::new());
+    }
+    ranges
+        .split(',')
+        .map(|range| {
+            let (start, end) = range
+                .split_once('-')
+                .ok_or(MainError::InvalidRange(range.to_string()))?;
+            let start = parse_address(start)?;
+            let end = parse_address(end)?;
+            if start > end {
+                return Err(MainError::InvalidRange(range.to_string()));
+            }
+            Ok(start..=end)
+        })
+        .collect()
+}
+
 fn disable_input_buffering(stdin_fd: BorrowedFd, termios: &mut Termios) -> Result<Termios, Errno> {
     let original_termios = termios.clone();
     let mut flags = termios.local_flags;
@@ -0,0 +1,449 @@
+use std::process::{Command, Stdio};
+
+type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+const ECHO: &str = "
+        .ORIG x3000
+LOOP    GETC
+        ADD R1, R0, #-10
+        BRz DONE
+        OUT
+        BR LOOP
+DONE    LEA R0, BYE
+        PUTS
+        HALT
+BYE     .STRINGZ \"!\"
+        .END
+";
+
+fn write_program(name: &str) -> Result<std::path::PathBuf, Box<dyn std::error::Error>> {
+    let path = std::env::temp_dir().join(format!("lc3-batch-{}-{name}.asm", std::process::id()));
+    std::fs::write(&path, ECHO)?;
+    Ok(path)
+}
+
+#[test]
+fn input_string_to_stdout() -> TestResult {
+    let program = write_program("string")?;
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .arg("--input-string")
+        .arg("hello\n")
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("hello!", String::from_utf8(output.stdout)?);
+    Ok(())
+}
+
+#[test]
+fn input_file_to_output_file() -> TestResult {
+    let program = write_program("file")?;
+    let input = program.with_extension("in");
+    let captured = program.with_extension("out");
+    std::fs::write(&input, "abc\n")?;
+    let status = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .arg("--input")
+        .arg(&input)
+        .arg("--output")
+        .arg(&captured)
+        .stdin(Stdio::null())
+        .status()?;
+    assert!(status.success());
+    assert_eq!("abc!", std::fs::read_to_string(&captured)?);
+    Ok(())
+}
+
+#[test]
+fn exhausted_input_is_an_error() -> TestResult {
+    let program = write_program("exhausted")?;
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .arg("--input-string")
+        .arg("no newline")
+        .stdin(Stdio::null())
+        .output()?;
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!("no newline", String::from_utf8(output.stdout)?);
+    Ok(())
+}
+
+#[test]
+fn os_image_runs_trap_routines() -> TestResult {
+    let program = write_program("os")?;
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg("--os")
+        .arg(&program)
+        .arg("--input-string")
+        .arg("hi\n")
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("hi!", String::from_utf8(output.stdout)?);
+    Ok(())
+}
+
+#[test]
+fn exceptions_can_stop_the_program() -> TestResult {
+    let program =
+        std::env::temp_dir().join(format!("lc3-batch-{}-illegal.asm", std::process::id()));
+    std::fs::write(&program, ".ORIG x3000\n.FILL xD000\nHALT\n.END\n")?;
+    let run = |mode: &str| {
+        Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+            .arg(&program)
+            .args(["--os", "--batch", "--exceptions", mode])
+            .stdin(Stdio::null())
+            .output()
+    };
+
+    // The OS reports the exception and stops the program with a fault
+    let trapped = run("trap")?;
+    assert_eq!(Some(2), trapped.status.code());
+    assert_eq!("\nIllegal opcode\n", String::from_utf8(trapped.stdout)?);
+
+    let stopped = run("stop")?;
+    assert_eq!(Some(2), stopped.status.code());
+    assert_eq!(
+        "Exception: illegal opcode at x3000\n",
+        String::from_utf8(stopped.stderr)?
+    );
+
+    // Without the OS there are no handlers to trap to
+    let unhandled = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .args(["--batch", "--exceptions", "trap"])
+        .stdin(Stdio::null())
+        .output()?;
+    assert_eq!(Some(1), unhandled.status.code());
+    assert!(unhandled.stdout.is_empty());
+    Ok(())
+}
+
+#[test]
+fn protected_ranges_can_be_relaxed() -> TestResult {
+    let program =
+        std::env::temp_dir().join(format!("lc3-batch-{}-protect.asm", std::process::id()));
+    std::fs::write(
+        &program,
+        ".ORIG x3000\nLD R0, CHAR\nSTI R0, DDR\nHALT\nCHAR .FILL x41\nDDR .FILL xFE06\n.END\n",
+    )?;
+    let run = |ranges: &str| {
+        Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+            .arg(&program)
+            .args([
+                "--os",
+                "--batch",
+                "--exceptions",
+                "stop",
+                "--protect",
+                ranges,
+            ])
+            .stdin(Stdio::null())
+            .output()
+    };
+
+    let protected = run("x0000-x2FFF,xFE00-xFFFF")?;
+    assert_eq!(Some(2), protected.status.code());
+    let relaxed = run("x0000-x2FFF")?;
+    assert!(relaxed.status.success());
+    assert_eq!("A", String::from_utf8(relaxed.stdout)?);
+    Ok(())
+}
+
+#[test]
+fn disk_sectors_are_read_and_written() -> TestResult {
+    // Copies sector 0 to sector 1 and prints its first word as a character
+    let program = std::env::temp_dir().join(format!("lc3-batch-{}-disk.asm", std::process::id()));
+    std::fs::write(
+        &program,
+        "
+        .ORIG x3000
+        AND R0, R0, #0
+        LEA R1, BUFFER
+        RDSEC
+        ADD R0, R0, #0
+        BRn FAILED
+        ADD R0, R0, #1
+        WRSEC
+        ADD R0, R0, #0
+        BRn FAILED
+        LD R0, BUFFER
+        OUT
+FAILED  HALT
+BUFFER  .BLKW 256
+        .END
+",
+    )?;
+    for os in [false, true] {
+        let disk = program.with_extension(format!("os-{os}.img"));
+        let mut image = vec![0u8; 512];
+        image.splice(0..2, [0x00, b'Z']);
+        std::fs::write(&disk, &image)?;
+        let mut command = Command::new(env!("CARGO_BIN_EXE_lc3-rust"));
+        command
+            .arg(&program)
+            .arg("--batch")
+            .arg("--disk")
+            .arg(&disk);
+        if os {
+            command.arg("--os");
+        }
+        let output = command.stdin(Stdio::null()).output()?;
+        assert!(output.status.success());
+        assert_eq!("Z", String::from_utf8(output.stdout)?);
+        image.extend_from_slice(&image.clone());
+        assert_eq!(image, std::fs::read(&disk)?);
+    }
+    Ok(())
+}
+
+#[test]
+fn trace_is_written_as_json_lines() -> TestResult {
+    let program = write_program("trace")?;
+    let trace = program.with_extension("jsonl");
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .args(["--input-string", "\n", "--trace-format", "jsonl"])
+        .args(["--trace-window", "0-1", "--trace"])
+        .arg(&trace)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("!", String::from_utf8(output.stdout)?);
+    let trace = std::fs::read_to_string(&trace)?;
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(2, lines.len());
+    assert!(lines
+        .first()
+        .is_some_and(|line| line.starts_with("{\"step\":0,\"pc\":12288,")));
+    assert!(lines
+        .get(1)
+        .is_some_and(|line| line.contains("\"opcode\":\"ADD R1, R0, #-10\"")));
+    Ok(())
+}
+
+#[test]
+fn profile_reports_are_written() -> TestResult {
+    let program = write_program("profile")?;
+    let report = program.with_extension("profile");
+    let folded = program.with_extension("folded");
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .args(["--input-string", "ab\n", "--profile"])
+        .arg(&report)
+        .arg("--profile-folded")
+        .arg(&folded)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    let report = std::fs::read_to_string(&report)?;
+    // GETC runs three times, the labels of the source file name the addresses
+    assert!(report.starts_with("Flat profile, 16 instructions\n"));
+    assert!(report.contains("  x3000    LOOP              GETC\n"));
+    assert_eq!("LOOP 16\n", std::fs::read_to_string(&folded)?);
+
+    // With the OS loaded its service routines show up as functions called by the program
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .args(["--os", "--input-string", "ab\n", "--profile-folded"])
+        .arg(&folded)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    let folded = std::fs::read_to_string(&folded)?;
+    assert!(folded.starts_with("LOOP 16\n"));
+    assert!(folded.contains("\nLOOP;TRAP_GETC "));
+    Ok(())
+}
+
+#[test]
+fn coverage_is_mapped_to_source_lines() -> TestResult {
+    let program = write_program("coverage")?;
+    let object = program.with_extension("obj");
+    let status = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg("asm")
+        .arg(&program)
+        .status()?;
+    assert!(status.success());
+    let report = program.with_extension("cov");
+    let lcov = program.with_extension("info");
+    // The source next to the object file provides the line numbers
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&object)
+        .args(["--input-string", "\n", "--coverage"])
+        .arg(&report)
+        .arg("--lcov")
+        .arg(&lcov)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    let report = std::fs::read_to_string(&report)?;
+    assert!(report.contains(": 6 of 8 instructions, 1 of 2 branch directions\n"));
+    assert!(report.contains("\n   #####      6          OUT\n"));
+    let lcov = std::fs::read_to_string(&lcov)?;
+    assert!(lcov.contains("\nBRDA:5,0,0,1\nBRDA:5,0,1,0\n"));
+    assert!(lcov.contains("\nDA:6,0\n"));
+    assert!(lcov.ends_with("LF:8\nLH:6\nend_of_record\n"));
+    Ok(())
+}
+
+#[test]
+fn snapshot_resumes_after_halt() -> TestResult {
+    let program =
+        std::env::temp_dir().join(format!("lc3-batch-{}-snapshot.asm", std::process::id()));
+    std::fs::write(
+        &program,
+        "
+        .ORIG x3000
+        GETC
+        OUT
+        HALT
+        GETC
+        OUT
+        HALT
+        .END
+",
+    )?;
+    let snapshot = program.with_extension("snap");
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .args(["--input-string", "a", "--save-on-exit"])
+        .arg(&snapshot)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("a", String::from_utf8(output.stdout)?);
+
+    // The restored machine carries on after the first HALT, no program file is needed
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .args(["--input-string", "b", "--restore"])
+        .arg(&snapshot)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("b", String::from_utf8(output.stdout)?);
+
+    std::fs::write(&snapshot, b"LC3S")?;
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .args(["--batch", "--restore"])
+        .arg(&snapshot)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("snapshot is truncated"));
+    Ok(())
+}
+
+#[test]
+fn input_is_recorded_and_replayed() -> TestResult {
+    let program = write_program("replay")?;
+    let log = program.with_extension("keys");
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .args(["--os", "--input-string", "ok\n", "--record-input"])
+        .arg(&log)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("ok!", String::from_utf8(output.stdout)?);
+    let events = std::fs::read_to_string(&log)?;
+    assert!(events.starts_with("lc3-input 1\n"));
+    assert_eq!(4, events.lines().count());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&program)
+        .args(["--os", "--batch", "--replay-input"])
+        .arg(&log)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("ok!", String::from_utf8(output.stdout)?);
+    Ok(())
+}
+
+#[test]
+fn symbol_file_names_addresses() -> TestResult {
+    let program =
+        std::env::temp_dir().join(format!("lc3-batch-{}-symbols.asm", std::process::id()));
+    std::fs::write(
+        &program,
+        ".ORIG x3000\nMAIN AND R0, R0, #0\nADD R0, R0, #1\n.FILL xD000\nHALT\n.END\n",
+    )?;
+    let object = program.with_extension("obj");
+    let status = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg("asm")
+        .arg(&program)
+        .status()?;
+    assert!(status.success());
+    assert!(std::fs::read_to_string(program.with_extension("sym"))?
+        .ends_with("//\tMAIN              3000\n"));
+    // Only the symbol file is left to name the addresses
+    std::fs::remove_file(&program)?;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&object)
+        .args(["--os", "--batch", "--exceptions", "stop"])
+        .stdin(Stdio::null())
+        .output()?;
+    assert_eq!(Some(2), output.status.code());
+    assert_eq!(
+        "Exception: illegal opcode at MAIN+2 (x3002)\n",
+        String::from_utf8(output.stderr)?
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg("disasm")
+        .arg(&object)
+        .output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.starts_with("MAIN (x3000)  x5020  AND R0, R0, #0\n"));
+    Ok(())
+}
+
+#[test]
+fn object_files_are_linked_into_one_run() -> TestResult {
+    let dir = std::env::temp_dir();
+    let main = dir.join(format!("lc3-batch-{}-link-main.asm", std::process::id()));
+    let library = dir.join(format!("lc3-batch-{}-link-lib.asm", std::process::id()));
+    // The program starts after its data, at the entry point
+    std::fs::write(
+        &main,
+        ".ORIG x3000\nPRINT .FILL x3100\nSTART LD R1, PRINT\nJSRR R1\nHALT\n.END\n",
+    )?;
+    std::fs::write(
+        &library,
+        ".ORIG x3100\nST R7, SAVE\nLD R0, CHAR\nOUT\nLD R7, SAVE\nRET\nCHAR .FILL x4C\nSAVE .BLKW 1\n.END\n",
+    )?;
+    let map = main.with_extension("map");
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&main)
+        .arg(&library)
+        .args(["--os", "--batch", "--entry", "START", "--memory-map"])
+        .arg(&map)
+        .stdin(Stdio::null())
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!("L", String::from_utf8(output.stdout)?);
+    let map = std::fs::read_to_string(&map)?;
+    assert!(map.starts_with("Memory map\n  x0000-x"));
+    assert!(map.ends_with(&format!(
+        "  x3000-x3003      4 words  {}\n  x3100-x3106      7 words  {}\n",
+        main.display(),
+        library.display()
+    )));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg(&main)
+        .arg(&main)
+        .arg("--batch")
+        .stdin(Stdio::null())
+        .output()?;
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8(output.stderr)?.contains(&format!(
+        "{} at x3000-x3003 overlaps {} at x3000-x3003",
+        main.display(),
+        main.display()
+    )));
+    Ok(())
+}
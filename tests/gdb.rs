@@ -0,0 +1,138 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Child, Command, Stdio},
+};
+
+type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+const PROGRAM: &str = "
+        .ORIG x3000
+        ADD R0, R0, #1
+        ADD R0, R0, #1
+        LD R1, ADDR
+        STR R0, R1, #0
+        HALT
+ADDR    .FILL x4000
+        .END
+";
+
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    /// Sends a packet and returns the reply, checking that both sides acknowledge
+    fn request(&mut self, data: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${data}#{checksum:02x}")?;
+
+        let mut byte = [0; 1];
+        self.stream.read_exact(&mut byte)?;
+        assert_eq!(*b"+", byte, "packet {data} was not acknowledged");
+        self.stream.read_exact(&mut byte)?;
+        assert_eq!(*b"$", byte);
+
+        let mut reply = Vec::new();
+        loop {
+            self.stream.read_exact(&mut byte)?;
+            if byte == *b"#" {
+                break;
+            }
+            reply.extend_from_slice(&byte);
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum)?;
+        self.stream.write_all(b"+")?;
+        Ok(String::from_utf8(reply)?)
+    }
+}
+
+fn start_server(name: &str) -> Result<(Child, Client), Box<dyn std::error::Error>> {
+    let source = std::env::temp_dir().join(format!("lc3-gdb-{}-{name}.asm", std::process::id()));
+    std::fs::write(&source, PROGRAM)?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lc3-rust"))
+        .arg("--gdb")
+        .arg("0")
+        .arg(&source)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stderr = child.stderr.take().ok_or("no stderr")?;
+    let mut line = String::new();
+    BufReader::new(stderr).read_line(&mut line)?;
+    let address = line
+        .trim()
+        .strip_prefix("Listening for gdb on ")
+        .ok_or("unexpected server output")?;
+    let stream = TcpStream::connect(address)?;
+    Ok((child, Client { stream }))
+}
+
+#[test]
+fn registers_breakpoints_and_halt() -> TestResult {
+    let (mut child, mut client) = start_server("registers")?;
+
+    assert!(client
+        .request("qSupported:swbreak+")?
+        .contains("PacketSize"));
+    assert_eq!("S05", client.request("?")?);
+    assert_eq!(
+        // R6 starts as the supervisor stack pointer, x3000
+        format!("{}{}{}{}", "0000".repeat(6), "00300000", "0030", "0200"),
+        client.request("g")?
+    );
+
+    assert_eq!("S05", client.request("s")?);
+    assert_eq!("0130", client.request("p8")?);
+    assert_eq!("0100", client.request("p0")?);
+
+    assert_eq!("OK", client.request("P0=0500")?);
+    assert_eq!("0500", client.request("p0")?);
+
+    // Packets with a bad checksum are rejected and the next one is served
+    write!(client.stream, "$p0#00")?;
+    let mut nack = [0; 1];
+    client.stream.read_exact(&mut nack)?;
+    assert_eq!(*b"-", nack);
+    assert_eq!("0500", client.request("p0")?);
+
+    // Entering user mode through the PSR swaps R6 with the saved user stack pointer
+    assert_eq!("OK", client.request("P6=3412")?);
+    assert_eq!("OK", client.request("P9=0280")?);
+    assert_eq!("0000", client.request("p6")?);
+    assert_eq!("OK", client.request("P9=0200")?);
+    assert_eq!("3412", client.request("p6")?);
+
+    assert_eq!("OK", client.request("Z0,3003,2")?);
+    assert_eq!("S05", client.request("c")?);
+    assert_eq!("0330", client.request("p8")?);
+    assert_eq!("OK", client.request("z0,3003,2")?);
+
+    assert_eq!("W00", client.request("c")?);
+    assert!(child.wait()?.success());
+    Ok(())
+}
+
+#[test]
+fn memory_and_watchpoints() -> TestResult {
+    let (mut child, mut client) = start_server("memory")?;
+
+    assert_eq!("OK", client.request("M4000,2:3412")?);
+    assert_eq!("3412", client.request("m4000,2")?);
+    // The first two words of the program: ADD R0, R0, #1
+    assert_eq!("21102110", client.request("m3000,4")?);
+    // Device registers are refused instead of triggering their side effects
+    assert_eq!("E01", client.request("MFFFE,2:0000")?);
+
+    assert_eq!("OK", client.request("Z2,4000,2")?);
+    assert_eq!("T05watch:4000;", client.request("c")?);
+    assert_eq!("0200", client.request("m4000,2")?);
+    assert_eq!("0430", client.request("p8")?);
+
+    client.request("k").ok();
+    assert!(child.wait()?.success());
+    Ok(())
+}
@@ -0,0 +1,301 @@
+use lc3_rust::{
+    assemble, read_input_log, BufferIo, ConditionFlags, CpuState, Device, DeviceContext, History,
+    InterruptRequest, Opcode, RecordingIo, Register, ReplayIo, SharedBuffer, VMError, VM,
+};
+use std::{io, ops::RangeInclusive};
+
+type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+#[test]
+fn embed_vm_with_scripted_io() -> TestResult {
+    let program = assemble(
+        "
+        .ORIG x3000
+        GETC
+        ADD R0, R0, #1
+        OUT
+        HALT
+        .END
+        ",
+    )?;
+    let io = BufferIo::with_input(b"a".to_vec());
+    let output = io.output().clone();
+    let mut vm = VM::new(Box::new(io));
+    let loaded = vm.load_bytes(&program.to_bytes())?;
+    assert_eq!(0x3000..0x3004, loaded);
+
+    vm.run()?;
+    assert!(!vm.running);
+    assert_eq!(b"b".to_vec(), output.contents());
+    Ok(())
+}
+
+#[test]
+fn disassemble_loaded_program() -> TestResult {
+    let mut vm = VM::default();
+    let loaded = vm.load_program("./test-programs/for_loop.obj")?;
+    let words = vm.disassemble(loaded);
+    assert_eq!(4, words.len());
+    assert_eq!(
+        Some(&Opcode::BR {
+            n: true,
+            z: false,
+            p: false,
+            offset: 0x1FD,
+        }),
+        words.last().map(|word| &word.opcode)
+    );
+    Ok(())
+}
+
+#[test]
+fn load_errors_are_reported() {
+    let mut vm = VM::default();
+    assert!(matches!(
+        vm.load_bytes(&[0x30]),
+        Err(VMError::LoadProgram(_))
+    ));
+}
+
+#[test]
+fn link_several_object_files() -> TestResult {
+    let main = assemble(".ORIG x3000\nLD R1, LIBRARY\nJSRR R1\nHALT\nLIBRARY .FILL x3100\n.END")?;
+    let library = assemble(".ORIG x3100\nADD R0, R0, #1\nRET\n.END")?;
+    let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+    vm.load_os()?;
+    vm.load_segment("main.obj", &main.to_bytes())?;
+    vm.load_segment("lib.obj", &library.to_bytes())?;
+    let names: Vec<&str> = vm
+        .memory_map()
+        .segments()
+        .iter()
+        .map(|segment| segment.name.as_str())
+        .collect();
+    assert_eq!(vec!["OS", "main.obj", "lib.obj"], names);
+
+    // An object file loaded over another one is refused and leaves memory untouched
+    let overlapping = assemble(".ORIG x3101\n.FILL x1234\n.END")?;
+    assert_eq!(
+        Some(String::from(
+            "Failed to load program into memory: patch.obj at x3101 overlaps lib.obj at x3100-x3101"
+        )),
+        vm.load_segment("patch.obj", &overlapping.to_bytes())
+            .err()
+            .map(|err| err.to_string())
+    );
+    assert_eq!(&[0xC1C0], vm.read_memory(0x3101, 1)?);
+    Ok(())
+}
+
+#[test]
+fn inspect_and_modify_cpu_state() -> TestResult {
+    let program = assemble(
+        "
+        .ORIG x3000
+        ADD R2, R1, #-1
+        HALT
+        .END
+        ",
+    )?;
+    let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+    vm.load_bytes(&program.to_bytes())?;
+    assert_eq!(&[0x147F, 0xF025], vm.read_memory(0x3000, 2)?);
+
+    vm.set_reg(Register::R1, 1);
+    vm.write_memory(0x3001, &[0x14BF])?;
+    vm.next_instruction()?;
+    vm.next_instruction()?;
+    assert_eq!(
+        CpuState {
+            registers: [0, 1, 0xFFFF, 0, 0, 0, 0x3000, 0],
+            pc: 0x3002,
+            condition: ConditionFlags::NEG,
+            psr: 0x0004,
+            running: false,
+        },
+        vm.state()
+    );
+
+    vm.set_pc(0x3000);
+    assert_eq!(0x3000, vm.pc());
+    vm.next_instruction()?;
+    assert_eq!(0, vm.reg(Register::R2));
+    assert_eq!(ConditionFlags::ZRO, vm.condition());
+    assert!(vm.read_memory(0xFFFF, 2).is_err());
+    Ok(())
+}
+
+/// Counts the values written to its data register and interrupts when the count reaches 3
+#[derive(Default)]
+struct Counter {
+    count: u16,
+    total: u16,
+}
+
+impl Device for Counter {
+    fn registers(&self) -> RangeInclusive<u16> {
+        0xFE10..=0xFE11
+    }
+
+    fn read(&mut self, address: u16, _context: &mut DeviceContext) -> io::Result<u16> {
+        Ok(self.peek(address))
+    }
+
+    fn write(&mut self, address: u16, value: u16, _context: &mut DeviceContext) -> io::Result<()> {
+        if address == 0xFE11 {
+            self.count = self.count.wrapping_add(1);
+            self.total = self.total.wrapping_add(value);
+        }
+        Ok(())
+    }
+
+    fn peek(&self, address: u16) -> u16 {
+        match address {
+            0xFE10 => self.count,
+            _ => self.total,
+        }
+    }
+
+    fn tick(&mut self, _context: &mut DeviceContext) -> io::Result<Option<InterruptRequest>> {
+        Ok((self.count == 3).then_some(InterruptRequest {
+            vector: 0x0190,
+            priority: 2,
+        }))
+    }
+}
+
+#[test]
+fn custom_device_on_the_bus() -> TestResult {
+    let program = assemble(
+        "
+        .ORIG x3000
+        LD R6, STACK
+        LEA R0, HANDLER
+        STI R0, VECTOR
+        AND R1, R1, #0
+LOOP    ADD R1, R1, #1
+        STI R1, DATA
+        BR LOOP
+HANDLER LDI R2, DATA
+        HALT
+STACK   .FILL x4000
+VECTOR  .FILL x0190
+DATA    .FILL xFE11
+        .END
+        ",
+    )?;
+    let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+    vm.add_device(Box::new(Counter::default()))?;
+    vm.load_bytes(&program.to_bytes())?;
+    vm.run()?;
+    assert_eq!(6, vm.reg(Register::R2));
+    assert_eq!(3, vm.peek_word(0xFE10));
+
+    assert!(matches!(
+        vm.add_device(Box::new(Counter::default())),
+        Err(VMError::Device(_))
+    ));
+    Ok(())
+}
+
+/// Stamps the number of instructions it has seen into memory, and copies the values written to
+/// its register into memory, like a DMA transfer
+#[derive(Default)]
+struct Stamp {
+    ticks: u16,
+}
+
+impl Device for Stamp {
+    fn registers(&self) -> RangeInclusive<u16> {
+        0xFE12..=0xFE12
+    }
+
+    fn read(&mut self, _address: u16, _context: &mut DeviceContext) -> io::Result<u16> {
+        Ok(0)
+    }
+
+    fn write(&mut self, _address: u16, value: u16, context: &mut DeviceContext) -> io::Result<()> {
+        context.write_memory(0x4001, &[value])
+    }
+
+    fn peek(&self, _address: u16) -> u16 {
+        0
+    }
+
+    fn tick(&mut self, context: &mut DeviceContext) -> io::Result<Option<InterruptRequest>> {
+        self.ticks = self.ticks.wrapping_add(1);
+        context.write_memory(0x4000, &[self.ticks])?;
+        Ok(None)
+    }
+}
+
+#[test]
+fn memory_written_by_devices_is_undone() -> TestResult {
+    let program = assemble(
+        "
+        .ORIG x3000
+        AND R0, R0, #0
+        ADD R0, R0, #7
+        STI R0, PORT
+        HALT
+PORT    .FILL xFE12
+        .END
+        ",
+    )?;
+    let mut vm = VM::new(Box::new(BufferIo::with_input(Vec::new())));
+    vm.add_device(Box::new(Stamp::default()))?;
+    vm.load_bytes(&program.to_bytes())?;
+    vm.set_history(Some(History::default()));
+    vm.run()?;
+    assert_eq!(4, vm.peek_word(0x4000));
+    assert_eq!(7, vm.peek_word(0x4001));
+
+    assert_eq!(1, vm.step_back(1)?);
+    assert_eq!(3, vm.peek_word(0x4000));
+    assert_eq!(7, vm.peek_word(0x4001));
+    assert_eq!(3, vm.step_back(3)?);
+    assert_eq!(0, vm.peek_word(0x4000));
+    assert_eq!(0, vm.peek_word(0x4001));
+    Ok(())
+}
+
+#[test]
+fn record_and_replay_keyboard_input() -> TestResult {
+    // The OS polls the keyboard, so when a key is read depends on when it was pressed
+    let program = assemble(
+        "
+        .ORIG x3000
+LOOP    GETC
+        OUT
+        ADD R1, R0, #-10
+        BRnp LOOP
+        HALT
+        .END
+        ",
+    )?;
+    let log = SharedBuffer::default();
+    let io = BufferIo::with_input(b"hi\n".to_vec());
+    let output = io.output().clone();
+    let mut vm = VM::new(Box::new(RecordingIo::new(
+        Box::new(io),
+        Box::new(log.clone()),
+    )?));
+    vm.load_os()?;
+    vm.load_bytes(&program.to_bytes())?;
+    vm.run()?;
+    assert_eq!(b"hi\n".to_vec(), output.contents());
+
+    // The replay ignores the keys of the device and reads the same keys at the same instructions
+    let events = read_input_log(log.contents().as_slice())?;
+    assert_eq!(3, events.len());
+    let io = BufferIo::with_input(b"xyz".to_vec());
+    let replayed = io.output().clone();
+    let mut replay = VM::new(Box::new(ReplayIo::new(Box::new(io), events)));
+    replay.load_os()?;
+    replay.load_bytes(&program.to_bytes())?;
+    replay.run()?;
+    assert_eq!(output.contents(), replayed.contents());
+    assert_eq!(vm.instructions(), replay.instructions());
+    assert_eq!(vm.snapshot(), replay.snapshot());
+    Ok(())
+}